@@ -0,0 +1,202 @@
+use whisper_real_time::whisper::{WhisperOptions, MAX_AUDIO_CTX};
+
+/// segments in a row that have to be too slow (or fast enough again) before
+/// the quality changes
+const ADAPT_AFTER: usize = 3;
+/// real time factor above which transcription can't keep up
+const SLOW_RTF: f64 = 1.0;
+/// real time factor below which quality gets restored step by step
+const RECOVERED_RTF: f64 = 0.5;
+/// the audio context is never reduced below this (~10s of audio)
+const MIN_AUDIO_CTX: i32 = 512;
+
+/// quality settings of a level, see [`Adaptive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quality {
+    pub best_of: i32,
+    pub beam_size: Option<i32>,
+    pub audio_ctx: Option<i32>,
+}
+
+/// Lowers whisper's quality settings while transcription is slower than real
+/// time and restores them once it catches up
+///
+/// Level 0 are the configured settings, level 1 decodes greedily with a
+/// single candidate and every further level halves the audio context. Whisper
+/// raises the context again for segments that wouldn't fit into it, so only
+/// shorter segments get faster.
+pub struct Adaptive {
+    configured: Quality,
+    level: u32,
+    max_level: u32,
+    slow: usize,
+    fast: usize,
+}
+
+impl Adaptive {
+    pub fn new(options: &WhisperOptions) -> Adaptive {
+        let mut max_level = 1;
+        let mut audio_ctx = options.audio_ctx.unwrap_or(MAX_AUDIO_CTX);
+        while audio_ctx / 2 >= MIN_AUDIO_CTX {
+            audio_ctx /= 2;
+            max_level += 1;
+        }
+        Adaptive {
+            configured: Quality {
+                best_of: options.best_of,
+                beam_size: options.beam_size,
+                audio_ctx: options.audio_ctx,
+            },
+            level: 0,
+            max_level,
+            slow: 0,
+            fast: 0,
+        }
+    }
+
+    /// Registers the real time factor of the last segment (processing time
+    /// divided by audio duration), returns the settings to switch to if the
+    /// quality changes
+    pub fn update(&mut self, rtf: f64) -> Option<Quality> {
+        self.slow = if rtf > SLOW_RTF { self.slow + 1 } else { 0 };
+        self.fast = if rtf < RECOVERED_RTF {
+            self.fast + 1
+        } else {
+            0
+        };
+        let level = if self.slow >= ADAPT_AFTER && self.level < self.max_level {
+            self.level + 1
+        } else if self.fast >= ADAPT_AFTER && self.level > 0 {
+            self.level - 1
+        } else {
+            return None;
+        };
+        self.slow = 0;
+        self.fast = 0;
+        let quality = self.quality(level);
+        eprintln!(
+            "adaptive: real time factor {rtf:.2}, {} quality ({}, audio context {})",
+            if level > self.level {
+                "lowering"
+            } else {
+                "raising"
+            },
+            match quality.beam_size {
+                Some(beam_size) => format!("beam size {beam_size}"),
+                None => format!("best of {}", quality.best_of),
+            },
+            quality
+                .audio_ctx
+                .map_or("default".to_string(), |ctx| ctx.to_string())
+        );
+        self.level = level;
+        Some(quality)
+    }
+
+    fn quality(&self, level: u32) -> Quality {
+        let greedy = Quality {
+            best_of: 1,
+            beam_size: None,
+            ..self.configured
+        };
+        match level {
+            0 => self.configured,
+            1 => greedy,
+            level => Quality {
+                audio_ctx: Some(self.configured.audio_ctx.unwrap_or(MAX_AUDIO_CTX) >> (level - 1)),
+                ..greedy
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use whisper_real_time::whisper::Translate;
+
+    use super::*;
+
+    fn options() -> WhisperOptions {
+        WhisperOptions {
+            translate: Translate::Off,
+            language: "en".to_string(),
+            languages: Vec::new(),
+            detect_window: None,
+            redetect_every: None,
+            best_of: 5,
+            beam_size: Some(4),
+            threads: 1,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            entropy_threshold: 2.4,
+            logprob_threshold: -1.0,
+            initial_prompt: None,
+            carry_context: 0,
+            suppress_non_speech: true,
+            single_segment: true,
+            timestamps: false,
+            word_timestamps: false,
+            audio_ctx: None,
+            max_tokens: None,
+            max_text_ctx: None,
+            trim_silence: false,
+            min_audio: Duration::ZERO,
+            history: 0,
+            max_window: Duration::from_secs(30),
+            load_retries: 0,
+            gpu_device: None,
+        }
+    }
+
+    fn run(adaptive: &mut Adaptive, rtf: f64, segments: usize) -> Option<Quality> {
+        (0..segments).fold(None, |_, _| adaptive.update(rtf))
+    }
+
+    #[test]
+    fn slow_segments_switch_beam_search_to_greedy() {
+        let mut adaptive = Adaptive::new(&options());
+        assert_eq!(run(&mut adaptive, 2.0, ADAPT_AFTER - 1), None);
+        assert_eq!(
+            adaptive.update(2.0),
+            Some(Quality {
+                best_of: 1,
+                beam_size: None,
+                audio_ctx: None,
+            })
+        );
+        // then the audio context is halved down to the minimum
+        assert_eq!(
+            run(&mut adaptive, 2.0, ADAPT_AFTER).unwrap().audio_ctx,
+            Some(750)
+        );
+        assert_eq!(run(&mut adaptive, 2.0, ADAPT_AFTER), None);
+    }
+
+    #[test]
+    fn quality_is_restored_step_by_step() {
+        let mut adaptive = Adaptive::new(&options());
+        run(&mut adaptive, 2.0, 2 * ADAPT_AFTER);
+        assert_eq!(
+            run(&mut adaptive, 0.1, ADAPT_AFTER).unwrap().audio_ctx,
+            None
+        );
+        let restored = run(&mut adaptive, 0.1, ADAPT_AFTER).unwrap();
+        assert_eq!(restored.beam_size, Some(4));
+        assert_eq!(restored.best_of, 5);
+        assert_eq!(run(&mut adaptive, 0.1, ADAPT_AFTER), None);
+    }
+
+    #[test]
+    fn rtf_in_between_keeps_the_level() {
+        let mut adaptive = Adaptive::new(&options());
+        // an interruption of the slow streak starts counting anew
+        assert_eq!(run(&mut adaptive, 2.0, ADAPT_AFTER - 1), None);
+        assert_eq!(adaptive.update(0.8), None);
+        assert_eq!(run(&mut adaptive, 2.0, ADAPT_AFTER - 1), None);
+        assert!(adaptive.update(2.0).is_some());
+        // neither slow nor fast enough to change anything
+        assert_eq!(run(&mut adaptive, 0.8, 10), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use cpal::{BufferSize, SampleRate, StreamConfig};
+use ringbuf::traits::{Consumer, Split};
+use ringbuf::HeapRb;
+
+use whisper_real_time::events::WarningSink;
+use whisper_real_time::vad::{self, get_resampler, Downmixer, Vad, VadActivity, VadOptions};
+use whisper_real_time::whisper::{Whisper, MAX_WHISPER_FRAME};
+
+use crate::error::PipelineError;
+
+/// Runs the VAD and whisper pipeline over a wav file as fast as possible and
+/// reports timings
+pub fn run(
+    file: &Path,
+    whisper: &mut Whisper,
+    vad_opts: VadOptions,
+    print: bool,
+) -> Result<(), PipelineError> {
+    let (header, waveform) = File::open(file)
+        .map_err(|err| err.to_string())
+        .and_then(|wav| wav_io::read_from_file(wav).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("error: could not read '{}': {err}", file.display());
+            process::exit(1);
+        });
+    let buf_size = (header.sample_rate / 30) * header.channels as u32;
+    let config = StreamConfig {
+        channels: header.channels,
+        sample_rate: SampleRate(header.sample_rate),
+        buffer_size: BufferSize::Fixed(buf_size),
+    };
+    let mut vad = Vad::try_new(&config, vad_opts).unwrap_or_else(|err| {
+        eprintln!("error: {err}");
+        process::exit(1);
+    });
+    let mut resample_with =
+        get_resampler(header.sample_rate, WarningSink::Stderr).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        });
+    let mut downmix = Downmixer::new(header.channels);
+    let ring = HeapRb::<i16>::new(MAX_WHISPER_FRAME * 2);
+    let (mut producer, mut consumer) = ring.split();
+    let (mut activity_tx, activity_rx) = mpsc::channel();
+
+    let mut vad_time = Duration::ZERO;
+    let mut transcription_time = Duration::ZERO;
+    let mut segments = 0;
+    let mut tokens = 0;
+    let mut decode_time = Duration::ZERO;
+    let mut chunks = waveform.chunks(buf_size as usize);
+    let mut ended = false;
+    while !ended {
+        let now = Instant::now();
+        match chunks.next() {
+            Some(chunk) => vad::audio_loop(
+                chunk,
+                &mut downmix,
+                &mut resample_with,
+                &mut producer,
+                &mut vad,
+                &mut activity_tx,
+                None,
+            ),
+            // speech running up to the end of the file gets transcribed too
+            None => vad::end_stream(&mut vad, &mut activity_tx),
+        }
+        vad_time += now.elapsed();
+
+        while let Ok(activity) = activity_rx.try_recv() {
+            let segment = match activity {
+                VadActivity::SpeechEnd(segment) => segment,
+                VadActivity::End(_) => {
+                    ended = true;
+                    continue;
+                }
+                _ => continue,
+            };
+            let now = Instant::now();
+            let buf = whisper
+                .audio_buf(segment.samples)
+                .expect("the vad splits segments to fit whisper's window");
+            if consumer.pop_slice(buf) != segment.samples {
+                panic!("logic error: not enough samples could be fetched");
+            }
+            let transcription = whisper.transcribe()?;
+            transcription_time += now.elapsed();
+            segments += 1;
+            let Some(transcription) = transcription else {
+                continue;
+            };
+            tokens += transcription.tokens;
+            decode_time += transcription.decode_time;
+            if print {
+                println!("{}", transcription.text);
+            }
+        }
+    }
+
+    let audio = Duration::from_secs_f64(
+        waveform.len() as f64 / header.channels as f64 / header.sample_rate as f64,
+    );
+    let total = vad_time + transcription_time;
+    println!("audio duration:       {audio:.2?}");
+    println!("vad time:             {vad_time:.2?}");
+    println!("transcription time:   {transcription_time:.2?}");
+    println!("  of that decoding:   {decode_time:.2?}");
+    println!(
+        "real time factor:     {:.3}",
+        total.as_secs_f64() / audio.as_secs_f64()
+    );
+    println!("segments:             {segments}");
+    println!("tokens:               {tokens}");
+    if !decode_time.is_zero() {
+        println!(
+            "tokens per second:    {:.1}",
+            tokens as f64 / decode_time.as_secs_f64()
+        );
+    }
+    if segments > 0 {
+        println!(
+            "mean segment latency: {:.2?}",
+            transcription_time / segments
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// program owning the clipboard for `--clipboard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClipboardBackend {
+    /// `wl-copy` from wl-clipboard, wayland sessions
+    WlCopy,
+    /// `xclip`, X11 sessions
+    Xclip,
+    /// `pbcopy`, macOS
+    Pbcopy,
+}
+
+impl ClipboardBackend {
+    /// The backend fitting the running session
+    pub fn detect() -> ClipboardBackend {
+        if cfg!(target_os = "macos") {
+            ClipboardBackend::Pbcopy
+        } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+            ClipboardBackend::WlCopy
+        } else {
+            ClipboardBackend::Xclip
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            ClipboardBackend::WlCopy => "wl-copy",
+            ClipboardBackend::Xclip => "xclip",
+            ClipboardBackend::Pbcopy => "pbcopy",
+        }
+    }
+
+    fn command(self) -> Command {
+        let mut command = Command::new(self.program());
+        if self == ClipboardBackend::Xclip {
+            command.args(["-selection", "clipboard"]);
+        }
+        command
+    }
+}
+
+/// what ends up on the clipboard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClipboardMode {
+    /// the latest transcript
+    #[default]
+    Segment,
+    /// all transcripts of the session, one per line
+    Session,
+}
+
+/// Copies transcripts to the clipboard
+pub struct Clipboard {
+    backend: ClipboardBackend,
+    mode: ClipboardMode,
+    /// transcripts so far in [`ClipboardMode::Session`]
+    session: String,
+}
+
+impl Clipboard {
+    pub fn new(backend: ClipboardBackend, mode: ClipboardMode) -> Clipboard {
+        Clipboard {
+            backend,
+            mode,
+            session: String::new(),
+        }
+    }
+
+    pub fn backend(&self) -> ClipboardBackend {
+        self.backend
+    }
+
+    /// Puts `text` (or the session including it) on the clipboard
+    pub fn copy(&mut self, text: &str) -> io::Result<()> {
+        let contents = match self.mode {
+            ClipboardMode::Segment => text,
+            ClipboardMode::Session => {
+                if !self.session.is_empty() {
+                    self.session.push('\n');
+                }
+                self.session.push_str(text);
+                &self.session
+            }
+        };
+        let program = self.backend.program();
+        let with_program = |err: io::Error| io::Error::new(err.kind(), format!("{program}: {err}"));
+        // the programs stay around in the background to serve the clipboard,
+        // so none of our output may be held open by them
+        let mut child = self
+            .backend
+            .command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(with_program)?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(contents.as_bytes())
+            .map_err(with_program)?;
+        let status = child.wait().map_err(with_program)?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{program} exited with {status}")));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use std::any::TypeId;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// settings that only make sense on the command line
+const COMMAND_LINE_ONLY: [&str; 4] = ["config", "no_config", "help", "version"];
+
+/// subcommand that runs when none is given
+pub const DEFAULT_SUBCOMMAND: &str = "listen";
+
+/// command line arguments made from a config file, see [`arguments`]
+#[derive(Default)]
+pub struct Arguments {
+    /// go before the subcommand
+    pub options: Vec<OsString>,
+    /// go after the subcommand
+    pub subcommand: Vec<OsString>,
+}
+
+/// `$XDG_CONFIG_HOME/whisper-real-time/config.toml`, falling back to
+/// `~/.config` without `XDG_CONFIG_HOME`
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("whisper-real-time").join("config.toml"))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// the file isn't valid toml
+    #[error("{0}")]
+    Syntax(#[from] toml::de::Error),
+    /// the value doesn't fit the option it's for
+    #[error("'{key}': {reason}")]
+    Invalid { key: String, reason: String },
+    /// the key isn't the name of a command line option
+    #[error("'{key}' isn't an option (see --help)")]
+    UnknownSetting { key: String },
+}
+
+/// `value` as a command line argument of `arg`, `None` for arrays and tables
+fn argument(arg: &Arg, value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) if is_path(arg) => Some(expand_home(string)),
+        Value::String(string) => Some(string.clone()),
+        Value::Integer(number) => Some(number.to_string()),
+        Value::Float(number) => Some(number.to_string()),
+        Value::Boolean(bool) => Some(bool.to_string()),
+        Value::Datetime(datetime) => Some(datetime.to_string()),
+        Value::Array(_) | Value::Table(_) => None,
+    }
+}
+
+/// Command line arguments for the settings in the config file at `path`
+///
+/// Settings are named like the long options (`model`, `silence-ms`, ...), an
+/// option given on `command_line` overrides the setting, so do options
+/// conflicting with it. Flags take `true` or `false`, options given more than
+/// once an array. A `~/` at the start of a path stands for the home directory.
+///
+/// Settings of the subcommand on `command_line` (`listen` without one) come
+/// back separately, they have to follow it. Settings of other subcommands are
+/// ignored.
+pub fn arguments(
+    path: &Path,
+    command: &Command,
+    command_line: &ArgMatches,
+) -> Result<Arguments, ConfigError> {
+    let settings: Table = fs::read_to_string(path)?.parse()?;
+    let (name, sub_line) = match command_line.subcommand() {
+        Some((name, matches)) => (name, Some(matches)),
+        None => (DEFAULT_SUBCOMMAND, None),
+    };
+    let subcommand = command
+        .find_subcommand(name)
+        .expect("clap only matches known subcommands");
+    let mut arguments = Arguments::default();
+    for (key, value) in settings {
+        if value.is_table() {
+            return Err(ConfigError::Invalid {
+                key,
+                reason: "tables aren't supported, settings go at the top".to_string(),
+            });
+        }
+        let id = key.replace('-', "_");
+        let (command, command_line, target) = if setting(command, &id).is_some() {
+            (command, Some(command_line), &mut arguments.options)
+        } else if setting(subcommand, &id).is_some() {
+            (subcommand, sub_line, &mut arguments.subcommand)
+        } else if command
+            .get_subcommands()
+            .any(|other| setting(other, &id).is_some())
+        {
+            continue;
+        } else {
+            return Err(ConfigError::UnknownSetting { key });
+        };
+        let arg = setting(command, &id).expect("looked up above");
+        let given = |id: &str| {
+            command_line
+                .is_some_and(|matches| matches.value_source(id) == Some(ValueSource::CommandLine))
+        };
+        // conflicts are only declared on one of both options
+        let conflicting = |other: &Arg| {
+            command.get_arg_conflicts_with(arg).contains(&other)
+                || command.get_arg_conflicts_with(other).contains(&arg)
+        };
+        if given(&id)
+            || command
+                .get_arguments()
+                .any(|other| given(other.get_id().as_str()) && conflicting(other))
+        {
+            continue;
+        }
+        let long = arg.get_long().expect("only options are settings");
+        let values = match (arg.get_action(), value) {
+            (ArgAction::SetTrue, Value::Boolean(true)) => {
+                target.push(format!("--{long}").into());
+                continue;
+            }
+            (ArgAction::SetTrue, Value::Boolean(false)) => continue,
+            (ArgAction::SetTrue, _) => {
+                return Err(ConfigError::Invalid {
+                    key,
+                    reason: "it's a flag, set it to true or false".to_string(),
+                })
+            }
+            // options with an optional value can be used like flags
+            (_, Value::Boolean(enabled)) if takes_no_value(arg) => {
+                if enabled {
+                    target.push(format!("--{long}").into());
+                }
+                continue;
+            }
+            (_, Value::Array(values)) => values,
+            (_, value) => vec![value],
+        };
+        for value in values {
+            let value = argument(arg, &value).ok_or_else(|| ConfigError::Invalid {
+                key: key.clone(),
+                reason: "arrays can't be nested".to_string(),
+            })?;
+            // `=` keeps values starting with a dash from looking like options
+            target.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(arguments)
+}
+
+/// the option of `command` a setting with `id` stands for
+fn setting<'a>(command: &'a Command, id: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !COMMAND_LINE_ONLY.contains(&arg.get_id().as_str()))
+        .find(|arg| arg.get_id() == id && arg.get_long().is_some())
+}
+
+/// whether `arg` can be given without a value, like `--push-to-talk`
+fn takes_no_value(arg: &Arg) -> bool {
+    arg.get_num_args()
+        .is_some_and(|values| values.min_values() == 0)
+}
+
+/// whether `arg` takes a path, whose `~/` is expanded
+fn is_path(arg: &Arg) -> bool {
+    arg.get_value_parser().type_id() == TypeId::of::<PathBuf>()
+}
+
+/// `~/` at the start of paths stands for the home directory like in a shell
+fn expand_home(value: &str) -> String {
+    match (value.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{value_parser, CommandFactory, Parser, Subcommand};
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Options {
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long)]
+        model: Option<PathBuf>,
+        #[arg(long)]
+        initial_prompt: Option<String>,
+        #[arg(long, value_parser = value_parser!(u64))]
+        silence_ms: Option<u64>,
+        #[arg(long, conflicts_with = "silence_ms")]
+        push_to_talk: bool,
+        #[arg(long)]
+        timestamps: bool,
+        #[command(subcommand)]
+        command: Option<Sub>,
+    }
+
+    #[derive(Subcommand)]
+    enum Sub {
+        Listen {
+            #[arg(long)]
+            device: Vec<String>,
+        },
+        Devices {
+            #[arg(long)]
+            host: Option<String>,
+        },
+    }
+
+    /// the arguments `config` turns into next to `command_line`
+    fn settings(config: &str, command_line: &[&str]) -> Result<Arguments, ConfigError> {
+        let dir = env::temp_dir().join(format!(
+            "whisper-real-time-config-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, config).unwrap();
+        let command = Options::command();
+        let given = command
+            .clone()
+            .ignore_errors(true)
+            .get_matches_from(["whisper-real-time"].iter().chain(command_line));
+        let arguments = arguments(&path, &command, &given);
+        fs::remove_dir_all(&dir).unwrap();
+        arguments
+    }
+
+    fn strings(arguments: &[OsString]) -> Vec<&str> {
+        let mut strings: Vec<_> = arguments.iter().map(|arg| arg.to_str().unwrap()).collect();
+        strings.sort();
+        strings
+    }
+
+    #[test]
+    fn settings_become_options() {
+        let arguments = settings(
+            "# comment\n\
+             silence-ms = 1_200\n\
+             timestamps = true\n\
+             push-to-talk = false\n\
+             device = ['first', \"second\"]\n\
+             host = 'jack'\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            strings(&arguments.options),
+            ["--silence-ms=1200", "--timestamps"]
+        );
+        // `devices` isn't running, so its settings are ignored
+        assert_eq!(
+            strings(&arguments.subcommand),
+            ["--device=first", "--device=second"]
+        );
+    }
+
+    #[test]
+    fn home_is_only_expanded_in_paths() {
+        let Ok(home) = env::var("HOME") else {
+            return;
+        };
+        let arguments = settings(
+            "model = \"~/models/base.bin\"\ninitial-prompt = \"~/ is home\"\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            strings(&arguments.options),
+            [
+                "--initial-prompt=~/ is home",
+                format!("--model={home}/models/base.bin").as_str()
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_the_config() {
+        let arguments = settings(
+            "silence-ms = 800\ntimestamps = true\n",
+            &["--push-to-talk", "--timestamps"],
+        )
+        .unwrap();
+        // `--push-to-talk` conflicts with `--silence-ms`
+        assert!(arguments.options.is_empty());
+        let arguments = settings("device = ['config']\n", &["listen", "--device", "mic"]).unwrap();
+        assert!(arguments.subcommand.is_empty());
+    }
+
+    #[test]
+    fn invalid_configs_are_errors() {
+        assert!(matches!(
+            settings("initial-prompt = 'unterminated\n", &[]),
+            Err(ConfigError::Syntax(_))
+        ));
+        assert!(matches!(
+            settings("silence-ms 800\n", &[]),
+            Err(ConfigError::Syntax(_))
+        ));
+        assert!(matches!(
+            settings("silence = 800\n", &[]),
+            Err(ConfigError::UnknownSetting { key }) if key == "silence"
+        ));
+        assert!(matches!(
+            settings("timestamps = 'yes'\n", &[]),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(matches!(
+            settings("[listen]\ndevice = ['mic']\n", &[]),
+            Err(ConfigError::Invalid { key, .. }) if key == "listen"
+        ));
+        assert!(matches!(
+            settings("model = [['nested']]\n", &[]),
+            Err(ConfigError::Invalid { .. })
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::{io, thread};
+
+/// commands accepted on the control channel
+pub enum ControlCommand {
+    /// `model <path>`: transcribe with another model from the next segment on
+    Model(PathBuf),
+    /// `redetect`: detect the language again on the next segment
+    Redetect,
+    /// `recent`: print the last `--history` transcripts to stderr
+    Recent,
+}
+
+impl ControlCommand {
+    fn parse(line: &str) -> Result<ControlCommand, String> {
+        let line = line.trim();
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match (command, argument.trim()) {
+            ("model", "") => Err("usage: model <path>".to_string()),
+            ("model", path) => Ok(ControlCommand::Model(PathBuf::from(path))),
+            ("redetect", "") => Ok(ControlCommand::Redetect),
+            ("recent", "") => Ok(ControlCommand::Recent),
+            (command, _) => Err(format!("unknown command '{command}'")),
+        }
+    }
+}
+
+/// Reads one [`ControlCommand`] per line from stdin on a separate thread
+pub fn read_stdin() -> Receiver<ControlCommand> {
+    let (commands, commands_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match ControlCommand::parse(&line) {
+                Ok(command) => {
+                    if commands.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("control: {err}"),
+            }
+        }
+    });
+    commands_rx
+}
@@ -0,0 +1,186 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, fs, thread};
+
+use serde_json::json;
+use whisper_real_time::metrics;
+
+/// clients get this long to send their command
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// the audio thread picks up device switches with its next buffer, a device
+/// that takes longer to open is reported as failed
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a device switch for the audio thread, answered on the sender with the
+/// reason it failed
+pub type DeviceSwitch = (String, Sender<Result<(), String>>);
+
+/// commands accepted on the control socket, one per connection
+enum SocketCommand {
+    /// `pause`: discard audio until `resume`
+    Pause,
+    /// `resume`: listen again after `pause`
+    Resume,
+    /// `status`: reply with a json line describing the pipeline
+    Status,
+    /// `switch-device <name>`: listen to another input device
+    SwitchDevice(String),
+    /// `shutdown`: transcribe speech in progress and exit
+    Shutdown,
+}
+
+impl SocketCommand {
+    fn parse(line: &str) -> Result<SocketCommand, String> {
+        let line = line.trim();
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match (command, argument.trim()) {
+            ("pause", "") => Ok(SocketCommand::Pause),
+            ("resume", "") => Ok(SocketCommand::Resume),
+            ("status", "") => Ok(SocketCommand::Status),
+            ("switch-device", "") => Err("usage: switch-device <name>".to_string()),
+            ("switch-device", name) => Ok(SocketCommand::SwitchDevice(name.to_string())),
+            ("shutdown", "") => Ok(SocketCommand::Shutdown),
+            (command, _) => Err(format!("unknown command '{command}'")),
+        }
+    }
+}
+
+/// Pipeline state the control socket reads and changes
+pub struct Controls {
+    /// set while audio is discarded
+    pub paused: Arc<AtomicBool>,
+    /// stops the audio side, see [`crate::interrupt`]
+    pub stopping: Arc<AtomicBool>,
+    /// name of the device the audio thread listens to
+    pub device: Arc<Mutex<String>>,
+    /// device switches for the audio thread, `None` if it listens to several
+    /// devices at once
+    pub switch_device: Option<Sender<DeviceSwitch>>,
+}
+
+/// `$XDG_RUNTIME_DIR/whisper-real-time.sock`, a per user socket in the temp
+/// directory without a runtime directory
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("whisper-real-time.sock"),
+        // SAFETY: getuid can't fail
+        None => env::temp_dir().join(format!("whisper-real-time-{}.sock", unsafe {
+            libc::getuid()
+        })),
+    }
+}
+
+/// Moves the process into the background
+///
+/// stdout and stderr stay where they were, so transcripts can still be
+/// redirected into a file. Has to happen before any thread is spawned
+pub fn detach() -> io::Result<()> {
+    // SAFETY: no other threads are running yet, which is all fork asks for
+    if unsafe { libc::daemon(1, 1) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Accepts commands on a unix socket at `path`, the socket file is removed
+/// again once this is dropped
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    pub fn bind(path: &Path, controls: Controls) -> io::Result<ControlSocket> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another instance is listening on the socket",
+                ));
+            }
+            // left behind by an instance that didn't exit cleanly
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        eprintln!("listening for commands on '{}'", path.display());
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = match client {
+                    Ok(client) => client,
+                    Err(err) => {
+                        eprintln!("control socket: could not accept client: {err}");
+                        continue;
+                    }
+                };
+                // clients are served one after another, commands are quick
+                if let Err(err) = serve(client, &controls) {
+                    eprintln!("control socket: {err}");
+                }
+            }
+        });
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Runs the command `client` sends and replies with `ok`, `error: <reason>`
+/// or the status
+fn serve(client: UnixStream, controls: &Controls) -> io::Result<()> {
+    client.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&client).read_line(&mut line)?;
+    let reply = match SocketCommand::parse(&line) {
+        Ok(SocketCommand::Pause) => {
+            controls.paused.store(true, Ordering::Relaxed);
+            eprintln!("[paused]");
+            "ok".to_string()
+        }
+        Ok(SocketCommand::Resume) => {
+            controls.paused.store(false, Ordering::Relaxed);
+            eprintln!("[resumed]");
+            "ok".to_string()
+        }
+        Ok(SocketCommand::Status) => json!({
+            "type": "status",
+            "paused": controls.paused.load(Ordering::Relaxed),
+            "device": controls.device.lock().expect("device name poisoned").clone(),
+            "segments": metrics::METRICS.segments(),
+        })
+        .to_string(),
+        Ok(SocketCommand::SwitchDevice(name)) => match switch_device(controls, name) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error: {err}"),
+        },
+        Ok(SocketCommand::Shutdown) => {
+            controls.stopping.store(true, Ordering::Relaxed);
+            "ok".to_string()
+        }
+        Err(err) => format!("error: {err}"),
+    };
+    writeln!(&client, "{reply}")
+}
+
+/// Hands the switch to the audio thread and waits for it to open the device
+fn switch_device(controls: &Controls, name: String) -> Result<(), String> {
+    let Some(switches) = &controls.switch_device else {
+        return Err("switching devices only works with a single --device".to_string());
+    };
+    let (done, done_rx) = mpsc::channel();
+    switches
+        .send((name, done))
+        .map_err(|_| "audio isn't running anymore".to_string())?;
+    done_rx
+        .recv_timeout(SWITCH_TIMEOUT)
+        .map_err(|_| "the device didn't open in time".to_string())?
+}
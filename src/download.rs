@@ -0,0 +1,222 @@
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use sha1::{Digest, Sha1};
+
+/// where `--model <name>` downloads models from
+const BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// models `--model` accepts by name, with the SHA-1 sums whisper.cpp lists
+/// for them
+const MODELS: [(&str, &str); 14] = [
+    ("tiny", "bd577a113a864445d4c299885e0cb97d4ba92b5f"),
+    ("tiny.en", "c78c86eb1a8faa21b369bcd33207cc90d64ae9df"),
+    ("base", "465707469ff3a37a2b9b8d8f89f2f99de7299dac"),
+    ("base.en", "137c40403d78fd54d454da0f9bd998f78703390c"),
+    ("small", "55356645c2b361a969dfd0ef2c5a50d530afd8d5"),
+    ("small.en", "db8a495a91d927739e50b3fc1cc4c6b8f6c2d022"),
+    ("medium", "fd9727b6e1217c2f614f9b698455c4ffd82463b4"),
+    ("medium.en", "8c30f0e44ce9560643ebd10bbe50cd20eafd3723"),
+    ("large-v1", "b1caaf735c4cc1429223d5a74f0f4d0b9b59a299"),
+    ("large-v2", "0f4c8e34f21cf1a914c59d8b3ce882345ad349d6"),
+    ("large-v2-q5_0", "00e39f2196344e901b3a2bd5814807a769bd1630"),
+    ("large-v3", "ad82bf6a9043ceed055076d0fd39f5f186ff8062"),
+    ("large-v3-q5_0", "e6e2ed78495d403bef4b7cff42ef4aaadcfea8de"),
+    ("large-v3-turbo", "4af2b29d7ec73d781377bfd1758ca957a807e941"),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    /// neither `XDG_CACHE_HOME` nor `HOME` is set
+    #[error("no cache directory, set XDG_CACHE_HOME")]
+    NoCacheDir,
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// the request failed or the server answered with an error
+    #[error("download failed: {0}")]
+    Transfer(Box<ureq::Error>),
+    /// the downloaded file isn't the model it should be
+    #[error("checksum mismatch, expected sha1 {expected} but got {actual}")]
+    Checksum { expected: String, actual: String },
+}
+
+/// `$XDG_CACHE_HOME/whisper-real-time/models`, falling back to `~/.cache`
+/// without `XDG_CACHE_HOME`
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(dir.join("whisper-real-time").join("models"))
+}
+
+/// The file to load for `--model <model>`
+///
+/// existing files are used as they are, names of known models resolve to
+/// their file in [`cache_dir`], which is downloaded first if it's missing.
+/// Anything else is returned unchanged for loading to fail on.
+pub fn resolve(model: &Path) -> Result<PathBuf, DownloadError> {
+    resolve_in(model, cache_dir)
+}
+
+fn resolve_in(
+    model: &Path,
+    cache_dir: impl FnOnce() -> Option<PathBuf>,
+) -> Result<PathBuf, DownloadError> {
+    let known = model
+        .to_str()
+        .and_then(|name| MODELS.iter().find(|(known, _)| *known == name));
+    let Some((name, sha1)) = known.filter(|_| !model.exists()) else {
+        return Ok(model.to_path_buf());
+    };
+    let dir = cache_dir().ok_or(DownloadError::NoCacheDir)?;
+    let path = dir.join(format!("ggml-{name}.bin"));
+    if !path.exists() {
+        fs::create_dir_all(&dir)?;
+        download(name, sha1, &path)?;
+    }
+    Ok(path)
+}
+
+/// Downloads the model `name` to `path` and checks it against `sha1`
+fn download(name: &str, sha1: &str, path: &Path) -> Result<(), DownloadError> {
+    let url = format!("{BASE_URL}/ggml-{name}.bin");
+    eprintln!("downloading model '{name}' to '{}'", path.display());
+    let response = ureq::get(&url)
+        .call()
+        .map_err(|err| DownloadError::Transfer(Box::new(err)))?;
+    let size = response
+        .header("Content-Length")
+        .and_then(|size| size.parse().ok());
+    let saved = save(Progress::new(response.into_reader(), size), sha1, path);
+    eprintln!();
+    saved
+}
+
+/// Writes `body` to `path` if its SHA-1 sum is `sha1`
+///
+/// the body goes to a temporary file next to `path` first, which only takes
+/// its place once it's complete and checked. Interrupted downloads leave
+/// nothing behind and runs downloading the same model don't share a file.
+fn save(mut body: impl Read, sha1: &str, path: &Path) -> Result<(), DownloadError> {
+    let dir = path
+        .parent()
+        .expect("models are saved in the cache directory");
+    let file_name = path.file_name().expect("models have a file name");
+    let mut partial = tempfile::Builder::new()
+        .prefix(file_name)
+        .suffix(".part")
+        .tempfile_in(dir)?;
+    let mut file = BufWriter::new(partial.as_file_mut());
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+    file.into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .sync_all()?;
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != sha1 {
+        return Err(DownloadError::Checksum {
+            expected: sha1.to_string(),
+            actual,
+        });
+    }
+    partial.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// draws the progress of a download on stderr while it's read
+struct Progress<R> {
+    body: R,
+    read: u64,
+    size: Option<u64>,
+    percent: Option<u64>,
+}
+
+impl<R> Progress<R> {
+    fn new(body: R, size: Option<u64>) -> Progress<R> {
+        Progress {
+            body,
+            read: 0,
+            size,
+            percent: None,
+        }
+    }
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.body.read(buffer)?;
+        self.read += read as u64;
+        let percent = self.size.map(|size| self.read * 100 / size.max(1));
+        if percent != self.percent {
+            self.percent = percent;
+            eprint!(
+                "\r{:>3}% of {}MB",
+                percent.unwrap_or_default(),
+                self.size.unwrap_or_default() >> 20
+            );
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "whisper-real-time-download-{name}-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn known_names_resolve_to_the_cache() {
+        let dir = temp_dir("resolve");
+        let cached = dir.join("ggml-base.en.bin");
+        fs::write(&cached, "model").unwrap();
+        assert_eq!(
+            resolve_in(Path::new("base.en"), || Some(dir.clone())).unwrap(),
+            cached
+        );
+        // files and unknown names are loaded as they are
+        assert_eq!(resolve_in(&cached, || None).unwrap(), cached);
+        assert_eq!(
+            resolve_in(Path::new("my-model"), || None).unwrap(),
+            Path::new("my-model")
+        );
+        assert!(matches!(
+            resolve_in(Path::new("tiny"), || None),
+            Err(DownloadError::NoCacheDir)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_checked_models_are_saved() {
+        let dir = temp_dir("save");
+        let path = dir.join("ggml-tiny.bin");
+        let abc = "a9993e364706816aba3e25717850c26c9cd0d89d";
+        assert!(matches!(
+            save(&b"abd"[..], abc, &path),
+            Err(DownloadError::Checksum { actual, .. }) if actual != abc
+        ));
+        // nothing is left behind by a failed download
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        save(&b"abc"[..], abc, &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use whisper_real_time::vad::AudioError;
+use whisper_real_time::whisper::TranscribeError;
+
+/// exit code of a run whose audio input failed while it was running
+pub const AUDIO_FAILED_EXIT_CODE: u8 = 3;
+/// exit code of a run whisper failed to transcribe in
+pub const TRANSCRIPTION_FAILED_EXIT_CODE: u8 = 4;
+
+/// failures that end a run
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error(transparent)]
+    Audio(#[from] AudioError),
+    #[error("could not create the recording '{}': {source}", path.display())]
+    Recording { path: PathBuf, source: io::Error },
+    #[error("could not finalize the recording: {0}")]
+    FinishRecording(io::Error),
+    #[error("could not create '{}': {source}", path.display())]
+    Subtitles { path: PathBuf, source: io::Error },
+    #[error("could not create a session log in '{}': {source}", dir.display())]
+    SessionLog { dir: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Transcribe(#[from] TranscribeError),
+    #[error("could not run whisper.cpp: {0}")]
+    WhisperCpp(io::Error),
+}
+
+impl PipelineError {
+    /// Prints the error, returns the exit code that tells what failed
+    pub fn report(self) -> ExitCode {
+        eprintln!("error: {self}");
+        ExitCode::from(self.exit_code())
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self {
+            PipelineError::Audio(_) => AUDIO_FAILED_EXIT_CODE,
+            PipelineError::Transcribe(_) | PipelineError::WhisperCpp(_) => {
+                TRANSCRIPTION_FAILED_EXIT_CODE
+            }
+            PipelineError::Recording { .. }
+            | PipelineError::FinishRecording(_)
+            | PipelineError::Subtitles { .. }
+            | PipelineError::SessionLog { .. } => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_while_running_have_their_own_exit_codes() {
+        let audio = PipelineError::from(AudioError::StreamFailed("gone".to_string()));
+        assert_eq!(audio.exit_code(), AUDIO_FAILED_EXIT_CODE);
+        let whisper_cpp = PipelineError::WhisperCpp(io::ErrorKind::NotFound.into());
+        assert_eq!(whisper_cpp.exit_code(), TRANSCRIPTION_FAILED_EXIT_CODE);
+        let session_log = PipelineError::SessionLog {
+            dir: "logs".into(),
+            source: io::ErrorKind::PermissionDenied.into(),
+        };
+        assert_eq!(session_log.exit_code(), 1);
+        assert!(session_log.to_string().contains("'logs'"));
+    }
+}
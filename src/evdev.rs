@@ -0,0 +1,169 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// event type of key presses and releases
+const EV_KEY: u16 = 0x01;
+/// `EVIOCGBIT(EV_KEY, len)`, asks a device which keys it has
+const EVIOCGBIT_KEY: libc::c_ulong =
+    (2 << 30) | ((KEY_BITS as libc::c_ulong) << 16) | (0x45 << 8) | 0x21;
+/// bytes of the bitmask of all key codes
+const KEY_BITS: usize = libc::KEY_MAX as usize / 8 + 1;
+/// values of key events
+const RELEASED: i32 = 0;
+const PRESSED: i32 = 1;
+
+/// key names `--push-to-talk` accepts besides letters, digits and `f1` to
+/// `f24`, with their linux key codes
+const KEYS: &[(&str, u16)] = &[
+    ("esc", 1),
+    ("backspace", 14),
+    ("tab", 15),
+    ("enter", 28),
+    ("leftctrl", 29),
+    ("leftshift", 42),
+    ("rightshift", 54),
+    ("leftalt", 56),
+    ("space", 57),
+    ("capslock", 58),
+    ("numlock", 69),
+    ("scrolllock", 70),
+    ("rightctrl", 97),
+    ("rightalt", 100),
+    ("home", 102),
+    ("up", 103),
+    ("pageup", 104),
+    ("left", 105),
+    ("right", 106),
+    ("end", 107),
+    ("down", 108),
+    ("pagedown", 109),
+    ("insert", 110),
+    ("delete", 111),
+    ("pause", 119),
+    ("leftmeta", 125),
+    ("rightmeta", 126),
+    ("compose", 127),
+    // the side buttons of mice
+    ("btn_side", 0x113),
+    ("btn_extra", 0x114),
+];
+
+/// The linux key code of the key called `name`
+///
+/// names are case insensitive and may carry the `KEY_` prefix of
+/// `input-event-codes.h`, numbers of more than one digit are taken as the code
+/// itself
+pub fn key_code(name: &str) -> Option<u16> {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix("key_").unwrap_or(&name);
+    if let Some(&(_, code)) = KEYS.iter().find(|(key, _)| *key == name) {
+        return Some(code);
+    }
+    // keys in rows share consecutive codes
+    let rows: [(&str, u16); 4] = [
+        ("1234567890", 2),
+        ("qwertyuiop", 16),
+        ("asdfghjkl", 30),
+        ("zxcvbnm", 44),
+    ];
+    let mut chars = name.chars();
+    if let (Some(key), None) = (chars.next(), chars.next()) {
+        return rows
+            .iter()
+            .find_map(|(row, first)| Some(first + row.find(key)? as u16));
+    }
+    if let Ok(code) = name.parse::<u16>() {
+        return Some(code);
+    }
+    let function = name.strip_prefix('f')?.parse::<u16>().ok()?;
+    match function {
+        1..=10 => Some(58 + function),
+        11..=12 => Some(76 + function),
+        13..=24 => Some(170 + function),
+        _ => None,
+    }
+}
+
+/// Keeps `paused` cleared exactly while the key with `code` is held on
+/// any keyboard
+///
+/// the key is read from the kernel's input devices, so it works in every
+/// session and whichever window has the focus. That needs read access to
+/// `/dev/input/event*`, usually through the `input` group
+pub fn hold_to_listen(code: u16, paused: Arc<AtomicBool>) -> io::Result<()> {
+    let mut denied = false;
+    let mut devices = 0;
+    for entry in fs::read_dir("/dev/input")? {
+        let path = entry?.path();
+        let is_event_device = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+        if !is_event_device {
+            continue;
+        }
+        let device = match File::open(&path) {
+            Ok(device) => device,
+            Err(err) => {
+                denied |= err.kind() == io::ErrorKind::PermissionDenied;
+                continue;
+            }
+        };
+        if !has_key(&device, code) {
+            continue;
+        }
+        devices += 1;
+        let paused = paused.clone();
+        thread::spawn(move || watch_key(device, code, &paused));
+    }
+    match (devices, denied) {
+        (0, true) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "no permission to read /dev/input, add yourself to the input group",
+        )),
+        (0, false) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no input device has this key",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// whether `device` can report the key with `code`
+fn has_key(device: &File, code: u16) -> bool {
+    let mut bits = [0u8; KEY_BITS];
+    // SAFETY: the ioctl writes at most `KEY_BITS` bytes, the size encoded in
+    // the request
+    let written = unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGBIT_KEY, bits.as_mut_ptr()) };
+    let byte = code as usize / 8;
+    written > byte as i32 && bits[byte] & (1 << (code % 8)) != 0
+}
+
+/// Follows the key on `device` until the device goes away
+fn watch_key(mut device: File, code: u16, paused: &AtomicBool) {
+    let mut event = [0u8; mem::size_of::<libc::input_event>()];
+    while device.read_exact(&mut event).is_ok() {
+        // SAFETY: input_event is plain data and the buffer has its size
+        let event = unsafe { (event.as_ptr() as *const libc::input_event).read_unaligned() };
+        if event.type_ != EV_KEY || event.code != code {
+            continue;
+        }
+        // key repeats keep it held
+        match event.value {
+            PRESSED => {
+                paused.store(false, Ordering::Relaxed);
+                eprintln!("[listening]");
+            }
+            RELEASED => {
+                paused.store(true, Ordering::Relaxed);
+                eprintln!("[not listening]");
+            }
+            _ => (),
+        }
+    }
+}
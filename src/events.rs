@@ -0,0 +1,239 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::metrics::METRICS;
+
+/// problems that don't stop transcription but are worth knowing about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warning {
+    /// audio from the device was dropped because processing fell behind
+    AudioDropped { samples: usize },
+    /// the transcription ring was full
+    RingFull { samples: usize },
+    /// a finished segment was dropped because whisper fell behind
+    SegmentDropped { samples: usize },
+    /// the input is overdriven
+    Clipping { fraction: f32 },
+    /// the device doesn't deliver whisper's sample rate
+    Resampling { rate: u32 },
+    /// the resampler rejected audio, which was dropped
+    ResampleFailed { samples: usize },
+    /// whisper.cpp took too long for a segment
+    Timeout,
+}
+
+impl Warning {
+    fn kind(&self) -> &'static str {
+        match self {
+            Warning::AudioDropped { .. } => "audio_dropped",
+            Warning::RingFull { .. } => "ring_full",
+            Warning::SegmentDropped { .. } => "segment_dropped",
+            Warning::Clipping { .. } => "clipping",
+            Warning::Resampling { .. } => "resampling",
+            Warning::ResampleFailed { .. } => "resample_failed",
+            Warning::Timeout => "timeout",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Warning::AudioDropped { .. } => "audio is being dropped".to_string(),
+            Warning::RingFull { .. } => {
+                "transcription audio ring was full, dropped some audio".to_string()
+            }
+            Warning::SegmentDropped { samples } => format!(
+                "whisper is falling behind, dropped a segment of {:.1}s",
+                *samples as f32 / crate::whisper::SAMPLE_RATE as f32
+            ),
+            Warning::Clipping { fraction } => format!(
+                "input is clipping ({:.1}% of samples), consider lowering the input gain",
+                fraction * 100.0
+            ),
+            Warning::Resampling { rate } => {
+                format!(
+                    "running with resampling src{rate}->dest{}",
+                    crate::whisper::SAMPLE_RATE
+                )
+            }
+            Warning::ResampleFailed { samples } => {
+                format!("could not resample audio, dropped {samples} samples")
+            }
+            Warning::Timeout => "whisper.cpp timed out, skipping segment".to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut event = json!({
+            "type": "warning",
+            "kind": self.kind(),
+        });
+        match self {
+            Warning::AudioDropped { samples }
+            | Warning::RingFull { samples }
+            | Warning::SegmentDropped { samples }
+            | Warning::ResampleFailed { samples } => event["samples"] = json!(samples),
+            Warning::Clipping { fraction } => event["fraction"] = json!(fraction),
+            Warning::Resampling { rate } => event["rate"] = json!(rate),
+            Warning::Timeout => (),
+        }
+        event
+    }
+}
+
+/// where [`Warning`]s end up
+#[derive(Debug, Clone, Default)]
+pub enum WarningSink {
+    /// human readable text on stderr
+    #[default]
+    Stderr,
+    /// json events, collected until the thread writing the other events
+    /// picks them up with [`PendingWarnings::take`]
+    Json(Arc<PendingWarnings>),
+}
+
+impl WarningSink {
+    /// a [`WarningSink::Json`] with nothing pending yet
+    pub fn json() -> WarningSink {
+        WarningSink::Json(Arc::default())
+    }
+
+    pub fn emit(&self, warning: Warning) {
+        if let Warning::AudioDropped { samples }
+        | Warning::RingFull { samples }
+        | Warning::SegmentDropped { samples }
+        | Warning::ResampleFailed { samples } = warning
+        {
+            METRICS.dropped(samples);
+        }
+        match self {
+            WarningSink::Stderr => eprintln!("warning: {}", warning.message()),
+            WarningSink::Json(pending) => pending.add(warning),
+        }
+    }
+}
+
+/// Warnings of a [`WarningSink::Json`] waiting to be written
+///
+/// Audio callbacks only bump atomics here, they never wait for the thread
+/// writing the events. Warnings of the same kind that come in between two
+/// [`PendingWarnings::take`]s are combined.
+#[derive(Debug, Default)]
+pub struct PendingWarnings {
+    audio_dropped: AtomicUsize,
+    ring_full: AtomicUsize,
+    segment_dropped: AtomicUsize,
+    /// bits of the last clipping fraction, 0 for none
+    clipping: AtomicU32,
+    /// rate of the last device that needs resampling, 0 for none
+    resampling: AtomicU32,
+    resample_failed: AtomicUsize,
+    timeouts: AtomicUsize,
+}
+
+impl PendingWarnings {
+    fn add(&self, warning: Warning) {
+        match warning {
+            Warning::AudioDropped { samples } => {
+                self.audio_dropped.fetch_add(samples, Ordering::Relaxed);
+            }
+            Warning::RingFull { samples } => {
+                self.ring_full.fetch_add(samples, Ordering::Relaxed);
+            }
+            Warning::SegmentDropped { samples } => {
+                self.segment_dropped.fetch_add(samples, Ordering::Relaxed);
+            }
+            Warning::Clipping { fraction } => {
+                self.clipping.store(fraction.to_bits(), Ordering::Relaxed);
+            }
+            Warning::Resampling { rate } => self.resampling.store(rate, Ordering::Relaxed),
+            Warning::ResampleFailed { samples } => {
+                self.resample_failed.fetch_add(samples, Ordering::Relaxed);
+            }
+            Warning::Timeout => {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Takes the warnings added since the last call
+    ///
+    /// warnings come from audio threads, writing them where the other events
+    /// are written keeps lines from mixing
+    pub fn take(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        let resampling = self.resampling.swap(0, Ordering::Relaxed);
+        if resampling != 0 {
+            warnings.push(Warning::Resampling { rate: resampling });
+        }
+        let samples = self.audio_dropped.swap(0, Ordering::Relaxed);
+        if samples != 0 {
+            warnings.push(Warning::AudioDropped { samples });
+        }
+        let samples = self.ring_full.swap(0, Ordering::Relaxed);
+        if samples != 0 {
+            warnings.push(Warning::RingFull { samples });
+        }
+        let samples = self.segment_dropped.swap(0, Ordering::Relaxed);
+        if samples != 0 {
+            warnings.push(Warning::SegmentDropped { samples });
+        }
+        let samples = self.resample_failed.swap(0, Ordering::Relaxed);
+        if samples != 0 {
+            warnings.push(Warning::ResampleFailed { samples });
+        }
+        let clipping = self.clipping.swap(0, Ordering::Relaxed);
+        if clipping != 0 {
+            warnings.push(Warning::Clipping {
+                fraction: f32::from_bits(clipping),
+            });
+        }
+        let timeouts = self.timeouts.swap(0, Ordering::Relaxed);
+        warnings.extend((0..timeouts).map(|_| Warning::Timeout));
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings_of_a_kind_are_combined_until_taken() {
+        let sink = WarningSink::json();
+        sink.emit(Warning::RingFull { samples: 100 });
+        sink.emit(Warning::RingFull { samples: 50 });
+        sink.emit(Warning::Timeout);
+        sink.emit(Warning::Timeout);
+        let WarningSink::Json(pending) = &sink else {
+            unreachable!();
+        };
+        assert_eq!(
+            pending.take(),
+            [
+                Warning::RingFull { samples: 150 },
+                Warning::Timeout,
+                Warning::Timeout
+            ]
+        );
+        assert!(pending.take().is_empty());
+    }
+
+    #[test]
+    fn the_latest_clipping_fraction_is_reported() {
+        let pending = PendingWarnings::default();
+        pending.add(Warning::Clipping { fraction: 0.2 });
+        pending.add(Warning::Clipping { fraction: 0.05 });
+        assert_eq!(pending.take(), [Warning::Clipping { fraction: 0.05 }]);
+    }
+
+    #[test]
+    fn json_events_carry_kind_and_amount() {
+        let event = Warning::AudioDropped { samples: 480 }.to_json();
+        assert_eq!(
+            event,
+            json!({"type": "warning", "kind": "audio_dropped", "samples": 480})
+        );
+    }
+}
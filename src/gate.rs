@@ -0,0 +1,32 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// pause switch toggled by the signal handler, set by [`toggle_on_signal`]
+static SWITCH: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+extern "C" fn toggle(_signal: libc::c_int) {
+    // only atomics in here, anything else isn't async signal safe
+    if let Some(switch) = SWITCH.get() {
+        switch.fetch_xor(true, Ordering::Relaxed);
+    }
+}
+
+/// Toggles `paused` whenever the process receives SIGUSR1
+///
+/// lets keybindings of the desktop or scripts drive push to talk with
+/// `kill -USR1 <pid>`, can only be set up once per process
+pub fn toggle_on_signal(paused: Arc<AtomicBool>) -> io::Result<()> {
+    if SWITCH.set(paused).is_err() {
+        return Err(io::Error::other(
+            "the signal already toggles another switch",
+        ));
+    }
+    // SAFETY: `toggle` is async signal safe, it only touches atomics
+    if unsafe { libc::signal(libc::SIGUSR1, toggle as *const () as libc::sighandler_t) }
+        == libc::SIG_ERR
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
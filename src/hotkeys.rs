@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::terminal;
+
+/// key toggling the paused state
+const PAUSE_KEY: u8 = b' ';
+
+/// Listens for key presses on the controlling terminal
+///
+/// The terminal only leaves line buffered mode (and stops echoing) while this
+/// is alive, output processing stays untouched so transcripts on stdout keep
+/// their layout. The terminal is also put back when the process exits
+/// without dropping this.
+pub struct Hotkeys {
+    tty: File,
+    original: libc::termios,
+}
+
+impl Hotkeys {
+    /// Toggles `paused` whenever space is pressed
+    ///
+    /// fails when the process has no controlling terminal
+    pub fn listen(paused: Arc<AtomicBool>) -> io::Result<Hotkeys> {
+        let tty = File::open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let mut original = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `fd` is an open file descriptor and tcgetattr initializes
+        // `original` when it succeeds
+        let original = unsafe {
+            if libc::tcgetattr(fd, original.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            original.assume_init()
+        };
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `fd` is an open file descriptor and `raw` a valid termios
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        terminal::raw_mode(fd, original);
+
+        let mut keys = tty.try_clone()?;
+        thread::spawn(move || {
+            let mut key = [0u8];
+            while keys.read_exact(&mut key).is_ok() {
+                if key[0] != PAUSE_KEY {
+                    continue;
+                }
+                // fetch_xor returns the previous state
+                match paused.fetch_xor(true, Ordering::Relaxed) {
+                    false => eprintln!("[paused]"),
+                    true => eprintln!("[resumed]"),
+                }
+            }
+        });
+        Ok(Hotkeys { tty, original })
+    }
+}
+
+impl Drop for Hotkeys {
+    fn drop(&mut self) {
+        terminal::cooked();
+        // SAFETY: the tty is still open and `original` came from tcgetattr
+        unsafe {
+            libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.original);
+        }
+    }
+}
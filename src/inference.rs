@@ -0,0 +1,281 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use whisper_real_time::events::WarningSink;
+use whisper_real_time::vad;
+use whisper_real_time::whisper::{
+    self, TranscribeError, Transcription, Whisper, WhisperOptions, WhisperSetupError,
+};
+
+use crate::adaptive::Adaptive;
+use crate::error::PipelineError;
+
+/// a whisper.cpp binary that transcribes segments instead of the library
+pub struct Binary {
+    pub path: PathBuf,
+    /// the process is killed once a segment takes longer
+    pub timeout: Option<Duration>,
+    pub warnings: WarningSink,
+}
+
+/// work for the inference thread, done in the order it was submitted
+pub enum Job {
+    /// a finished segment, answered with [`Answer::Transcribed`] (or
+    /// [`Answer::Decoded`] for a whisper.cpp binary), which hand the samples
+    /// back
+    Transcribe(Vec<i16>),
+    /// audio that is only looked at, for partials and the wake word, and
+    /// doesn't become context of following segments
+    Preview(Vec<i16>),
+    /// switches to another model, answered with [`Answer::Switched`]
+    Model(PathBuf),
+    /// forgets the detected language, not answered
+    Redetect,
+    /// answered with [`Answer::Recent`]
+    Recent,
+}
+
+pub enum Answer {
+    /// a line whisper.cpp printed, more follow until [`Answer::Decoded`]
+    Line(String),
+    Transcribed {
+        transcription: Option<Transcription>,
+        /// time spent transcribing, without waiting in the queue
+        elapsed: Duration,
+        samples: Vec<i16>,
+    },
+    Decoded {
+        text: Option<String>,
+        elapsed: Duration,
+        samples: Vec<i16>,
+    },
+    Previewed(Option<Transcription>),
+    /// whisper failed, the thread stops and takes no more jobs
+    Failed(PipelineError),
+    Switched(Result<(), WhisperSetupError>),
+    Recent(Vec<Transcription>),
+}
+
+/// an answer to the job [`Inference::submit`] returned `job` for
+pub struct Output {
+    pub job: u64,
+    pub answer: Answer,
+}
+
+/// Runs whisper on its own thread so audio, partials and events keep flowing
+/// while a segment is transcribed
+pub struct Inference {
+    jobs: Sender<(u64, Job)>,
+    next_job: u64,
+}
+
+impl Inference {
+    /// Loads `model` on the inference thread, returns once it's ready
+    ///
+    /// answers are sent to `results` in the order the jobs were submitted
+    pub fn spawn<T: From<Output> + Send + 'static>(
+        model: PathBuf,
+        options: WhisperOptions,
+        adaptive: bool,
+        binary: Option<Binary>,
+        results: SyncSender<T>,
+    ) -> Result<Inference, WhisperSetupError> {
+        let (jobs, jobs_rx) = mpsc::channel::<(u64, Job)>();
+        let (ready_tx, ready) = mpsc::channel();
+        // whisper's parameters can't be sent between threads, so the model
+        // is loaded where it runs
+        thread::spawn(move || {
+            let mut whisper = match Whisper::with_options(&model, options) {
+                Ok(whisper) => {
+                    let _ = ready_tx.send(Ok(()));
+                    whisper
+                }
+                Err(err) => {
+                    let _ = ready_tx.send(Err(err));
+                    return;
+                }
+            };
+            let mut adaptive = adaptive.then(|| Adaptive::new(whisper.options()));
+            let mut model = model;
+            let send = |job, answer| results.send(T::from(Output { job, answer })).is_ok();
+            for (job, work) in jobs_rx {
+                let answer = match work {
+                    Job::Transcribe(samples) => match &binary {
+                        Some(binary) => {
+                            let started = Instant::now();
+                            let text = crate::decode_bin(
+                                model.clone(),
+                                binary.path.clone(),
+                                &samples,
+                                binary.timeout,
+                                binary.warnings.clone(),
+                                |line| {
+                                    send(job, Answer::Line(line.to_string()));
+                                },
+                            );
+                            match text {
+                                Ok(text) => Answer::Decoded {
+                                    text,
+                                    elapsed: started.elapsed(),
+                                    samples,
+                                },
+                                Err(err) => Answer::Failed(PipelineError::WhisperCpp(err)),
+                            }
+                        }
+                        None => {
+                            let started = Instant::now();
+                            let transcription = match whisper.transcribe_samples(&samples) {
+                                Ok(transcription) => transcription,
+                                // merged segments can outgrow the window the vad splits at
+                                Err(TranscribeError::TooLong(err)) => {
+                                    eprintln!("warning: {err}, skipping segment");
+                                    None
+                                }
+                                Err(err) => {
+                                    send(job, Answer::Failed(err.into()));
+                                    break;
+                                }
+                            };
+                            let elapsed = started.elapsed();
+                            let audio = vad::samples_to_duration(samples.len());
+                            let rtf = elapsed.as_secs_f64() / audio.as_secs_f64();
+                            if let Some(quality) =
+                                adaptive.as_mut().and_then(|adaptive| adaptive.update(rtf))
+                            {
+                                whisper.set_quality(
+                                    quality.best_of,
+                                    quality.beam_size,
+                                    quality.audio_ctx,
+                                );
+                            }
+                            Answer::Transcribed {
+                                transcription,
+                                elapsed,
+                                samples,
+                            }
+                        }
+                    },
+                    Job::Preview(samples) => match whisper.preview_samples(&samples) {
+                        Ok(transcription) => Answer::Previewed(transcription),
+                        Err(TranscribeError::TooLong(_)) => Answer::Previewed(None),
+                        Err(err) => Answer::Failed(err.into()),
+                    },
+                    Job::Model(path) => {
+                        let loaded = match binary {
+                            Some(_) => whisper::check_model_file(&path),
+                            None => whisper.set_model(&path),
+                        };
+                        if loaded.is_ok() {
+                            model = path;
+                        }
+                        Answer::Switched(loaded)
+                    }
+                    Job::Redetect => {
+                        whisper.redetect_language();
+                        continue;
+                    }
+                    Job::Recent => Answer::Recent(whisper.recent().to_vec()),
+                };
+                let failed = matches!(answer, Answer::Failed(_));
+                if !send(job, answer) || failed {
+                    break;
+                }
+            }
+        });
+        ready
+            .recv()
+            .expect("the inference thread reports whether the model loaded")?;
+        Ok(Inference { jobs, next_job: 0 })
+    }
+
+    /// Queues `work` behind the jobs submitted before, returns the id its
+    /// answer carries
+    pub fn submit(&mut self, work: Job) -> u64 {
+        let job = self.next_job;
+        self.next_job += 1;
+        // a thread that's gone took the receiving side with it, nothing is
+        // waiting for the answer then
+        let _ = self.jobs.send((job, work));
+        job
+    }
+}
+
+/// what happens to a finished segment while the segment queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SegmentOverflow {
+    /// it stays in the ring until whisper catches up, once the ring is full
+    /// the vad drops audio
+    #[default]
+    Wait,
+    /// it is dropped with a warning
+    Drop,
+}
+
+/// Limits the segments taken off the rings that whisper hasn't finished yet
+///
+/// without a limit they would pile up in memory for as long as whisper is
+/// slower than real time
+#[derive(Clone)]
+pub struct SegmentSlots(Arc<(Mutex<usize>, Condvar)>, usize);
+
+impl SegmentSlots {
+    pub fn new(capacity: usize) -> SegmentSlots {
+        SegmentSlots(Arc::new((Mutex::new(0), Condvar::new())), capacity)
+    }
+
+    /// Waits until a slot is free
+    pub fn acquire(&self) -> Slot {
+        let (taken, freed) = &*self.0;
+        let mut taken = freed
+            .wait_while(taken.lock().unwrap(), |taken| *taken >= self.1)
+            .unwrap();
+        *taken += 1;
+        Slot(self.0.clone())
+    }
+
+    /// A slot if one is free
+    pub fn try_acquire(&self) -> Option<Slot> {
+        let mut taken = self.0 .0.lock().unwrap();
+        if *taken >= self.1 {
+            return None;
+        }
+        *taken += 1;
+        Some(Slot(self.0.clone()))
+    }
+}
+
+/// a segment's place in [`SegmentSlots`], freed when dropped
+pub struct Slot(Arc<(Mutex<usize>, Condvar)>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let (taken, freed) = &*self.0;
+        *taken.lock().unwrap() -= 1;
+        freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_freed_when_dropped() {
+        let slots = SegmentSlots::new(2);
+        let first = slots.try_acquire().unwrap();
+        let _second = slots.acquire();
+        assert!(slots.try_acquire().is_none());
+        let waiting = thread::spawn({
+            let slots = slots.clone();
+            move || drop(slots.acquire())
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiting.is_finished());
+        drop(first);
+        waiting.join().unwrap();
+        assert!(slots.try_acquire().is_some());
+    }
+}
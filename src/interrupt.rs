@@ -0,0 +1,49 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::terminal;
+
+/// flag raised by the signal handler, set by [`stop_on_interrupt`]
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+/// whether a signal arrived before
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(signal: libc::c_int) {
+    // only atomics, `terminal::restore`, `signal` and `raise` in here,
+    // anything else isn't async signal safe
+    if let Some(stop) = STOP.get() {
+        stop.store(true, Ordering::Relaxed);
+    }
+    if !INTERRUPTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // a second Ctrl-C kills the process right away, without destructors
+    // putting the terminal back
+    terminal::restore();
+    // SAFETY: `signal` and `raise` are async signal safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Sets `stop` once the process receives SIGINT or SIGTERM instead of dying
+///
+/// lets the audio side finish the utterance in progress so it still gets
+/// transcribed, receiving the signal a second time terminates as usual. Can
+/// only be set up once per process
+pub fn stop_on_interrupt(flag: Arc<AtomicBool>) -> io::Result<()> {
+    if STOP.set(flag).is_err() {
+        return Err(io::Error::other("interrupts already raise another flag"));
+    }
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: `stop` is async signal safe
+        if unsafe { libc::signal(signal, stop as *const () as libc::sighandler_t) } == libc::SIG_ERR
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! Continuous speech transcription with whisper
+//!
+//! Audio from any source is downmixed and resampled to 16kHz mono
+//! ([`vad::to_mono`]), split into utterances by the voice activity detection
+//! ([`vad::Vad`]) and transcribed by [`whisper::Whisper`].
+//!
+//! The simplest way to run the whole pipeline on audio from anywhere is a
+//! [`transcriber::Transcriber`]: audio pushed into it is split into
+//! utterances and every utterance comes back as a
+//! [`transcriber::Transcript`] on a channel. [`transcriber::AudioFeed`] stops
+//! after the voice detection, speech it detects ends up in a ring buffer and
+//! gets announced as [`vad::VadActivity`] on a channel, every
+//! [`vad::VadActivity::SpeechEnd`] segment is then popped from the ring and
+//! handed to [`whisper::Whisper::transcribe_samples`] by the caller.
+
+pub mod events;
+pub mod merge;
+pub mod metrics;
+pub mod mix;
+pub mod queue;
+#[cfg(feature = "silero")]
+pub mod silero;
+pub mod transcriber;
+pub mod vad;
+pub mod whisper;
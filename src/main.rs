@@ -1,192 +1,2696 @@
 use core::panic;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::env;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::ops::ControlFlow;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::sync::mpsc::{self};
+use std::process::{self, Child, Command, ExitCode, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
-use clap::{arg, Parser};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{BufferSize, Stream};
-use ringbuf::traits::{Consumer, Split};
+use clap::builder::TypedValueParser;
+use clap::{CommandFactory, Parser, Subcommand};
+use clipboard::{Clipboard, ClipboardBackend, ClipboardMode};
+use config::ConfigError;
+use control::ControlCommand;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{BufferSize, Device, Host, SampleRate, Stream, StreamConfig};
+use daemon::{ControlSocket, Controls};
+use error::PipelineError;
+use hotkeys::Hotkeys;
+use inference::{Answer, Binary, Inference, Job, Output, SegmentOverflow, SegmentSlots, Slot};
+use monitor::Monitor;
+use output::{ColorMode, OutputFormat, PartialLine};
+use raw::RawFormat;
+use record::Recorder;
+use refine::{Refiner, Revision};
+use ringbuf::traits::{Consumer, Observer, Split};
 use ringbuf::HeapRb;
-use vad::{get_resampler, Vad, VadActivity};
-use wav_io::header;
+use serde_json::json;
+use session::SessionLog;
+use sink::{SharedStdout, SinkSpec, Sinks, Transcript};
+use subtitle::{SubtitleFormat, SubtitleWriter};
+use tcp::TcpBroadcaster;
+use time::{OffsetDateTime, UtcOffset};
+use tui::Tui;
+use typing::{TypeBackend, TypeSuffix, Typist};
+use wake::{WakeWord, WAKE_WINDOW};
 use wav_io::writer::Writer;
-use whisper::{Whisper, WhisperOptions, MAX_WHISPER_FRAME, SAMPLE_RATE};
+use websocket::WebSocketBroadcaster;
+use whisper_real_time::events::{Warning, WarningSink};
+use whisper_real_time::merge::{self, ShortSegmentMerger, MERGE_SPACER};
+use whisper_real_time::metrics;
+use whisper_real_time::mix::Mixer;
+use whisper_real_time::queue::{self, OverflowPolicy, QueueReceiver, QueueSender, SendOutcome};
+use whisper_real_time::transcriber::AudioFeed;
+use whisper_real_time::vad::{
+    self, get_resampler, AlwaysSpeech, AudioError, ClipDetector, Downmixer, Segment, Vad,
+    VadActivity, VadMode, VadOptions,
+};
+use whisper_real_time::whisper::{
+    Translate, Whisper, WhisperOptions, DEFAULT_ENTROPY_THRESHOLD, DEFAULT_LOGPROB_THRESHOLD,
+    DEFAULT_TEMPERATURE_INC, DEFAULT_THREADS, MAX_AUDIO_CTX, MAX_TEXT_CTX, MAX_WHISPER_FRAME,
+    MAX_WINDOW_SECS, MIN_WHISPER_FRAME, SAMPLE_RATE,
+};
 
-mod vad;
-mod whisper;
+mod adaptive;
+mod bench;
+mod clipboard;
+mod config;
+mod control;
+mod daemon;
+mod download;
+mod error;
+mod evdev;
+mod gate;
+mod hotkeys;
+mod inference;
+mod interrupt;
+mod monitor;
+mod output;
+mod raw;
+mod record;
+mod refine;
+mod session;
+mod sink;
+mod subtitle;
+mod tcp;
+mod terminal;
+mod tui;
+mod typing;
+mod wake;
+mod websocket;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
-    /// path to the whisper.cpp model to be used
-    #[arg(short, long, value_name = "FILE")]
-    model: PathBuf,
+    /// what to do, listens to the microphone without a subcommand
+    ///
+    /// options go before the subcommand, options of the subcommand after it
+    #[command(subcommand)]
+    mode: Option<Mode>,
+
+    /// config file with defaults for any of these options, defaults to
+    /// `~/.config/whisper-real-time/config.toml`
+    ///
+    /// settings are named like the options (`silence-ms = 800`), options given
+    /// here override them
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// ignore the config file
+    #[arg(long, conflicts_with = "config")]
+    no_config: bool,
+
+    /// path to the whisper.cpp model to be used, or the name of a model to
+    /// download (tiny, tiny.en, base, base.en, small, medium, large-v3, ...)
+    #[arg(short, long, value_name = "FILE", required = true)]
+    model: Option<PathBuf>,
+
+    /// smaller model (a path or name like for `--model`) whose transcripts
+    /// are printed right away, `--model` then transcribes every segment again
+    /// in the background and its transcript follows as a revision
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["whisper_cpp", "bench"])]
+    draft_model: Option<PathBuf>,
 
     /// path to the whisper.cpp binary
     #[arg(short, long, value_name = "FILE")]
     whisper_cpp: Option<PathBuf>,
 
-    /// path to a file to transcribe
+    /// how often loading the model is retried when whisper.cpp fails to set
+    /// it up, e.g. on a busy gpu
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    load_retries: u32,
+
+    /// run whisper on the cpu even in builds with gpu support
+    #[arg(long, conflicts_with = "whisper_cpp")]
+    cpu: bool,
+
+    /// index of the gpu whisper runs on
+    #[arg(long, value_name = "ID", default_value_t = 0, conflicts_with = "cpu")]
+    gpu_device: i32,
+
+    /// kill whisper.cpp and skip the segment if it takes longer than this
+    #[arg(long, value_name = "MS", requires = "whisper_cpp")]
+    whisper_cpp_timeout_ms: Option<u64>,
+
+    /// measure the throughput of the pipeline on a wav file and exit
     ///
-    /// Transcribes the file instead of the microphone stream
-    #[arg(short, long, value_name = "FILE")]
-    file: Option<PathBuf>,
+    /// the file is processed as fast as possible instead of in real time
+    #[arg(long, value_name = "FILE")]
+    bench: Option<PathBuf>,
+
+    /// print transcripts while benchmarking
+    #[arg(long, requires = "bench")]
+    print: bool,
+
+    /// print the resolved device and transcription settings and exit
+    /// without listening
+    #[arg(long)]
+    dry_run: bool,
+
+    /// pause and resume transcribing by pressing space in the terminal
+    ///
+    /// audio is discarded while paused, speech that started before pausing
+    /// still gets transcribed
+    #[arg(long, conflicts_with = "control_stdin")]
+    hotkeys: bool,
+
+    /// keep running in the background and take commands on a unix socket
+    ///
+    /// `pause`, `resume`, `status`, `switch-device <name>` and `shutdown`,
+    /// one per connection
+    #[arg(long, conflicts_with_all = ["bench", "hotkeys", "dry_run"])]
+    daemon: bool,
+
+    /// control socket of `--daemon`, defaults to
+    /// `$XDG_RUNTIME_DIR/whisper-real-time.sock`
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    socket: Option<PathBuf>,
+
+    /// only listen while push to talk is active, toggled by SIGUSR1 (and
+    /// space with `--hotkeys`) or while KEY is held
+    ///
+    /// starts out not listening, speech is finished as soon as listening
+    /// stops. With a KEY (`f12`, `rightctrl`, `btn_side`, ...) the voice
+    /// activity detection is bypassed, everything said while holding it is one
+    /// segment
+    #[arg(
+        long,
+        value_name = "KEY",
+        num_args = 0..=1,
+        conflicts_with = "bench"
+    )]
+    push_to_talk: Option<Option<String>>,
+
+    /// only transcribe after this phrase was said, until nobody spoke for
+    /// `--wake-timeout-ms`
+    ///
+    /// while waiting for it only the first seconds of every utterance are
+    /// transcribed to look for the phrase
+    #[arg(
+        long,
+        value_name = "PHRASE",
+        value_parser = parse_wake_word,
+        conflicts_with_all = ["whisper_cpp", "bench"]
+    )]
+    wake_word: Option<String>,
+
+    /// silence in milliseconds after which the `--wake-word` has to be said
+    /// again
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 10_000,
+        requires = "wake_word"
+    )]
+    wake_timeout_ms: u64,
+
+    /// exit after the first transcribed utterance
+    ///
+    /// exits with code 2 if nothing was transcribed
+    #[arg(long)]
+    once: bool,
+
+    /// give up if no speech starts within this many milliseconds
+    #[arg(long, value_name = "MS", requires = "once")]
+    timeout_ms: Option<u64>,
+
+    /// read control commands from stdin while running
+    ///
+    /// `model <path>` switches to another model without interrupting the audio
+    /// stream, `recent` prints the last `--history` transcripts to stderr
+    #[arg(long)]
+    control_stdin: bool,
+
+    /// amount of recent transcripts kept for the `recent` control command
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    history: usize,
+
+    /// transcribe every `--device` on its own and label the transcripts with
+    /// the device they were heard on
+    #[arg(long, conflicts_with_all = ["record", "monitor_device"])]
+    tag_devices: bool,
+
+    /// play the captured audio back on this output device
+    #[arg(long, value_name = "NAME")]
+    monitor_device: Option<String>,
+
+    /// translate the speech instead of transcribing it
+    ///
+    /// whisper is only able to translate into english
+    #[arg(long, value_enum, default_value_t)]
+    translate: Translate,
+
+    /// language code of the speech, `auto` detects it for every segment
+    /// [default: en]
+    #[arg(long, value_name = "CODE")]
+    language: Option<String>,
+
+    /// restrict language detection to these comma separated codes
+    ///
+    /// keeps detection from drifting to unrelated languages in multilingual
+    /// conversations, implies `--language auto`
+    #[arg(long, value_name = "CODES", value_delimiter = ',')]
+    languages: Vec<String>,
+
+    /// with `--language auto` detect the language on only the first MS of a
+    /// segment and keep it for the following ones
+    ///
+    /// faster and steadier than detecting it on every whole segment,
+    /// `redetect` on `--control-stdin` forgets the kept language
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "whisper_cpp"
+    )]
+    detect_window_ms: Option<u64>,
+
+    /// detect the language again every N segments instead of keeping the
+    /// first one
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "detect_window_ms",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    redetect_every: Option<u32>,
+
+    /// amount of candidates whisper decodes to pick the best one from
+    ///
+    /// higher values trade latency for accuracy
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(i32).range(1..)
+    )]
+    best_of: i32,
+
+    /// decode with beam search keeping N candidates instead of sampling
+    /// greedily
+    ///
+    /// slower but can be more accurate, `--best-of` has no effect with it
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "whisper_cpp",
+        value_parser = clap::value_parser!(i32).range(1..)
+    )]
+    beam_size: Option<i32>,
+
+    /// threads whisper runs on
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_THREADS,
+        value_parser = clap::value_parser!(u64).range(1..).map(|threads| threads as usize)
+    )]
+    threads: usize,
+
+    /// text whisper treats as preceding every segment
+    ///
+    /// biases the transcription towards names and jargon used in it
+    #[arg(long, value_name = "TEXT", conflicts_with = "whisper_cpp")]
+    initial_prompt: Option<String>,
+
+    /// feed the last TOKENS tokens of each transcript to whisper as the prompt
+    /// of the next segment
+    ///
+    /// keeps continuous dictation consistent across pauses, replaces
+    /// `--initial-prompt` once there is a transcript
+    #[arg(
+        long,
+        value_name = "TOKENS",
+        default_value_t = 0,
+        conflicts_with = "whisper_cpp"
+    )]
+    carry_context: usize,
+
+    /// sampling temperature of the first decoding attempt, between 0 and 1
+    ///
+    /// 0 always picks the most likely token, `--best-of` candidates are only
+    /// sampled above 0
+    #[arg(long, value_name = "T", default_value_t = 0.0, value_parser = parse_temperature)]
+    temperature: f32,
+
+    /// temperature added every time a decoding is rejected by the entropy or
+    /// log probability threshold, 0 disables these retries
+    ///
+    /// helps when whisper gets stuck repeating itself
+    #[arg(
+        long,
+        value_name = "T",
+        default_value_t = DEFAULT_TEMPERATURE_INC,
+        value_parser = parse_temperature
+    )]
+    temperature_inc: f32,
+
+    /// retry decodings more repetitive than this (compression ratio)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ENTROPY_THRESHOLD)]
+    entropy_threshold: f32,
 
-    /// list available audio devices
+    /// retry decodings with a lower mean token log probability
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_LOGPROB_THRESHOLD,
+        allow_negative_numbers = true
+    )]
+    logprob_threshold: f32,
+
+    /// size of whisper's audio context, defaults to the model's (1500)
+    ///
+    /// lower values speed up processing at some accuracy cost, 1500
+    /// corresponds to 30s of audio
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(i32).range(1..=MAX_AUDIO_CTX as i64)
+    )]
+    audio_ctx: Option<i32>,
+
+    /// lower the quality settings while transcription can't keep up with
+    /// real time and restore them once it does again
+    #[arg(long, conflicts_with = "whisper_cpp")]
+    adaptive: bool,
+
+    /// maximum amount of tokens whisper produces per segment, unlimited by
+    /// default
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i32).range(1..))]
+    max_tokens: Option<i32>,
+
+    /// tokens of earlier text whisper uses as context
+    ///
+    /// only matters for models built with an extended text context, others
+    /// use at most 224 tokens anyway
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "whisper_cpp",
+        value_parser = clap::value_parser!(i32).range(1..=MAX_TEXT_CTX as i64)
+    )]
+    max_text_ctx: Option<i32>,
+
+    /// allow whisper to emit non speech tokens like `[music]`
+    #[arg(long)]
+    no_suppress_nonspeech: bool,
+
+    /// allow whisper to split a transcription into multiple segments
+    #[arg(long)]
+    multi_segment: bool,
+
+    /// let whisper predict timestamp tokens
+    ///
+    /// can improve punctuation for some models
+    #[arg(long)]
+    whisper_timestamps: bool,
+
+    /// silence in milliseconds that ends a speech segment (the vad hangover)
+    ///
+    /// defaults depend on the language (240ms for english)
+    #[arg(long, value_name = "MS")]
+    silence_ms: Option<u64>,
+
+    /// trailing silence in milliseconds recorded after speech
+    ///
+    /// has to be shorter than the silence that ends a segment, defaults depend
+    /// on the language (90ms for english)
+    #[arg(long, value_name = "MS")]
+    tail_ms: Option<u64>,
+
+    /// longest stretch of audio in seconds whisper transcribes at once,
+    /// longer utterances are split
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = MAX_WINDOW_SECS,
+        value_parser = clap::value_parser!(u64).range(1..=MAX_WINDOW_SECS)
+    )]
+    max_window_sec: u64,
+
+    /// audio in milliseconds shared by both halves when an utterance is too
+    /// long for whisper and has to be split
+    ///
+    /// words repeated at the cut are removed from the output
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    overlap_ms: u64,
+
+    /// audio in milliseconds before a detected onset that is still
+    /// transcribed (the vad pre pad), so fast first syllables aren't clipped
+    ///
+    /// also makes speech start only after two speech frames in a row, off by
+    /// default
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    onset_lookahead_ms: u64,
+
+    /// speech in milliseconds the detector has to hear without a pause
+    /// before an utterance starts, shorter noises like clicks are ignored
+    ///
+    /// 0 starts on the first speech frame
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    vad_min_speech_ms: u64,
+
+    /// report that nobody is speaking every SECONDS of silence
+    ///
+    /// sent as `{"type":"idle"}` events to `--tcp` clients so they can tell a
+    /// quiet room apart from a hung process, off by default
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    idle_interval: Option<u64>,
+
+    /// cut leading and trailing silence off each segment before transcribing
+    ///
+    /// saves whisper some work and can reduce hallucinated filler words
+    #[arg(long)]
+    trim_silence: bool,
+
+    /// utterances shorter than this many milliseconds are not transcribed
+    ///
+    /// can be lowered for short voice commands, whisper may still not pick up
+    /// clips well below the default
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = (MIN_WHISPER_FRAME * 1000 / SAMPLE_RATE) as u64
+    )]
+    min_audio_ms: u64,
+
+    /// don't transcribe the first N detected utterances
+    ///
+    /// a heuristic against whisper transcribing ambient noise right after
+    /// startup
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip_first: usize,
+
+    /// don't transcribe utterances starting within the first MS milliseconds
+    ///
+    /// a heuristic like `--skip-first`
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    warmup_skip_ms: u64,
+
+    /// merge utterances too short for whisper into the following one
+    ///
+    /// keeps short answers like "yes" or "no" from being discarded
+    #[arg(long)]
+    merge_short: bool,
+
+    /// silence in milliseconds put between merged utterances so whisper
+    /// doesn't run their words together
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "merge_short",
+        default_value_t = MERGE_SPACER.as_millis() as u64
+    )]
+    merge_gap_ms: u64,
+
+    /// how speech is detected
+    ///
+    /// `rms` can behave better than the neural detector in steady noise,
+    /// `silero` needs `--silero-model`
+    #[arg(long, value_enum, default_value_t)]
+    vad: VadMode,
+
+    /// onnx file of the silero vad model (silero_vad.onnx, v5)
+    ///
+    /// it runs with onnxruntime, in builds with the `silero` feature
+    #[arg(long, value_name = "PATH", required_if_eq("vad", "silero"))]
+    silero_model: Option<PathBuf>,
+
+    /// seconds of audio the transcription queue can hold
+    ///
+    /// raise this if audio gets dropped while whisper is busy
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_RING_SECONDS,
+        value_parser = parse_ring_seconds
+    )]
+    ring_seconds: usize,
+
+    /// amount of audio buffers that can wait for voice detection
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    input_queue: u16,
+
+    /// what to do with incoming audio while the input queue is full
+    #[arg(long, value_enum, default_value_t)]
+    on_overflow: OverflowPolicy,
+
+    /// amount of finished segments that can wait for whisper
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    segment_queue: u16,
+
+    /// what to do with a finished segment while the segment queue is full
+    ///
+    /// `wait` leaves it in the transcription ring (see `--ring-seconds`),
+    /// `drop` discards it with a warning
+    #[arg(long, value_enum, default_value_t)]
+    on_segment_overflow: SegmentOverflow,
+
+    /// print the voice detection decision for every 30ms frame to stderr
+    ///
+    /// `S` marks speech, the count is the silence in frames and `>` marks
+    /// frames inside a segment
+    #[arg(long)]
+    vad_debug: bool,
+
+    /// print additional diagnostics to stderr
     #[arg(short, long)]
-    list: bool,
+    verbose: bool,
+
+    /// suppress warnings about the input audio (like clipping) and the
+    /// "speech started" notices
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// print every sentence of a transcript on its own line
+    #[arg(long)]
+    split_sentences: bool,
+
+    /// don't flush stdout after every segment
+    ///
+    /// transcripts then reach pipes in bursts, which trades latency for
+    /// throughput
+    #[arg(long)]
+    no_flush: bool,
+
+    /// separate transcripts by a blank line when the silence before them
+    /// lasted at least this many milliseconds
+    ///
+    /// turns long pauses in dictations into paragraphs
+    #[arg(long, value_name = "MS")]
+    paragraph_gap_ms: Option<u64>,
+
+    /// prefix every transcript with the time it was spoken at
+    ///
+    /// uses the wall clock for live audio and the offset into the file
+    /// otherwise
+    #[arg(long)]
+    timestamps: bool,
+
+    /// prefix every transcript with its offset since the start of the stream
+    /// as `[MM:SS]`, or `[HH:MM:SS]` past an hour
+    #[arg(long, conflicts_with = "timestamps")]
+    inline_timestamps: bool,
+
+    /// log every transcript to a new timestamped file in this directory
+    #[arg(long, value_name = "DIR")]
+    session_dir: Option<PathBuf>,
+
+    /// send every transcript as a JSON line to clients connecting to this
+    /// address
+    #[arg(long, value_name = "ADDR")]
+    tcp: Option<String>,
+
+    /// send transcripts to this sink too, can be given multiple times
+    ///
+    /// `stdout` (only final transcripts, in place of the usual lines),
+    /// `file:PATH`, `command:CMD` (run with `sh -c` for every transcript,
+    /// which it reads from stdin), `clipboard`, `type`, `tcp:ADDR` or
+    /// `websocket:ADDR` (`speech_start`, `partial` and `final` events)
+    #[arg(long, value_name = "SINK")]
+    sink: Vec<SinkSpec>,
+
+    /// serve prometheus metrics (segments, audio seconds, dropped samples,
+    /// real time factor, queue depth, uptime) over http on this address
+    #[arg(long, value_name = "ADDR")]
+    metrics: Option<String>,
+
+    /// how transcripts are written to stdout
+    ///
+    /// `jsonl` writes the same events `--tcp` clients receive, `srt` and
+    /// `vtt` write subtitles timed by the detected speech
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// send a `partial` event with the transcript so far every MS
+    /// milliseconds while someone speaks
+    ///
+    /// partials are best effort and get revised by the `final` event with the
+    /// same `segment_id`, in text mode they are updated in place on a terminal
+    /// and printed one per line otherwise
+    #[arg(
+        long,
+        value_name = "MS",
+        conflicts_with = "whisper_cpp",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    partial_ms: Option<u64>,
+
+    /// report runtime warnings (dropped audio, clipping, ...) as json events
+    /// on stdout instead of text on stderr, implies `--format jsonl`
+    #[arg(long)]
+    events: bool,
+
+    /// type every transcript into the focused window, for dictating into any
+    /// application
+    #[arg(long = "type")]
+    type_text: bool,
+
+    /// program sending the keystrokes, picked by the session type if unset
+    #[arg(long, value_enum, requires = "type_text")]
+    type_backend: Option<TypeBackend>,
+
+    /// what gets typed after every transcript
+    #[arg(long, value_enum, default_value_t, requires = "type_text")]
+    type_suffix: TypeSuffix,
+
+    /// copy transcripts to the clipboard
+    #[arg(long)]
+    clipboard: bool,
+
+    /// whether the clipboard holds the latest transcript or the whole session
+    #[arg(long, value_enum, default_value_t, requires = "clipboard")]
+    clipboard_mode: ClipboardMode,
+
+    /// program owning the clipboard, picked by the session type if unset
+    #[arg(long, value_enum, requires = "clipboard")]
+    clipboard_backend: Option<ClipboardBackend>,
+
+    /// time every word, `final` events get a `words` array and subtitles a
+    /// cue per word
+    #[arg(long, conflicts_with = "whisper_cpp")]
+    word_timestamps: bool,
+
+    /// color transcripts by how confident whisper was
+    ///
+    /// green is confident, yellow unsure and red likely wrong
+    #[arg(long, value_enum, default_value_t)]
+    color: ColorMode,
+
+    /// show the input level, the voice detection, the queue and the
+    /// transcripts with their latency on a full screen view
+    ///
+    /// messages on stderr go to a log below the transcripts, the transcripts
+    /// are printed once it exits
+    #[arg(long, conflicts_with_all = ["format", "events", "bench", "daemon", "dry_run"])]
+    tui: bool,
+
+    /// record the whole session (16kHz mono) into this wav file
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// write a WebVTT subtitle file of the transcription
+    #[arg(long, value_name = "FILE")]
+    output_vtt: Option<PathBuf>,
+}
+
+/// roughly two whisper frames
+const DEFAULT_RING_SECONDS: usize = 60;
+/// finished segments and answers of the inference thread waiting to be
+/// handled before the rings stop being drained
+const SEGMENT_QUEUE: usize = 16;
+
+fn parse_ring_seconds(arg: &str) -> Result<usize, String> {
+    let seconds: usize = arg.parse().map_err(|err| format!("{err}"))?;
+    if seconds * SAMPLE_RATE < MAX_WHISPER_FRAME {
+        return Err(format!(
+            "has to hold at least one whisper frame ({}s)",
+            MAX_WHISPER_FRAME.div_ceil(SAMPLE_RATE)
+        ));
+    }
+    Ok(seconds)
+}
+
+fn parse_temperature(arg: &str) -> Result<f32, String> {
+    let temperature: f32 = arg.parse().map_err(|err| format!("{err}"))?;
+    if !(0.0..=1.0).contains(&temperature) {
+        return Err("has to be between 0 and 1".to_string());
+    }
+    Ok(temperature)
+}
+
+fn parse_wake_word(arg: &str) -> Result<String, String> {
+    WakeWord::new(arg, Duration::ZERO)?;
+    Ok(arg.to_string())
+}
+
+/// how often revisions of `--draft-model` runs are checked for while waiting
+/// for audio
+const REVISION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// how often `--events` warnings of the audio threads are written while
+/// waiting for audio
+const WARNING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// how often the microphone thread checks whether it should stop while no
+/// audio arrives
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// exit code of `--once` runs that didn't transcribe anything
+const NO_SPEECH_EXIT_CODE: u8 = 2;
+
+/// subcommands, they pick what runs
+#[derive(Subcommand)]
+enum Mode {
+    /// transcribe the microphone, same as no subcommand
+    Listen(MicArgs),
+    /// transcribe an audio file instead of the microphone
+    Transcribe {
+        /// wav file, `-` reads raw audio from stdin (see `--stdin-format`)
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// layout of the raw audio read from `-`, as
+        /// `<s16le|f32le>:<sample rate>:<channels>`
+        #[arg(long, value_name = "FORMAT", default_value = "s16le:16000:1")]
+        stdin_format: RawFormat,
+    },
+    /// list available audio hosts and the input devices of the selected one
+    Devices {
+        #[command(flatten)]
+        host: HostArgs,
+    },
+    /// transcribe the microphone and send the `--tcp` events as WebSocket
+    /// messages to clients connecting to an address, for browser based
+    /// captions and overlays
+    Serve {
+        #[arg(value_name = "ADDR")]
+        addr: String,
+
+        #[command(flatten)]
+        mic: MicArgs,
+    },
+}
+
+impl Mode {
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Listen(_) => "listen",
+            Mode::Transcribe { .. } => "transcribe",
+            Mode::Devices { .. } => "devices",
+            Mode::Serve { .. } => "serve",
+        }
+    }
+}
+
+#[derive(clap::Args, Default)]
+struct HostArgs {
+    /// audio host (backend) to use, like `alsa` or `jack`, see `devices`
+    ///
+    /// falls back to the default host if this one is unavailable
+    #[arg(long, value_name = "NAME")]
+    host: Option<String>,
+}
+
+/// options of the subcommands capturing microphones
+#[derive(clap::Args, Default)]
+struct MicArgs {
+    #[command(flatten)]
+    host: HostArgs,
 
     /// audio device to listen to
+    ///
+    /// can be given multiple times to capture from several devices, their
+    /// audio is mixed into a single stream
     #[arg(short, long)]
-    device: Option<String>,
+    device: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let mut command_line = env::args_os().collect::<Vec<_>>();
+    // only tells which options were given, missing ones may come from the
+    // config file
+    let given = Args::command()
+        .ignore_errors(true)
+        .get_matches_from(&command_line);
+    let explicit = given.get_one::<PathBuf>("config").cloned();
+    let path = match given.get_flag("no_config") {
+        true => None,
+        false => explicit.clone().or_else(config::default_path),
+    };
+    if let Some(path) = path {
+        match config::arguments(&path, &Args::command(), &given) {
+            Ok(arguments) => {
+                // settings go before the subcommand, or clap takes them for
+                // options of it
+                command_line.splice(1..1, arguments.options);
+                if !arguments.subcommand.is_empty() {
+                    if given.subcommand().is_none() {
+                        command_line.push(config::DEFAULT_SUBCOMMAND.into());
+                    }
+                    command_line.extend(arguments.subcommand);
+                }
+            }
+            // only a config file asked for has to exist
+            Err(ConfigError::Io(err))
+                if explicit.is_none() && err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                eprintln!("error: could not read config '{}': {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    whisper(Args::parse_from(command_line))
+}
+
+/// what the main loop gets woken up by
+enum Step {
+    /// segments come with their samples and their place in the segment queue
+    Activity(VadActivity, Vec<i16>, Option<Slot>),
+    Inference(Output),
+    /// the audio side is done, nothing but answers follow
+    Closed,
+}
+
+impl From<Output> for Step {
+    fn from(output: Output) -> Step {
+        Step::Inference(output)
+    }
+}
+
+/// a segment handed to the inference thread, written out once its answer
+/// arrives
+struct Pending<'a> {
+    segment_id: u64,
+    segment: Segment,
+    /// taken by [`Job::Transcribe`], the answer hands them back
+    samples: Vec<i16>,
+    /// freed once the segment is written out or dropped
+    _slot: Option<Slot>,
+    /// when the segment was handed over, for its latency
+    queued: Instant,
+    timestamp: Option<String>,
+    label: Option<&'a str>,
+    /// the wake word was heard in this segment
+    woke: bool,
+    /// a line of it was written already
+    printed: bool,
+}
+
+impl Pending<'_> {
+    /// whether a blank line goes before the next line of the segment, only
+    /// the first one can start a paragraph
+    fn starts_paragraph(&mut self, gap: Option<Duration>, last_end: Option<Duration>) -> bool {
+        !mem::replace(&mut self.printed, true)
+            && gap
+                .zip(last_end)
+                .is_some_and(|(gap, end)| self.segment.start_time().saturating_sub(end) >= gap)
+    }
 }
 
-fn main() {
-    let args = Args::parse();
-    whisper(args);
+/// what an answer of the inference thread belongs to
+enum Queued<'a> {
+    Final(Box<Pending<'a>>),
+    /// a segment checked for the wake word, transcribed once it's heard
+    Wake(Box<Pending<'a>>),
+    Partial {
+        source: usize,
+        segment_id: u64,
+    },
+    Model(PathBuf),
+    Recent,
 }
 
 // this is a drop guard/container object
 // no need to read the values
 enum StreamHandle {
-    #[allow(dead_code)]
-    Thread(JoinHandle<()>),
+    Thread(JoinHandle<Result<(), AudioError>>),
     #[allow(dead_code)]
     Stream(Stream),
 }
 
-fn whisper(args: Args) {
-    if args.list {
-        let host = cpal::default_host();
-        let devices = host.input_devices().unwrap();
-        eprintln!("Available audio devices:");
-        for dev in devices {
-            println!("- {}", dev.name().expect("couldnt get device name"));
+/// reference point for `--timestamps`
+enum Clock {
+    /// local wall clock time at which the stream started
+    Live(OffsetDateTime),
+    /// offset into the transcribed file
+    File,
+}
+
+impl Clock {
+    fn timestamp(&self, offset: Duration) -> String {
+        let (hours, minutes, seconds) = match self {
+            Clock::Live(stream_start) => {
+                let time = *stream_start + offset;
+                (
+                    time.hour() as u64,
+                    time.minute() as u64,
+                    time.second() as u64,
+                )
+            }
+            Clock::File => {
+                let seconds = offset.as_secs();
+                (seconds / 3600, (seconds / 60) % 60, seconds % 60)
+            }
+        };
+        format!("[{hours:02}:{minutes:02}:{seconds:02}]")
+    }
+}
+
+/// formats an offset for `--inline-timestamps`
+fn inline_timestamp(offset: Duration) -> String {
+    let seconds = offset.as_secs();
+    match seconds / 3600 {
+        0 => format!("[{:02}:{:02}]", seconds / 60, seconds % 60),
+        hours => format!(
+            "[{hours:02}:{:02}:{:02}]",
+            (seconds / 60) % 60,
+            seconds % 60
+        ),
+    }
+}
+
+/// Prints the audio hosts and the input devices of `host` for `devices`
+fn list_devices(host: Option<&str>, format: OutputFormat) -> ExitCode {
+    let host = vad::pick_host(host);
+    let devices = vad::list_input_devices(&host);
+    if matches!(format, OutputFormat::Jsonl | OutputFormat::Json) {
+        let hosts = cpal::available_hosts()
+            .into_iter()
+            .map(|id| {
+                json!({
+                    "type": "host",
+                    "name": id.name(),
+                    "selected": id == host.id(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let devices = devices
+            .into_iter()
+            .map(|device| {
+                json!({
+                    "type": "device",
+                    "name": device.name,
+                    "host": host.id().name(),
+                    "default": device.default,
+                    "sample_rates": device.sample_rates,
+                    "channels": device.channels,
+                })
+            })
+            .collect::<Vec<_>>();
+        match format {
+            OutputFormat::Json => println!("{}", json!({ "hosts": hosts, "devices": devices })),
+            _ => {
+                for entry in hosts.iter().chain(&devices) {
+                    println!("{entry}");
+                }
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+    eprintln!("Available audio hosts:");
+    for id in cpal::available_hosts() {
+        match id == host.id() {
+            true => println!("- {} (selected)", id.name()),
+            false => println!("- {}", id.name()),
         }
-        return;
     }
+    eprintln!("Available audio devices:");
+    for device in devices {
+        let rates = device
+            .sample_rates
+            .iter()
+            .map(|&(min, max)| match min == max {
+                true => format!("{min}Hz"),
+                false => format!("{min}-{max}Hz"),
+            })
+            .collect::<Vec<_>>();
+        match device.default {
+            true => println!("- {} ({}) (default)", device.name, rates.join(", ")),
+            false => println!("- {} ({})", device.name, rates.join(", ")),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// The language whisper transcribes in, english unless `--language` is
+/// given, auto detection when restricted to `--languages`
+fn transcription_language(
+    language: Option<String>,
+    languages: &[String],
+) -> Result<String, &'static str> {
+    match (language, languages.is_empty()) {
+        (Some(language), true) => Ok(language),
+        (None, true) => Ok("en".to_string()),
+        (None, false) => Ok("auto".to_string()),
+        (Some(language), false) if language == "auto" => Ok(language),
+        (Some(_), false) => Err("--languages only works with --language auto"),
+    }
+}
 
+fn whisper(mut args: Args) -> ExitCode {
+    let mode = args
+        .mode
+        .take()
+        .unwrap_or_else(|| Mode::Listen(MicArgs::default()));
+    // clap only checks conflicts between options of the same subcommand
+    let unsupported = match &mode {
+        Mode::Listen(_) | Mode::Serve { .. } => Vec::new(),
+        Mode::Transcribe { .. } => vec![
+            ("--bench", args.bench.is_some()),
+            ("--daemon", args.daemon),
+            ("--push-to-talk", args.push_to_talk.is_some()),
+            ("--tag-devices", args.tag_devices),
+        ],
+        Mode::Devices { .. } => vec![("--daemon", args.daemon), ("--tui", args.tui)],
+    };
+    if let Some((option, _)) = unsupported.iter().find(|(_, given)| *given) {
+        eprintln!("error: {option} can't be used with `{}`", mode.name());
+        return ExitCode::from(2);
+    }
+    let (mic, file, websocket) = match mode {
+        Mode::Devices { host } => return list_devices(host.host.as_deref(), args.format),
+        Mode::Listen(mic) => (mic, None, None),
+        Mode::Transcribe { file, stdin_format } => {
+            (MicArgs::default(), Some((file, stdin_format)), None)
+        }
+        Mode::Serve { addr, mic } => (mic, None, Some(addr)),
+    };
+    let host = vad::pick_host(mic.host.host.as_deref());
+
+    if args.format == OutputFormat::Json {
+        eprintln!("error: --format json only works with `devices`, transcripts use --format jsonl");
+        process::exit(1);
+    }
+
+    // the local offset can only be determined safely while we are single threaded
+    let local_offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+
+    let language =
+        transcription_language(args.language.take(), &args.languages).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        });
+    if args.control_stdin
+        && file
+            .as_ref()
+            .is_some_and(|(file, _)| file.as_os_str() == "-")
+    {
+        eprintln!("error: --control-stdin can't be used while audio is read from stdin");
+        process::exit(1);
+    }
+    if args.detect_window_ms.is_some() && language != "auto" {
+        eprintln!("error: --detect-window-ms only works with --language auto");
+        process::exit(1);
+    }
     let whisper_opts = WhisperOptions {
-        translate_en: false,
-        language: "en".to_string(),
-    };
-    let mut whisper =
-        Whisper::with_options(&args.model, whisper_opts).expect("should be able to load whisper");
-    let host = cpal::default_host(); // TODO add mic selection
-    let mic = host.default_input_device().expect("no mic");
-    let (mic, config) = vad::get_microphone_by_name(
-        &args
-            .device
-            .unwrap_or_else(|| mic.name().expect("default device should have a name")),
+        translate: args.translate,
+        language: language.clone(),
+        languages: args.languages.clone(),
+        detect_window: args.detect_window_ms.map(Duration::from_millis),
+        redetect_every: args.redetect_every,
+        best_of: args.best_of,
+        beam_size: args.beam_size,
+        threads: args.threads,
+        initial_prompt: args.initial_prompt.clone(),
+        carry_context: args.carry_context,
+        temperature: args.temperature,
+        temperature_inc: args.temperature_inc,
+        entropy_threshold: args.entropy_threshold,
+        logprob_threshold: args.logprob_threshold,
+        suppress_non_speech: !args.no_suppress_nonspeech,
+        single_segment: !args.multi_segment,
+        timestamps: args.whisper_timestamps,
+        word_timestamps: args.word_timestamps,
+        audio_ctx: args.audio_ctx,
+        max_tokens: args.max_tokens,
+        max_text_ctx: args.max_text_ctx,
+        trim_silence: args.trim_silence,
+        min_audio: Duration::from_millis(args.min_audio_ms),
+        history: args.history,
+        max_window: Duration::from_secs(args.max_window_sec),
+        load_retries: args.load_retries,
+        gpu_device: (!args.cpu).then_some(args.gpu_device),
+    };
+    // subcommands skip clap's check
+    let Some(model_path) = args.model.clone() else {
+        eprintln!("error: --model is required");
+        return ExitCode::from(2);
+    };
+    if matches!(args.vad, VadMode::Silero) && args.silero_model.is_none() {
+        eprintln!("error: --vad silero needs --silero-model");
+        return ExitCode::from(2);
+    }
+    let resolve = |model: &PathBuf| {
+        download::resolve(model).unwrap_or_else(|err| {
+            eprintln!("error: could not download '{}': {err}", model.display());
+            process::exit(1);
+        })
+    };
+    let model_path = resolve(&model_path);
+    let draft_path = args.draft_model.as_ref().map(resolve);
+    // forking only works before any threads exist, the model sets up some
+    if args.daemon {
+        if let Err(err) = daemon::detach() {
+            eprintln!("error: could not move to the background: {err}");
+            process::exit(1);
+        }
+    }
+    // with a draft model the segments are transcribed by it first
+    let first_model = draft_path.as_ref().unwrap_or(&model_path);
+    let max_frame = whisper_opts.max_frame();
+    let (format, warnings) = match args.events {
+        true => (OutputFormat::Jsonl, WarningSink::json()),
+        false => (args.format, WarningSink::Stderr),
+    };
+    let stdout_sink = args
+        .sink
+        .iter()
+        .any(|sink| matches!(sink, SinkSpec::Stdout));
+    if stdout_sink && (format != OutputFormat::Text || args.tui) {
+        eprintln!(
+            "error: --sink stdout only works with text output, not with --events, --tui or \
+             another --format"
+        );
+        process::exit(1);
+    }
+    // the tui shows transcripts itself, a stdout sink replaces the lines
+    let text_output = format == OutputFormat::Text && !args.tui && !stdout_sink;
+    let vad_opts = VadOptions {
+        mode: args.vad,
+        silero_model: args.silero_model.clone(),
+        language: language.clone(),
+        end_silence: args.silence_ms.map(Duration::from_millis),
+        tail: args.tail_ms.map(Duration::from_millis),
+        overlap: Duration::from_millis(args.overlap_ms),
+        idle_interval: args.idle_interval.map(Duration::from_secs),
+        max_samples: Some(max_frame),
+        onset_lookahead: Duration::from_millis(args.onset_lookahead_ms),
+        min_speech: Duration::from_millis(args.vad_min_speech_ms),
+        warnings: warnings.clone(),
+    };
+    if let Some(file) = args.bench {
+        let mut whisper = Whisper::with_options(first_model, whisper_opts).unwrap_or_else(|err| {
+            eprintln!("error: could not load '{}': {err}", first_model.display());
+            process::exit(1);
+        });
+        return match bench::run(&file, &mut whisper, vad_opts, args.print) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => err.report(),
+        };
+    }
+    // segments, answers of the inference thread and the end of the audio all
+    // wake up the main loop
+    let (steps_tx, steps_rx) = mpsc::sync_channel(SEGMENT_QUEUE);
+    let binary = args.whisper_cpp.clone().map(|path| Binary {
+        path,
+        timeout: args.whisper_cpp_timeout_ms.map(Duration::from_millis),
+        warnings: warnings.clone(),
+    });
+    let mut inference = Inference::spawn(
+        first_model.clone(),
+        whisper_opts.clone(),
+        args.adaptive,
+        binary,
+        steps_tx.clone(),
     )
-    .expect("should be able to get default mic");
-    eprintln!("using audio: '{}'", mic.name().unwrap());
-    let ring = HeapRb::<i16>::try_new(MAX_WHISPER_FRAME * 2).expect("cannot allocate audio ring");
-    let (mut producer, mut consumer) = ring.split();
+    .unwrap_or_else(|err| {
+        eprintln!("error: could not load '{}': {err}", first_model.display());
+        process::exit(1);
+    });
+    // files don't need a microphone
+    let devices = match file {
+        Some(_) => Vec::new(),
+        None => vad::resolve_input_devices(mic.device, || vad::default_input_device_name(&host))
+            .unwrap_or_else(|err| {
+                eprintln!("error: {err}");
+                process::exit(1);
+            }),
+    };
+    let mut mics = devices
+        .iter()
+        .map(|device| {
+            let (mic, config) = vad::get_microphone_by_name(&host, device).unwrap_or_else(|err| {
+                eprintln!("error: {err}");
+                process::exit(1);
+            });
+            eprintln!("using audio: '{device}'");
+            (mic, config)
+        })
+        .collect::<Vec<_>>();
+    // tagged devices get a detector and ring each, mixed devices share one
+    let sources = match args.tag_devices {
+        true => mics.len(),
+        false => 1,
+    };
+    let ring_capacity = args.ring_seconds * SAMPLE_RATE;
+    if args.verbose {
+        eprintln!(
+            "transcription ring holds {}s ({ring_capacity} samples)",
+            args.ring_seconds
+        );
+    }
+    let mut producers = Vec::with_capacity(sources);
+    let mut consumers = Vec::with_capacity(sources);
+    let mut vads = Vec::with_capacity(sources);
+    let configs = match file {
+        // the file thread resamples to 16kHz mono
+        Some(_) => vec![StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(SAMPLE_RATE as u32),
+            buffer_size: BufferSize::Default,
+        }],
+        None => mics.iter().map(|(_, config)| config.clone()).collect(),
+    };
+    for (source, config) in configs.iter().take(sources).enumerate() {
+        let ring = HeapRb::<i16>::try_new(ring_capacity).unwrap_or_else(|_| {
+            eprintln!("error: cannot allocate a {}s audio ring", args.ring_seconds);
+            process::exit(1);
+        });
+        let (producer, consumer) = ring.split();
+        producers.push(producer);
+        consumers.push(consumer);
+        let mut vad = Vad::try_new(config, vad_opts.clone()).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        });
+        vad.set_source(source);
+        vads.push(vad);
+    }
     let (mut activity_tx, activity_rx) = mpsc::channel::<VadActivity>();
-    let mut vad = Vad::try_new(&config).expect("should be able to build vad");
-    let _handle = if let Some(file) = args.file {
-        let (header, waveform) =
-            wav_io::read_from_file(File::open(file).expect("file doesnt exist"))
-                .expect("invalid wav file");
-        let buf_size = (header.sample_rate / 30) * header.channels as u32;
-        let handle = thread::spawn(move || {
-            let resample_with = get_resampler(header.sample_rate);
-            if header.channels == 2 {
-                eprintln!("converting stereo to mono audio");
-            }
-            for chunk in waveform.chunks(buf_size as usize) {
-                let now = Instant::now();
-                let timeout =
-                    Duration::from_millis((chunk.len() as u64 * 1000) / header.sample_rate as u64);
-                vad::audio_loop(
-                    chunk,
-                    header.channels,
-                    &resample_with,
-                    &mut producer,
-                    &mut vad,
-                    &mut activity_tx,
+    if args.dry_run {
+        let config = match args.no_config {
+            true => None,
+            false => args.config.clone().or_else(config::default_path),
+        };
+        if let Some(config) = config.filter(|config| config.exists()) {
+            println!("config:         {}", config.display());
+        }
+        for (device, (_, config)) in devices.iter().zip(&mics) {
+            let buffer_size = match config.buffer_size {
+                BufferSize::Fixed(frames) => format!("{frames} frames"),
+                BufferSize::Default => "host default".to_string(),
+            };
+            let resampling = match config.sample_rate.0 as usize {
+                SAMPLE_RATE => "no".to_string(),
+                rate => format!("{rate}Hz -> {SAMPLE_RATE}Hz"),
+            };
+            println!("device:         {device}");
+            println!("sample rate:    {}Hz", config.sample_rate.0);
+            println!("channels:       {}", config.channels);
+            println!("buffer size:    {buffer_size}");
+            println!("resampling:     {resampling}");
+        }
+        if mics.len() > 1 {
+            let routing = match args.tag_devices {
+                true => "tagged",
+                false => "mixed",
+            };
+            println!("devices:        {routing}");
+        }
+        if let Some((file, _)) = &file {
+            println!("input file:     {}", file.display());
+        }
+        println!("vad:            {:?}", args.vad);
+        println!("end silence:    {:?}", vads[0].end_silence());
+        println!("recorded tail:  {:?}", vads[0].tail());
+        println!("overlap:        {}ms", args.overlap_ms);
+        println!("lookahead:      {}ms", args.onset_lookahead_ms);
+        println!("min speech:     {}ms", args.vad_min_speech_ms);
+        println!("model:          {}", model_path.display());
+        if let Some(draft) = &draft_path {
+            println!("draft model:    {}", draft.display());
+        }
+        if let Some(bin) = &args.whisper_cpp {
+            println!("whisper.cpp:    {}", bin.display());
+        }
+        println!("language:       {language}");
+        if !args.languages.is_empty() {
+            println!("allowed:        {}", args.languages.join(","));
+        }
+        if let Some(window) = args.detect_window_ms {
+            println!("detect window:  {window}ms");
+        }
+        println!("translate:      {:?}", args.translate);
+        println!("best of:        {}", args.best_of);
+        if let Some(beam_size) = args.beam_size {
+            println!("beam size:      {beam_size}");
+        }
+        println!("threads:        {}", args.threads);
+        if args.carry_context > 0 {
+            println!("carry context:  {} tokens", args.carry_context);
+        }
+        println!(
+            "temperature:    {} (+{})",
+            args.temperature, args.temperature_inc
+        );
+        let non_speech = match args.no_suppress_nonspeech {
+            true => "kept",
+            false => "suppressed",
+        };
+        println!("non speech:     {non_speech}");
+        println!("single segment: {}", !args.multi_segment);
+        println!("timestamps:     {}", args.whisper_timestamps);
+        if let Some(audio_ctx) = args.audio_ctx {
+            println!("audio context:  {audio_ctx}");
+        }
+        if let Some(max_tokens) = args.max_tokens {
+            println!("max tokens:     {max_tokens}");
+        }
+        if let Some(max_text_ctx) = args.max_text_ctx {
+            println!("max text ctx:   {max_text_ctx}");
+        }
+        println!("trim silence:   {}", args.trim_silence);
+        return ExitCode::SUCCESS;
+    }
+    let mut tui = args.tui.then(|| {
+        Tui::new(sources).unwrap_or_else(|err| {
+            eprintln!("error: could not start the tui: {err}");
+            process::exit(1);
+        })
+    });
+    if let Some(tui) = &tui {
+        for (source, vad) in vads.iter_mut().enumerate() {
+            vad.set_activity_callback(tui.vad_state(source));
+        }
+        vads[0].add_input_tap(tui.level_tap());
+    }
+    // structured consumers get onsets as `speech_start` events instead
+    if text_output && !args.quiet {
+        for vad in &mut vads {
+            // report onsets right away instead of after the previous transcription finished
+            vad.set_activity_callback(|activity| {
+                if let VadActivity::SpeechStart { .. } = activity {
+                    eprintln!("speech started");
+                }
+            });
+        }
+    }
+    if args.vad_debug {
+        for (source, vad) in vads.iter_mut().enumerate() {
+            vad.set_frame_callback(move |trace| {
+                eprintln!(
+                    "vad {source} #{:<6} {} {:>3} {}",
+                    trace.index,
+                    if trace.is_speech { 'S' } else { '.' },
+                    trace.silence_frames,
+                    if trace.in_speech { '>' } else { ' ' },
                 );
-                let delta = Instant::now() - now;
-                thread::sleep(timeout - delta);
+            });
+        }
+    }
+    // taps are only supported on a single detector, see `--tag-devices`
+    let recorder = match args.record {
+        Some(path) => match Recorder::create(&path) {
+            Ok(recorder) => {
+                vads[0].add_input_tap(recorder.sender());
+                Some(recorder)
+            }
+            Err(source) => return PipelineError::Recording { path, source }.report(),
+        },
+        None => None,
+    };
+    // set while audio is discarded, push to talk starts out not listening
+    let paused = Arc::new(AtomicBool::new(args.push_to_talk.is_some()));
+    if args.hotkeys || args.push_to_talk.is_some() || args.daemon {
+        for vad in &mut vads {
+            vad.set_pause_switch(paused.clone());
+        }
+    }
+    match &args.push_to_talk {
+        Some(Some(key)) => {
+            let Some(code) = evdev::key_code(key) else {
+                eprintln!("error: unknown push to talk key '{key}'");
+                process::exit(1);
+            };
+            if let Err(err) = evdev::hold_to_listen(code, paused.clone()) {
+                eprintln!("error: could not set up push to talk: {err}");
+                process::exit(1);
+            }
+            // the key decides what is speech
+            for vad in &mut vads {
+                vad.set_detector(AlwaysSpeech);
+            }
+            eprintln!("not listening, hold '{key}' to talk");
+        }
+        Some(None) => {
+            if let Err(err) = gate::toggle_on_signal(paused.clone()) {
+                eprintln!("error: could not set up push to talk: {err}");
+                process::exit(1);
+            }
+            eprintln!(
+                "not listening, `kill -USR1 {}` starts or stops listening",
+                process::id()
+            );
+        }
+        None => (),
+    }
+    // set on Ctrl-C, the audio side then stops and flushes open speech
+    let stopping = Arc::new(AtomicBool::new(false));
+    if let Err(err) = interrupt::stop_on_interrupt(stopping.clone()) {
+        eprintln!("warning: Ctrl-C can't flush speech in progress ({err})");
+    }
+    // device name `status` reports on the control socket, follows device switches
+    let device_name = Arc::new(Mutex::new(devices.join(", ")));
+    let (switch_requests, switches) = match args.daemon && mics.len() == 1 {
+        true => {
+            let (switch_requests, switches) = mpsc::channel();
+            (Some(switch_requests), Some(switches))
+        }
+        false => (None, None),
+    };
+    let _socket = args.daemon.then(|| {
+        let path = args.socket.clone().unwrap_or_else(daemon::default_socket);
+        let controls = Controls {
+            paused: paused.clone(),
+            stopping: stopping.clone(),
+            device: device_name.clone(),
+            switch_device: switch_requests,
+        };
+        ControlSocket::bind(&path, controls).unwrap_or_else(|err| {
+            eprintln!("error: could not listen on '{}': {err}", path.display());
+            process::exit(1);
+        })
+    });
+    let _hotkeys = match args.hotkeys {
+        true => match Hotkeys::listen(paused.clone()) {
+            Ok(hotkeys) => {
+                eprintln!("press space to pause or resume transcribing");
+                Some(hotkeys)
+            }
+            Err(err) => {
+                eprintln!("warning: hotkeys need a terminal ({err}), continuing without");
+                None
+            }
+        },
+        false => None,
+    };
+    let _monitor = args.monitor_device.map(|name| {
+        let monitor = Monitor::open(&host, &name).unwrap_or_else(|err| {
+            eprintln!("error: {err}");
+            process::exit(1);
+        });
+        vads[0].add_input_tap(monitor.sender());
+        monitor
+    });
+    let mut vtt = match args.output_vtt {
+        Some(path) => match SubtitleWriter::create(&path, SubtitleFormat::Vtt) {
+            Ok(vtt) => Some(vtt),
+            Err(source) => return PipelineError::Subtitles { path, source }.report(),
+        },
+        None => None,
+    };
+    let mut subtitles = match format {
+        OutputFormat::Srt => Some(SubtitleFormat::Srt),
+        OutputFormat::Vtt => Some(SubtitleFormat::Vtt),
+        OutputFormat::Text | OutputFormat::Jsonl | OutputFormat::Json => None,
+    }
+    .map(|subtitles| {
+        SubtitleWriter::new(io::stdout(), subtitles).expect("could not write to stdout")
+    });
+    // short segments are only merged with following ones of the same device
+    let mut mergers = args.merge_short.then(|| {
+        (0..sources)
+            .map(|_| {
+                ShortSegmentMerger::new(
+                    args.min_audio_ms as usize * SAMPLE_RATE / 1000,
+                    max_frame,
+                    Duration::from_millis(args.merge_gap_ms),
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    let started = OffsetDateTime::now_utc().to_offset(local_offset);
+    // buffered so `--no-flush` can batch output, flushed after every segment
+    // otherwise
+    let stdout: SharedStdout = Rc::new(RefCell::new(BufWriter::new(io::stdout())));
+    let mut sinks = Sinks::default();
+    if args.clipboard {
+        let clipboard = Clipboard::new(
+            args.clipboard_backend
+                .unwrap_or_else(ClipboardBackend::detect),
+            args.clipboard_mode,
+        );
+        eprintln!("copying transcripts with {:?}", clipboard.backend());
+        sinks.push("clipboard", Box::new(clipboard));
+    }
+    if args.type_text {
+        let typist = Typist::new(
+            args.type_backend.unwrap_or_else(TypeBackend::detect),
+            args.type_suffix,
+        );
+        eprintln!("typing transcripts with {:?}", typist.backend());
+        sinks.push("type", Box::new(typist));
+    }
+    for spec in args.sink {
+        let kind = spec.kind();
+        let sink = spec.open(&stdout).unwrap_or_else(|err| {
+            eprintln!("error: could not set up the {kind} sink: {err}");
+            process::exit(1);
+        });
+        sinks.push(kind, sink);
+    }
+    let mut session_log = match args.session_dir {
+        Some(dir) => match SessionLog::create(&dir, started) {
+            Ok(log) => {
+                eprintln!("logging transcripts to '{}'", log.path().display());
+                Some(log)
             }
+            Err(source) => return PipelineError::SessionLog { dir, source }.report(),
+        },
+        None => None,
+    };
+    if let Some(addr) = &args.metrics {
+        metrics::serve(addr).unwrap_or_else(|err| {
+            eprintln!("error: could not listen on '{addr}': {err}");
+            process::exit(1);
         });
+    }
+    let tcp = args.tcp.map(|addr| {
+        TcpBroadcaster::bind(&addr).unwrap_or_else(|err| {
+            eprintln!("error: could not listen on '{addr}': {err}");
+            process::exit(1);
+        })
+    });
+    let websocket = websocket.map(|addr| {
+        WebSocketBroadcaster::bind(&addr).unwrap_or_else(|err| {
+            eprintln!("error: could not listen on '{addr}': {err}");
+            process::exit(1);
+        })
+    });
+    let color = args.color.enabled();
+    let auto_language = language == "auto";
+    let clock = match file {
+        Some(_) => Clock::File,
+        None => Clock::Live(started),
+    };
+    let handle = if let Some((file, format)) = file {
+        let vad = vads.pop().expect("files are read by a single detector");
+        let producer = producers
+            .pop()
+            .expect("files are read by a single detector");
+        let quiet = args.quiet;
+        let handle = match file.as_os_str() == "-" {
+            true => {
+                let stopping = stopping.clone();
+                let warnings = warnings.clone();
+                thread::spawn(move || {
+                    let mut feed = AudioFeed::manual(
+                        format.sample_rate,
+                        vad,
+                        producer,
+                        activity_tx,
+                        warnings.clone(),
+                    )?;
+                    if !quiet {
+                        feed.set_clip_detector(ClipDetector::new(warnings));
+                    }
+                    // audio piped faster than real time is slowed down like files
+                    let started = Instant::now();
+                    let mut samples = 0;
+                    let read = raw::read(io::stdin().lock(), format, |chunk| {
+                        if stopping.load(Ordering::Relaxed) {
+                            return ControlFlow::Break(());
+                        }
+                        feed.push_f32(chunk, format.channels);
+                        samples += chunk.len();
+                        let due = started
+                            + Duration::from_secs_f64(
+                                samples as f64 / format.channels as f64 / format.sample_rate as f64,
+                            );
+                        thread::sleep(due.saturating_duration_since(Instant::now()));
+                        ControlFlow::Continue(())
+                    });
+                    // speech read up to the error still gets transcribed
+                    feed.finish();
+                    read.map_err(|err| AudioError::UnreadableInput(format!("stdin: {err}")))
+                })
+            }
+            false => {
+                let (header, waveform) = File::open(&file)
+                    .map_err(|err| err.to_string())
+                    .and_then(|wav| wav_io::read_from_file(wav).map_err(|err| err.to_string()))
+                    .unwrap_or_else(|err| {
+                        eprintln!("error: could not read '{}': {err}", file.display());
+                        process::exit(1);
+                    });
+                let buf_size = (header.sample_rate / 30) * header.channels as u32;
+                let stopping = stopping.clone();
+                let warnings = warnings.clone();
+                thread::spawn(move || {
+                    let mut feed = AudioFeed::manual(
+                        header.sample_rate,
+                        vad,
+                        producer,
+                        activity_tx,
+                        warnings.clone(),
+                    )?;
+                    if !quiet {
+                        feed.set_clip_detector(ClipDetector::new(warnings));
+                    }
+                    for chunk in waveform.chunks(buf_size as usize) {
+                        if stopping.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let now = Instant::now();
+                        let timeout = Duration::from_millis(
+                            (chunk.len() as u64 * 1000) / header.sample_rate as u64,
+                        );
+                        feed.push_f32(chunk, header.channels);
+                        let delta = Instant::now() - now;
+                        thread::sleep(timeout - delta);
+                    }
+                    feed.finish();
+                    Ok(())
+                })
+            }
+        };
         StreamHandle::Thread(handle)
     } else {
+        let mut clipping = mics
+            .iter()
+            .map(|_| (!args.quiet).then(|| ClipDetector::new(warnings.clone())))
+            .collect::<Vec<_>>();
+        let (input_queue, on_overflow) = (args.input_queue, args.on_overflow);
+        let tag_devices = args.tag_devices;
+        let stopping = stopping.clone();
+        let host_name = mic.host.host.clone();
+        let device_name = device_name.clone();
+        let warnings = warnings.clone();
         let handle = thread::spawn(move || {
-            let resample_with = get_resampler(config.sample_rate.0);
-            if config.channels == 2 {
-                eprintln!("converting stereo to mono audio");
-            }
-            let (audio_tx, audio_rx) = mpsc::sync_channel(10);
-            let stream = mic
-                .build_input_stream(
-                    &config,
-                    move |data: &[f32], _info| {
-                        if audio_tx.try_send(data.to_vec()).is_err() {
-                            eprintln!("audio is being dropped");
-                        }
-                    },
-                    move |err| {
-                        eprintln!("error: {err}");
-                    },
-                    None,
-                )
-                .expect("config should be able to work");
-            stream.play().expect("could not listen to microphone");
-            while let Ok(data) = audio_rx.recv() {
-                vad::audio_loop(
-                    &data,
-                    config.channels,
-                    &resample_with,
-                    &mut producer,
-                    &mut vad,
-                    &mut activity_tx,
-                );
+            let configs = mics
+                .iter()
+                .map(|(_, config)| config.clone())
+                .collect::<Vec<_>>();
+            for config in &configs {
+                if config.channels > 1 {
+                    eprintln!("converting {} channel audio to mono", config.channels);
+                }
             }
+            let mut mixer = match !tag_devices && configs.len() > 1 {
+                true => Some(Mixer::new(&configs, warnings.clone())?),
+                false => None,
+            };
+            // without mixing every device is converted on its own
+            let mut inputs = match mixer {
+                Some(_) => Vec::new(),
+                None => configs
+                    .iter()
+                    .map(|config| {
+                        Ok((
+                            Downmixer::new(config.channels),
+                            get_resampler(config.sample_rate.0, warnings.clone())?,
+                        ))
+                    })
+                    .collect::<Result<_, AudioError>>()?,
+            };
+            // every device gets its share of the queue
+            let (audio_tx, audio_rx) =
+                queue::bounded(usize::from(input_queue) * mics.len(), on_overflow);
+            let audio_tx = Arc::new(audio_tx);
+            let mut streams = mics
+                .iter()
+                .enumerate()
+                .map(|(device, (mic, config))| {
+                    play_stream(mic, config, device, &audio_tx, warnings.clone())
+                        .map_err(AudioError::StreamFailed)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            // wakes up now and then so a stalled device doesn't keep it from
+            // stopping
+            while !stopping.load(Ordering::Relaxed) {
+                let (device, data) = match audio_rx.recv_timeout(STOP_POLL_INTERVAL) {
+                    Ok(item) => item,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if let Some((name, done)) = switches.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                    let switched = switch_device(
+                        &vad::pick_host(host_name.as_deref()),
+                        &name,
+                        &mut mics[0],
+                        &mut streams,
+                        &audio_tx,
+                        &audio_rx,
+                        warnings.clone(),
+                    );
+                    if switched.is_ok() {
+                        // speech heard on the old device ends with it
+                        vad::end_speech(&mut vads[0], &mut activity_tx);
+                        let config = &mics[0].1;
+                        inputs[0] = (
+                            Downmixer::new(config.channels),
+                            get_resampler(config.sample_rate.0, warnings.clone())?,
+                        );
+                        eprintln!("switched to audio: '{name}'");
+                        *device_name.lock().unwrap() = name;
+                    }
+                    let _ = done.send(switched);
+                    // neither device could be opened, nothing is left to listen to
+                    if streams.is_empty() {
+                        vad::end_stream(&mut vads[0], &mut activity_tx);
+                        return Err(AudioError::StreamFailed(
+                            "the previous device could not be opened again".to_string(),
+                        ));
+                    }
+                    continue;
+                }
+                metrics::METRICS.set_queue_depth(audio_rx.queued());
+                let Some(mixer) = mixer.as_mut() else {
+                    let (downmix, resample_with) = &mut inputs[device];
+                    vad::audio_loop(
+                        &data,
+                        downmix,
+                        resample_with,
+                        &mut producers[device],
+                        &mut vads[device],
+                        &mut activity_tx,
+                        clipping[device].as_mut(),
+                    );
+                    continue;
+                };
+                if let Some(clipping) = clipping[device].as_mut() {
+                    clipping.check(&data);
+                }
+                mixer.push(device, &data);
+                let mixed = wav_io::convert_samples_f32_to_i16(&mixer.mix());
+                vad::detect(&mixed, &mut producers[0], &mut vads[0], &mut activity_tx);
+            }
+            drop(streams);
+            // whatever was said up to here still gets transcribed before the
+            // end of the stream stops the transcription loop
+            let (last, others) = vads
+                .split_last_mut()
+                .expect("there is a detector per source");
+            for vad in others {
+                vad::end_speech(vad, &mut activity_tx);
+            }
+            vad::end_stream(last, &mut activity_tx);
+            Ok(())
         });
         StreamHandle::Thread(handle)
     };
 
-    while let Ok(event) = activity_rx.recv() {
-        match event {
-            VadActivity::SpeechStart => eprintln!("speech started"),
-            VadActivity::SpeechEnd(samples) => {
-                let now = Instant::now();
-                match args.whisper_cpp.clone() {
-                    Some(bin) => {
-                        let mut buf = vec![0; samples];
-                        if consumer.pop_slice(&mut buf) != samples {
+    // text of the last segment per source, used to remove repeated words after
+    // a split
+    let mut previous_text: Vec<Option<String>> = vec![None; sources];
+    let mut refiner = draft_path.is_some().then(|| {
+        Refiner::spawn(model_path.clone(), whisper_opts).unwrap_or_else(|err| {
+            eprintln!("error: could not load '{}': {err}", model_path.display());
+            process::exit(1);
+        })
+    });
+    let refined_model = model_path.display().to_string();
+    let mut model = draft_path.unwrap_or(model_path);
+    let control = args.control_stdin.then(control::read_stdin);
+    let mut speech_deadline = args
+        .timeout_ms
+        .map(|timeout| Instant::now() + Duration::from_millis(timeout));
+    let mut transcribed = false;
+    // for the summary at the end of a file
+    let mut segments = 0;
+    let wall_clock = Instant::now();
+    // end of the last transcribed segment, for `--paragraph-gap-ms`
+    let mut last_end: Option<Duration> = None;
+    let paragraph_gap = args.paragraph_gap_ms.map(Duration::from_millis);
+    // segments seen so far, for `--skip-first`
+    let mut detected = 0;
+    let warmup = Duration::from_millis(args.warmup_skip_ms);
+    // id of the utterance each source is in the middle of, shared by its
+    // partial and final events
+    let mut segment_ids: Vec<Option<u64>> = vec![None; sources];
+    let mut next_segment_id = 0;
+    let partial_interval = args.partial_ms.map(Duration::from_millis);
+    let mut next_partial: Option<Instant> = None;
+    let partial_line = RefCell::new(PartialLine::for_stdout());
+    // from here on stderr ends up in the tui's log
+    if let Some(tui) = tui.as_mut() {
+        if let Err(err) = tui.start(paused.clone()) {
+            eprintln!("error: could not start the tui: {err}");
+            process::exit(1);
+        }
+    }
+    let mut wake = args.wake_word.as_deref().map(|phrase| {
+        eprintln!("say '{phrase}' to start transcribing");
+        WakeWord::new(phrase, Duration::from_millis(args.wake_timeout_ms))
+            .expect("checked by parse_wake_word")
+    });
+    // sends `event` to `--tcp` and `serve` clients and the jsonl output
+    let send_event = |event: &serde_json::Value| {
+        if let Some(tcp) = &tcp {
+            tcp.broadcast(&event.to_string());
+        }
+        if let Some(websocket) = &websocket {
+            websocket.broadcast(&event.to_string());
+        }
+        if format == OutputFormat::Jsonl {
+            writeln!(stdout.borrow_mut(), "{event}").expect("could not write to stdout");
+        }
+    };
+    let fall_asleep = || {
+        eprintln!("[asleep]");
+        send_event(&json!({ "type": "sleep" }));
+    };
+    // replaces a draft with the refining model's transcript
+    let send_revision = |revision: Revision| {
+        // without text the draft stays
+        let Some(text) = revision.text else {
+            return;
+        };
+        let label = args
+            .tag_devices
+            .then(|| devices[revision.segment.source].as_str());
+        let mut event = json!({
+            "type": "final",
+            "revised": true,
+            "segment_id": revision.segment_id,
+            "text": text,
+            "draft": revision.draft,
+            "language": revision.language,
+            "start": revision.segment.start_time().as_secs_f64(),
+            "end": revision.segment.end_time().as_secs_f64(),
+            "latency_ms": revision.latency.as_millis() as u64,
+            "model": refined_model,
+        });
+        if let Some(label) = label {
+            event["device"] = json!(label);
+        }
+        send_event(&event);
+        let mut stdout = stdout.borrow_mut();
+        // lines can't be taken back, unchanged transcripts aren't repeated
+        if let Some(tui) = &tui {
+            tui.revise(revision.segment_id, &text);
+        }
+        if text_output && revision.draft.as_ref() != Some(&text) {
+            partial_line
+                .borrow_mut()
+                .clear(&mut *stdout)
+                .expect("could not write to stdout");
+            match label {
+                Some(label) => writeln!(stdout, "(revised) [{label}] {text}"),
+                None => writeln!(stdout, "(revised) {text}"),
+            }
+            .expect("could not write to stdout");
+        }
+        if !args.no_flush {
+            stdout.flush().expect("could not write to stdout");
+        }
+    };
+    // finished segments are popped off the rings on their own thread, so the
+    // vad keeps finding room in them while whisper is busy
+    // a segment stays in its ring until there's room for it in the segment
+    // queue, so a lagging whisper fills the rings instead of memory
+    let rings = Arc::new(consumers.into_iter().map(Mutex::new).collect::<Vec<_>>());
+    let slots = SegmentSlots::new(args.segment_queue.into());
+    thread::spawn({
+        let rings = rings.clone();
+        let on_segment_overflow = args.on_segment_overflow;
+        let warnings = warnings.clone();
+        move || {
+            for activity in activity_rx {
+                let (samples, slot) = match &activity {
+                    VadActivity::SpeechEnd(segment) => {
+                        let slot = match on_segment_overflow {
+                            SegmentOverflow::Wait => slots.acquire(),
+                            SegmentOverflow::Drop => match slots.try_acquire() {
+                                Some(slot) => slot,
+                                None => {
+                                    rings[segment.source].lock().unwrap().skip(segment.samples);
+                                    warnings.emit(Warning::SegmentDropped {
+                                        samples: segment.samples,
+                                    });
+                                    continue;
+                                }
+                            },
+                        };
+                        let mut samples = vec![0; segment.samples];
+                        let mut ring = rings[segment.source].lock().unwrap();
+                        if ring.pop_slice(&mut samples) != segment.samples {
                             panic!("logic error: not enough samples could be fetched");
                         }
-                        decode_bin(args.model.clone(), bin, &buf)
+                        (samples, Some(slot))
                     }
-                    None => {
-                        if consumer.pop_slice(whisper.audio_buf(samples)) != samples {
-                            panic!("logic error: not enough samples could be fetched");
+                    _ => (Vec::new(), None),
+                };
+                if steps_tx
+                    .send(Step::Activity(activity, samples, slot))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            // the inference thread holds a sender too, so the channel
+            // doesn't disconnect
+            let _ = steps_tx.send(Step::Closed);
+        }
+    });
+    // segments held back by `--merge-short` when the stream ended, handled
+    // before the end itself
+    let mut flushed = VecDeque::new();
+    let mut flushing = false;
+    // jobs of the inference thread that wait for their answer
+    let mut queued: HashMap<u64, Queued> = HashMap::new();
+    // the end of the audio waits for the answers still to come
+    let mut ending = None;
+    // ends the run once everything heard so far is written
+    let mut failure = None;
+    let write_warnings = || {
+        let WarningSink::Json(pending) = &warnings else {
+            return;
+        };
+        let pending = pending.take();
+        if pending.is_empty() {
+            return;
+        }
+        let mut stdout = stdout.borrow_mut();
+        for warning in pending {
+            writeln!(stdout, "{}", warning.to_json()).expect("could not write to stdout");
+        }
+        if !args.no_flush {
+            stdout.flush().expect("could not write to stdout");
+        }
+    };
+    let print_line =
+        |timestamp: &Option<String>, label: Option<&str>, paragraph: bool, text: &str| {
+            if !text_output {
+                return;
+            }
+            let mut stdout = stdout.borrow_mut();
+            partial_line
+                .borrow_mut()
+                .clear(&mut *stdout)
+                .expect("could not write to stdout");
+            if paragraph {
+                writeln!(stdout).expect("could not write to stdout");
+            }
+            let lines = match args.split_sentences {
+                true => output::split_sentences(text),
+                false => vec![text],
+            };
+            for line in lines {
+                let line = match label {
+                    Some(label) => format!("[{label}] {line}"),
+                    None => line.to_string(),
+                };
+                match timestamp {
+                    Some(timestamp) => writeln!(stdout, "{timestamp} {line}"),
+                    None => writeln!(stdout, "{line}"),
+                }
+                .expect("could not write to stdout");
+            }
+            if !args.no_flush {
+                stdout.flush().expect("could not write to stdout");
+            }
+        };
+    loop {
+        write_warnings();
+        for revision in refiner.iter_mut().flat_map(Refiner::revisions) {
+            send_revision(revision);
+        }
+        // outstanding revisions are picked up while waiting for audio
+        let revision_poll = refiner
+            .as_ref()
+            .filter(|refiner| refiner.pending() > 0)
+            .map(|_| Instant::now() + REVISION_POLL_INTERVAL);
+        // so are warnings of the audio threads
+        let warning_poll = matches!(warnings, WarningSink::Json(_))
+            .then(|| Instant::now() + WARNING_POLL_INTERVAL);
+        // partials are only due while someone speaks, so after the speech
+        // deadline is gone
+        let deadline = speech_deadline
+            .or(next_partial)
+            .into_iter()
+            .chain(revision_poll)
+            .chain(warning_poll)
+            .min();
+        let step = match deadline {
+            _ if !flushed.is_empty() => flushed.pop_front().expect("checked for segments"),
+            Some(deadline) => {
+                match steps_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(step) => step,
+                    Err(RecvTimeoutError::Timeout)
+                        if speech_deadline.is_some_and(|due| due <= Instant::now()) =>
+                    {
+                        eprintln!("no speech detected in time");
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout)
+                        if speech_deadline.is_none()
+                            && next_partial.is_some_and(|due| due <= Instant::now()) =>
+                    {
+                        for (source, segment_id) in segment_ids.iter().enumerate() {
+                            let Some(segment_id) = *segment_id else {
+                                continue;
+                            };
+                            // one preview per source at a time, whisper falls
+                            // behind otherwise
+                            if queued.values().any(|queued| {
+                                matches!(queued, Queued::Partial { source: busy, .. } if *busy == source)
+                            }) {
+                                continue;
+                            }
+                            // the ring holds the utterance captured so far
+                            let samples = {
+                                let consumer = rings[source].lock().unwrap();
+                                let mut samples =
+                                    vec![0; consumer.occupied_len().min(max_frame - 1)];
+                                consumer.peek_slice(&mut samples);
+                                samples
+                            };
+                            let job = inference.submit(Job::Preview(samples));
+                            queued.insert(job, Queued::Partial { source, segment_id });
+                        }
+                        next_partial = partial_interval.map(|interval| Instant::now() + interval);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match steps_rx.recv() {
+                Ok(step) => step,
+                Err(_) => break,
+            },
+        };
+        // models are switched between segments, new audio keeps queueing up
+        // in the ring
+        for command in control.iter().flat_map(|control| control.try_iter()) {
+            match command {
+                ControlCommand::Model(path) => {
+                    let job = inference.submit(Job::Model(path.clone()));
+                    queued.insert(job, Queued::Model(path));
+                }
+                ControlCommand::Redetect => {
+                    inference.submit(Job::Redetect);
+                }
+                ControlCommand::Recent => {
+                    let job = inference.submit(Job::Recent);
+                    queued.insert(job, Queued::Recent);
+                }
+            }
+        }
+        let (event, samples, slot) = match step {
+            Step::Activity(event, samples, slot) => (event, samples, slot),
+            Step::Inference(Output {
+                job,
+                answer: Answer::Line(line),
+            }) => {
+                // whisper.cpp's lines are shown as they are printed
+                if let Some(Queued::Final(pending)) = queued.get_mut(&job) {
+                    let paragraph = pending.starts_paragraph(paragraph_gap, last_end);
+                    print_line(&pending.timestamp, pending.label, paragraph, &line);
+                }
+                continue;
+            }
+            Step::Inference(Output {
+                answer: Answer::Failed(err),
+                ..
+            }) => {
+                failure = Some(err);
+                break;
+            }
+            Step::Inference(Output { job, answer }) => {
+                // redetecting isn't answered, so everything else belongs to a
+                // queued job
+                let job = queued.remove(&job).expect("answers belong to queued jobs");
+                if queued.is_empty() {
+                    flushed.extend(ending.take());
+                }
+                match (job, answer) {
+                    (Queued::Partial { source, segment_id }, Answer::Previewed(transcription)) => {
+                        // the utterance may have ended while it was previewed
+                        if segment_ids[source] != Some(segment_id) {
+                            continue;
+                        }
+                        let Some(transcription) = transcription else {
+                            continue;
+                        };
+                        let mut event = json!({
+                            "type": "partial",
+                            "segment_id": segment_id,
+                            "text": transcription.text,
+                        });
+                        if args.tag_devices {
+                            event["device"] = json!(devices[source]);
+                        }
+                        send_event(&event);
+                        sinks.partial(&Transcript {
+                            segment_id,
+                            text: &transcription.text,
+                            device: args.tag_devices.then(|| devices[source].as_str()),
+                            event: &event,
+                        });
+                        let line = match args.tag_devices {
+                            true => format!("[{}] {}", devices[source], transcription.text),
+                            false => transcription.text,
+                        };
+                        if let Some(tui) = &tui {
+                            tui.partial(source, &line);
+                        }
+                        let mut stdout = stdout.borrow_mut();
+                        if text_output {
+                            partial_line
+                                .borrow_mut()
+                                .show(&mut *stdout, &line)
+                                .expect("could not write to stdout");
                         }
-                        if let Some(final_text) = whisper.transcribe() {
-                            println!("{final_text}");
+                        if !args.no_flush {
+                            stdout.flush().expect("could not write to stdout");
                         }
                     }
+                    (Queued::Wake(mut pending), Answer::Previewed(heard)) => {
+                        let wake = wake.as_mut().expect("only listened for with a wake word");
+                        // an earlier segment may have woken it up meanwhile
+                        if !wake.is_awake() {
+                            if !heard.is_some_and(|heard| {
+                                wake.listen(&heard.text, pending.segment.end_time())
+                            }) {
+                                continue;
+                            }
+                            pending.woke = true;
+                            eprintln!("[awake]");
+                            send_event(&json!({
+                                "type": "wake",
+                                "segment_id": pending.segment_id,
+                            }));
+                        }
+                        wake.heard(pending.segment.end_time());
+                        pending.queued = Instant::now();
+                        let job =
+                            inference.submit(Job::Transcribe(mem::take(&mut pending.samples)));
+                        queued.insert(job, Queued::Final(pending));
+                    }
+                    (Queued::Model(path), Answer::Switched(loaded)) => match loaded {
+                        Ok(()) => {
+                            eprintln!("switched to model '{}'", path.display());
+                            model = path;
+                        }
+                        Err(err) => eprintln!(
+                            "error: could not load '{}': {err}, keeping '{}'",
+                            path.display(),
+                            model.display()
+                        ),
+                    },
+                    (Queued::Recent, Answer::Recent(transcriptions)) => {
+                        for transcription in transcriptions {
+                            eprintln!("recent: {}", transcription.text);
+                        }
+                    }
+                    (Queued::Final(mut pending), answer) => {
+                        let mut detected_language = None;
+                        // tokens and decode time, unknown for whisper.cpp binaries
+                        let mut decode_stats = None;
+                        let mut words = Vec::new();
+                        let (text, elapsed, samples) = match answer {
+                            // the lines were shown as whisper.cpp printed them
+                            Answer::Decoded {
+                                text,
+                                elapsed,
+                                samples,
+                            } => (text, elapsed, samples),
+                            Answer::Transcribed {
+                                transcription,
+                                elapsed,
+                                samples,
+                            } => {
+                                let text = transcription.and_then(|mut transcription| {
+                                    if let Some(previous) = previous_text[pending.segment.source]
+                                        .as_ref()
+                                        .filter(|_| pending.segment.overlap > 0)
+                                    {
+                                        transcription.text =
+                                            merge::strip_overlap(previous, &transcription.text)
+                                                .to_string();
+                                        if transcription.text.is_empty() {
+                                            return None;
+                                        }
+                                    }
+                                    // the wake word itself isn't part of the transcript
+                                    if let Some(rest) = wake
+                                        .as_ref()
+                                        .filter(|_| pending.woke)
+                                        .and_then(|wake| wake.after_phrase(&transcription.text))
+                                    {
+                                        transcription.text = rest.to_string();
+                                        if transcription.text.is_empty() {
+                                            return None;
+                                        }
+                                    }
+                                    let line = match color {
+                                        true => output::colorize(
+                                            &transcription.text,
+                                            transcription.avg_logprob,
+                                        ),
+                                        false => transcription.text.clone(),
+                                    };
+                                    // the language can change from segment to segment
+                                    let line = match auto_language {
+                                        true => format!("[{}] {line}", transcription.language),
+                                        false => line,
+                                    };
+                                    let paragraph =
+                                        pending.starts_paragraph(paragraph_gap, last_end);
+                                    print_line(&pending.timestamp, pending.label, paragraph, &line);
+                                    detected_language = Some(transcription.language);
+                                    decode_stats =
+                                        Some((transcription.tokens, transcription.decode_time));
+                                    words = transcription.words;
+                                    Some(transcription.text)
+                                });
+                                (text, elapsed, samples)
+                            }
+                            _ => unreachable!("segments are answered with their transcript"),
+                        };
+                        let Pending {
+                            segment_id,
+                            segment,
+                            queued: now,
+                            label,
+                            ..
+                        } = *pending;
+                        previous_text[segment.source].clone_from(&text);
+                        let audio = vad::samples_to_duration(samples.len());
+                        if let Some(refiner) = refiner.as_mut() {
+                            refiner.submit(segment_id, segment, samples, text.clone());
+                        }
+                        transcribed |= text.is_some();
+                        let rtf = elapsed.as_secs_f64() / audio.as_secs_f64();
+                        if text.is_some() {
+                            metrics::METRICS.segment(audio, rtf);
+                        }
+                        if let Some(text) = text {
+                            segments += 1;
+                            last_end = Some(segment.end_time());
+                            let labeled = match label {
+                                Some(label) => format!("[{label}] {text}"),
+                                None => text.clone(),
+                            };
+                            // karaoke style captions get a cue per word
+                            if let Some(vtt) = vtt.as_mut() {
+                                match words.is_empty() {
+                                    true => vtt.write_cue(&segment, &labeled),
+                                    false => vtt.write_words(segment.start_time(), &words),
+                                }
+                                .expect("could not write to vtt file");
+                            }
+                            if let Some(subtitles) = subtitles.as_mut() {
+                                match words.is_empty() {
+                                    true => subtitles.write_cue(&segment, &labeled),
+                                    false => subtitles.write_words(segment.start_time(), &words),
+                                }
+                                .expect("could not write to stdout");
+                            }
+                            if let Some(log) = session_log.as_mut() {
+                                log.append(&clock.timestamp(segment.start_time()), &labeled)
+                                    .expect("could not write to session log");
+                            }
+                            if let Some(tui) = &tui {
+                                tui.transcript(segment_id, &labeled, now.elapsed());
+                            }
+                            let mut event = json!({
+                                "type": "final",
+                                "segment_id": segment_id,
+                                "text": text,
+                                "language": detected_language,
+                                "start": segment.start_time().as_secs_f64(),
+                                "end": segment.end_time().as_secs_f64(),
+                                "samples": segment.samples,
+                                "latency_ms": now.elapsed().as_millis() as u64,
+                                "model": model.display().to_string(),
+                            });
+                            if let Some(label) = label {
+                                event["device"] = json!(label);
+                            }
+                            if !words.is_empty() {
+                                event["words"] = words
+                                    .iter()
+                                    .map(|word| {
+                                        json!({
+                                            "text": word.text,
+                                            "start": (segment.start_time() + word.start).as_secs_f64(),
+                                            "end": (segment.start_time() + word.end).as_secs_f64(),
+                                        })
+                                    })
+                                    .collect();
+                            }
+                            if let Some((tokens, decode_time)) = decode_stats {
+                                if args.verbose {
+                                    eprintln!("{tokens} tokens decoded in {decode_time:.2?}");
+                                }
+                                event["tokens"] = json!(tokens);
+                                event["decode_ms"] = json!(decode_time.as_millis() as u64);
+                            }
+                            send_event(&event);
+                            sinks.finished(&Transcript {
+                                segment_id,
+                                text: &text,
+                                device: label,
+                                event: &event,
+                            });
+                        }
+                        if let Some(tui) = &tui {
+                            tui.clear_partial(segment.source);
+                        }
+                        let mut stdout = stdout.borrow_mut();
+                        if text_output {
+                            // a segment without text leaves its partial behind
+                            partial_line
+                                .borrow_mut()
+                                .clear(&mut *stdout)
+                                .expect("could not write to stdout");
+                            writeln!(stdout, "\t@{:?}", now.elapsed())
+                                .expect("could not write to stdout");
+                        }
+                        if !args.no_flush {
+                            stdout.flush().expect("could not write to stdout");
+                        }
+                        if args.once && transcribed {
+                            break;
+                        }
+                    }
+                    _ => unreachable!("answers match the job they belong to"),
+                }
+                continue;
+            }
+            Step::Closed if queued.is_empty() => break,
+            Step::Closed => {
+                ending = Some(Step::Closed);
+                continue;
+            }
+        };
+        match event {
+            VadActivity::SpeechStart { start, source } => {
+                speech_deadline = None;
+                segment_ids[source] = Some(next_segment_id);
+                if wake
+                    .as_mut()
+                    .is_some_and(|wake| wake.sleep_before(vad::samples_to_duration(start)))
+                {
+                    fall_asleep();
+                }
+                // nothing is shown before the wake word
+                next_partial = partial_interval
+                    .filter(|_| wake.as_ref().is_none_or(WakeWord::is_awake))
+                    .map(|interval| Instant::now() + interval);
+                let mut event = json!({
+                    "type": "speech_start",
+                    "segment_id": next_segment_id,
+                    "offset": vad::samples_to_duration(start).as_secs_f64(),
+                });
+                next_segment_id += 1;
+                if args.tag_devices {
+                    event["device"] = json!(devices[source]);
+                }
+                send_event(&event);
+                sinks.speech_start(&event);
+                if !args.no_flush {
+                    stdout
+                        .borrow_mut()
+                        .flush()
+                        .expect("could not write to stdout");
+                }
+            }
+            VadActivity::End(duration) => {
+                // short segments held back for merging are transcribed on
+                // their own before the end
+                if !flushing {
+                    flushing = true;
+                    flushed.extend(
+                        mergers
+                            .iter_mut()
+                            .flatten()
+                            .filter_map(ShortSegmentMerger::flush)
+                            .map(|(segment, samples)| {
+                                Step::Activity(VadActivity::SpeechEnd(segment), samples, None)
+                            }),
+                    );
+                    if !flushed.is_empty() {
+                        flushed.push_back(Step::Activity(
+                            VadActivity::End(duration),
+                            Vec::new(),
+                            None,
+                        ));
+                        continue;
+                    }
+                }
+                // segments still being transcribed are written out first
+                if !queued.is_empty() {
+                    ending = Some(Step::Activity(VadActivity::End(duration), Vec::new(), None));
+                    continue;
+                }
+                if let Some(refiner) = refiner.take() {
+                    refiner.finish().for_each(&send_revision);
+                }
+                send_event(&json!({
+                    "type": "eof",
+                    "segments": segments,
+                    "duration": duration.as_secs_f64(),
+                }));
+                if !args.quiet {
+                    eprintln!(
+                        "transcribed {segments} segments of {duration:.1?} audio in {:.1?}",
+                        wall_clock.elapsed()
+                    );
+                }
+                break;
+            }
+            VadActivity::Idle(silence) => {
+                if wake.as_mut().is_some_and(|wake| wake.sleep_after(silence)) {
+                    fall_asleep();
+                }
+                if args.verbose {
+                    eprintln!("idle for {}s", silence.as_secs());
+                }
+                send_event(&json!({
+                    "type": "idle",
+                    "silence": silence.as_secs_f64(),
+                }));
+                if !args.no_flush {
+                    stdout
+                        .borrow_mut()
+                        .flush()
+                        .expect("could not write to stdout");
+                }
+            }
+            VadActivity::SpeechEnd(segment) => {
+                // the rest of a split utterance didn't start anew and gets a fresh id
+                let segment_id = segment_ids[segment.source].take().unwrap_or_else(|| {
+                    next_segment_id += 1;
+                    next_segment_id - 1
+                });
+                if segment_ids.iter().all(Option::is_none) {
+                    next_partial = None;
+                }
+                detected += 1;
+                if detected <= args.skip_first || segment.start_time() < warmup {
+                    if args.verbose {
+                        eprintln!("skipping warmup segment");
+                    }
+                    continue;
                 }
-                println!("\t@{:?}", now.elapsed());
+                let (segment, samples) = match mergers.as_mut().filter(|_| !flushing) {
+                    Some(mergers) => match mergers[segment.source].push(segment, samples) {
+                        Some(merged) => merged,
+                        None => continue,
+                    },
+                    None => (segment, samples),
+                };
+                // until the wake word is heard only the start of segments is
+                // transcribed, just to look for it
+                let mut pending = Box::new(Pending {
+                    segment_id,
+                    queued: Instant::now(),
+                    timestamp: match (args.timestamps, args.inline_timestamps) {
+                        (true, _) => Some(clock.timestamp(segment.start_time())),
+                        (_, true) => Some(inline_timestamp(segment.start_time())),
+                        _ => None,
+                    },
+                    label: args.tag_devices.then(|| devices[segment.source].as_str()),
+                    woke: false,
+                    printed: false,
+                    segment,
+                    samples,
+                    _slot: slot,
+                });
+                if let Some(wake) = wake.as_mut() {
+                    if wake.sleep_before(pending.segment.start_time()) {
+                        fall_asleep();
+                    }
+                    if !wake.is_awake() {
+                        let window = pending.samples
+                            [..pending.samples.len().min(WAKE_WINDOW).min(max_frame)]
+                            .to_vec();
+                        let job = inference.submit(Job::Preview(window));
+                        queued.insert(job, Queued::Wake(pending));
+                        continue;
+                    }
+                    wake.heard(pending.segment.end_time());
+                }
+                let job = inference.submit(Job::Transcribe(mem::take(&mut pending.samples)));
+                queued.insert(job, Queued::Final(pending));
+            }
+        }
+    }
+    if let Some(refiner) = refiner {
+        refiner.finish().for_each(&send_revision);
+    }
+    write_warnings();
+    // hands the terminal and stderr back before anything else is reported
+    drop(tui);
+    stdout
+        .borrow_mut()
+        .flush()
+        .expect("could not write to stdout");
+    if let Some(recorder) = recorder {
+        if let Err(err) = recorder.finish() {
+            return PipelineError::FinishRecording(err).report();
+        }
+    }
+    if let Some(err) = failure {
+        return err.report();
+    }
+    // the audio side only finishes on its own at the end of the input or
+    // when it failed
+    if let StreamHandle::Thread(thread) = handle {
+        if thread.is_finished() {
+            if let Err(err) = thread.join().expect("audio thread panicked") {
+                return PipelineError::Audio(err).report();
+            }
+        }
+    }
+    match args.once && !transcribed {
+        true => ExitCode::from(NO_SPEECH_EXIT_CODE),
+        false => ExitCode::SUCCESS,
+    }
+}
+
+/// Starts listening to `mic`, its audio is queued as coming from `device`
+///
+/// buffer sizes the host rejects are retried with the next one on
+/// [`vad::buffer_size_ladder`]
+fn play_stream(
+    mic: &Device,
+    config: &StreamConfig,
+    device: usize,
+    audio_tx: &Arc<QueueSender<(usize, Vec<f32>)>>,
+    warnings: WarningSink,
+) -> Result<Stream, String> {
+    let build_stream = |config: &StreamConfig| {
+        let audio_tx = audio_tx.clone();
+        let warnings = warnings.clone();
+        mic.build_input_stream(
+            config,
+            move |data: &[f32], _info| match audio_tx.send((device, data.to_vec())) {
+                SendOutcome::Queued | SendOutcome::Disconnected => (),
+                SendOutcome::DroppedOldest | SendOutcome::DroppedNewest => {
+                    warnings.emit(Warning::AudioDropped {
+                        samples: data.len(),
+                    });
+                }
+            },
+            move |err| {
+                eprintln!("error: {err}");
+            },
+            None,
+        )
+    };
+    let mut sizes = vad::buffer_size_ladder(config.buffer_size).into_iter();
+    let mut buffer_size = sizes.next().expect("the ladder is never empty");
+    let stream = loop {
+        let err = match build_stream(&StreamConfig {
+            buffer_size,
+            ..config.clone()
+        }) {
+            Ok(stream) => break stream,
+            Err(err) => err,
+        };
+        let Some(next) = sizes.next() else {
+            return Err(err.to_string());
+        };
+        eprintln!("warning: buffer size {buffer_size:?} was rejected ({err}), trying {next:?}");
+        buffer_size = next;
+    };
+    stream.play().map_err(|err| err.to_string())?;
+    Ok(stream)
+}
+
+/// Moves the only device in `mics` over to the device named `name`
+///
+/// audio of the old device still waiting in `audio_rx` is discarded, the old
+/// device is listened to again if the new one can't be opened. `streams` is
+/// left empty if that fails too
+fn switch_device(
+    host: &Host,
+    name: &str,
+    mic: &mut (Device, StreamConfig),
+    streams: &mut Vec<Stream>,
+    audio_tx: &Arc<QueueSender<(usize, Vec<f32>)>>,
+    audio_rx: &QueueReceiver<(usize, Vec<f32>)>,
+    warnings: WarningSink,
+) -> Result<(), String> {
+    let (new_mic, config) =
+        vad::get_microphone_by_name(host, name).map_err(|err| err.to_string())?;
+    // some hosts only open a device once, so the old stream stops first
+    streams.clear();
+    audio_rx.clear();
+    match play_stream(&new_mic, &config, 0, audio_tx, warnings.clone()) {
+        Ok(stream) => {
+            streams.push(stream);
+            *mic = (new_mic, config);
+            Ok(())
+        }
+        Err(err) => {
+            match play_stream(&mic.0, &mic.1, 0, audio_tx, warnings) {
+                Ok(stream) => streams.push(stream),
+                Err(again) => eprintln!("error: could not listen to microphone again: {again}"),
             }
+            Err(err)
         }
     }
 }
 
-fn decode_bin(model: PathBuf, binary: PathBuf, samples: &[i16]) {
+/// how often a running whisper.cpp process is checked for completion
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Waits for `child` to exit, killing it once `deadline` is exceeded
+///
+/// returns `None` if the child had to be killed
+fn wait_until(child: &mut Child, deadline: Option<Instant>) -> io::Result<Option<ExitStatus>> {
+    let Some(deadline) = deadline else {
+        return child.wait().map(Some);
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            // the child may have exited in the meantime, which is fine too
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(CHILD_POLL_INTERVAL);
+    }
+}
+
+/// Transcribes `samples` with a whisper.cpp binary
+///
+/// Every line of output is handed to `on_line` as soon as whisper.cpp prints
+/// it, the full text is returned once the process exits.
+///
+/// fails if whisper.cpp can't be run at all, a segment it fails on or takes
+/// too long for only has no text
+fn decode_bin(
+    model: PathBuf,
+    binary: PathBuf,
+    samples: &[i16],
+    timeout: Option<Duration>,
+    warnings: WarningSink,
+    mut on_line: impl FnMut(&str),
+) -> io::Result<Option<String>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
     let header = wav_io::new_header(SAMPLE_RATE as u32, 16, false, true);
     let mut writer = Writer::new();
     writer
         .from_scratch_i16(&header, &samples.to_vec())
-        .expect("could not turn into wav file");
+        .map_err(io::Error::other)?;
     let bytes = writer.to_bytes();
 
     let mut out = Command::new(binary)
@@ -198,29 +2702,134 @@ fn decode_bin(model: PathBuf, binary: PathBuf, samples: &[i16]) {
         .arg(model.clone().into_os_string())
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .spawn()
-        .expect("could not execute child");
-    out.stdin
-        .as_mut()
-        .expect("child should have stdin")
-        .write_all(&bytes)
-        .expect("could not pipe audio");
-    out.wait().expect("whisper.cpp should not error");
-    let mut lines = String::new();
-    out.stdout
+        .spawn()?;
+    // written on a separate thread so a child that doesn't read its input
+    // can't block us past the deadline, stdin is closed afterwards so
+    // whisper.cpp knows all audio was sent
+    let mut stdin = out.stdin.take().expect("child should have stdin");
+    let writer = thread::spawn(move || stdin.write_all(&bytes));
+
+    // read on a separate thread so a hanging child can't block us past the
+    // deadline
+    let stdout = out
+        .stdout
         .take()
-        .expect("whisper.cpp should have an stdout")
-        .read_to_string(&mut lines)
-        .expect("whisper.cpp output should be utf8");
-    let stdout = lines
-        .strip_prefix('\n')
-        .expect("output from this tool should have a leading newline");
-    if !stdout.is_empty() {
-        print!(
-            "{}",
-            &stdout
-                .strip_prefix(' ')
-                .expect("output from this tool should have a leading space")
+        .expect("whisper.cpp should have an stdout");
+    let (line_tx, line_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            // a garbled character shouldn't cost the whole line
+            let line = String::from_utf8_lossy(&line).into_owned();
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = Vec::new();
+    loop {
+        let line = match deadline {
+            Some(deadline) => {
+                // on timeout the child gets killed below
+                match line_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => line,
+                    Err(_) => break,
+                }
+            }
+            None => match line_rx.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            },
+        };
+        let line = if lines.is_empty() {
+            // output from this tool has a leading newline and a leading space
+            if line.is_empty() {
+                continue;
+            }
+            line.strip_prefix(' ').unwrap_or(&line).to_string()
+        } else {
+            line
+        };
+        on_line(&line);
+        lines.push(line);
+    }
+    if wait_until(&mut out, deadline)?.is_none() {
+        warnings.emit(Warning::Timeout);
+        return Ok(None);
+    }
+    // a child that exited early fails the write with a broken pipe
+    if let Err(err) = writer.join().expect("audio writer panicked") {
+        eprintln!("warning: could not pipe audio to whisper.cpp: {err}");
+        return Ok(None);
+    }
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(lines.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_whisper_cpp_binary_is_an_error() {
+        let text = decode_bin(
+            "model.bin".into(),
+            "no/such/whisper-cli".into(),
+            &[0; SAMPLE_RATE],
+            None,
+            WarningSink::Stderr,
+            |_| (),
+        );
+        assert!(text.is_err());
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn mode_options_belong_to_their_subcommand() {
+        let parse = |line: &str| Args::try_parse_from(line.split_whitespace());
+        assert!(parse("wrt --model m --device mic transcribe a.wav").is_err());
+        assert!(parse("wrt --model m transcribe a.wav --device mic").is_err());
+        assert!(parse("wrt --model m devices --device mic").is_err());
+        let args = parse("wrt --model m listen --device mic --host jack").unwrap();
+        assert!(matches!(
+            args.mode,
+            Some(Mode::Listen(MicArgs { ref device, .. })) if device == &["mic"]
+        ));
+        let args = parse("wrt --model m transcribe - --stdin-format f32le:48000:2").unwrap();
+        assert!(matches!(args.mode, Some(Mode::Transcribe { .. })));
+        assert!(parse("wrt devices").is_ok());
+    }
+
+    #[test]
+    fn language_and_translation_reach_whisper() {
+        let parse = |line: &str| Args::try_parse_from(line.split_whitespace()).unwrap();
+        let args = parse("wrt --model m");
+        assert_eq!(
+            transcription_language(args.language, &args.languages).unwrap(),
+            "en"
+        );
+        assert_eq!(args.translate, Translate::Off);
+        let args = parse("wrt --model m --language auto --translate english");
+        assert_eq!(
+            transcription_language(args.language, &args.languages).unwrap(),
+            "auto"
+        );
+        assert_eq!(args.translate, Translate::English);
+        let args = parse("wrt --model m --languages de,fr");
+        assert_eq!(
+            transcription_language(args.language, &args.languages).unwrap(),
+            "auto"
         );
+        let args = parse("wrt --model m --language de --languages de,fr");
+        assert!(transcription_language(args.language, &args.languages).is_err());
     }
 }
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use crate::vad::{NSamples, Segment};
+use crate::whisper::SAMPLE_RATE;
+
+/// short segments further apart than this are not merged
+pub const MERGE_MAX_GAP: Duration = Duration::from_secs(2);
+/// default silence inserted between merged segments so words don't run
+/// together
+pub const MERGE_SPACER: Duration = Duration::from_millis(100);
+
+/// Holds back segments too short for whisper and prepends them to the
+/// following segment
+pub struct ShortSegmentMerger {
+    pending: Option<(Segment, Vec<i16>)>,
+    min_samples: NSamples,
+    /// merged audio stays below this, whisper's window
+    max_samples: NSamples,
+    max_gap: NSamples,
+    spacer: NSamples,
+}
+
+impl ShortSegmentMerger {
+    /// `spacer` is the silence put between merged segments, merged audio
+    /// stays shorter than `max_samples`
+    pub fn new(
+        min_samples: NSamples,
+        max_samples: NSamples,
+        spacer: Duration,
+    ) -> ShortSegmentMerger {
+        ShortSegmentMerger {
+            pending: None,
+            min_samples,
+            max_samples,
+            max_gap: MERGE_MAX_GAP.as_millis() as usize * SAMPLE_RATE / 1000,
+            spacer: spacer.as_millis() as usize * SAMPLE_RATE / 1000,
+        }
+    }
+
+    /// Registers a finished segment
+    ///
+    /// returns the audio that should be transcribed now, if any
+    pub fn push(&mut self, segment: Segment, samples: Vec<i16>) -> Option<(Segment, Vec<i16>)> {
+        let (segment, samples) = match self.pending.take() {
+            Some((pending, mut merged))
+                if segment.start - pending.end <= self.max_gap
+                    && merged.len() + self.spacer + samples.len() < self.max_samples =>
+            {
+                merged.resize(merged.len() + self.spacer, 0);
+                merged.extend_from_slice(&samples);
+                let segment = Segment {
+                    start: pending.start,
+                    end: segment.end,
+                    samples: merged.len(),
+                    overlap: pending.overlap,
+                    source: segment.source,
+                };
+                (segment, merged)
+            }
+            // a pending segment this far away, or one that would push the
+            // merged audio past whisper's window, is dropped as it is too short
+            // to be transcribed on its own
+            _ => (segment, samples),
+        };
+        if samples.len() < self.min_samples {
+            self.pending = Some((segment, samples));
+            return None;
+        }
+        Some((segment, samples))
+    }
+
+    /// Hands out the held back segment once no more will follow, no matter
+    /// how short it is
+    pub fn flush(&mut self) -> Option<(Segment, Vec<i16>)> {
+        self.pending.take()
+    }
+}
+
+/// Strips the words from the start of `text` that repeat the end of
+/// `previous`
+///
+/// used at the seam of segments sharing overlapping audio, words are compared
+/// ignoring case and punctuation
+pub fn strip_overlap<'a>(previous: &str, text: &'a str) -> &'a str {
+    let normalize = |word: &str| -> String {
+        word.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let previous = previous
+        .split_whitespace()
+        .map(normalize)
+        .collect::<Vec<_>>();
+    // (end of the word in `text`, normalized word)
+    let words = text
+        .split_whitespace()
+        .map(|word| {
+            let end = word.as_ptr() as usize - text.as_ptr() as usize + word.len();
+            (end, normalize(word))
+        })
+        .collect::<Vec<_>>();
+    for len in (1..=previous.len().min(words.len())).rev() {
+        let suffix = &previous[previous.len() - len..];
+        if words[..len].iter().map(|(_, word)| word).eq(suffix) {
+            return text[words[len - 1].0..].trim_start();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: usize, samples: usize) -> (Segment, Vec<i16>) {
+        let segment = Segment {
+            start,
+            end: start + samples,
+            samples,
+            overlap: 0,
+            source: 0,
+        };
+        (segment, vec![1; samples])
+    }
+
+    #[test]
+    fn merges_short_segment_into_the_next() {
+        let mut merger = ShortSegmentMerger::new(1000, 10_000, Duration::from_millis(10));
+        let (short, samples) = segment(0, 500);
+        assert!(merger.push(short, samples).is_none());
+        let (next, samples) = segment(800, 1000);
+        let (merged, samples) = merger.push(next, samples).expect("long enough");
+        assert_eq!(merged.start, 0);
+        assert_eq!(merged.end, 1800);
+        assert_eq!(samples.len(), 500 + 160 + 1000);
+        assert_eq!(merged.samples, samples.len());
+    }
+
+    #[test]
+    fn spacers_separate_every_merged_piece() {
+        let spacer = MERGE_SPACER.as_millis() as usize * SAMPLE_RATE / 1000;
+        let mut merger = ShortSegmentMerger::new(5000, 20_000, MERGE_SPACER);
+        for (start, len) in [(0, 300), (1000, 300)] {
+            let (short, samples) = segment(start, len);
+            assert!(merger.push(short, samples).is_none());
+        }
+        let (next, samples) = segment(2000, 3000);
+        let (_, merged) = merger.push(next, samples).expect("long enough");
+        assert_eq!(merged.len(), 300 + spacer + 300 + spacer + 3000);
+        // (samples of speech, samples of silence) in the merged audio
+        let mut runs: Vec<(bool, usize)> = Vec::new();
+        for sample in merged {
+            match runs.last_mut() {
+                Some((speech, len)) if *speech == (sample != 0) => *len += 1,
+                _ => runs.push((sample != 0, 1)),
+            }
+        }
+        assert_eq!(
+            runs,
+            [
+                (true, 300),
+                (false, spacer),
+                (true, 300),
+                (false, spacer),
+                (true, 3000)
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_stay_below_max_samples() {
+        let mut merger = ShortSegmentMerger::new(1000, 2000, Duration::ZERO);
+        let (short, samples) = segment(0, 500);
+        assert!(merger.push(short, samples).is_none());
+        let (next, samples) = segment(600, 1800);
+        let (kept, samples) = merger.push(next, samples).expect("long enough");
+        assert_eq!(kept.start, 600);
+        assert_eq!(samples.len(), 1800);
+        assert!(merger.flush().is_none());
+    }
+
+    #[test]
+    fn flush_returns_the_pending_segment() {
+        let mut merger = ShortSegmentMerger::new(1000, 10_000, Duration::ZERO);
+        let (short, samples) = segment(0, 500);
+        assert!(merger.push(short, samples).is_none());
+        let (flushed, samples) = merger.flush().expect("a segment is pending");
+        assert_eq!(flushed.start, 0);
+        assert_eq!(samples.len(), 500);
+        assert!(merger.flush().is_none());
+    }
+
+    #[test]
+    fn word_spanning_a_split_appears_once() {
+        let previous = "we should meet at the station";
+        let text = "the Station, tomorrow morning";
+        let joined = format!("{previous} {}", strip_overlap(previous, text));
+        assert_eq!(joined, "we should meet at the station tomorrow morning");
+        assert_eq!(joined.matches("station").count(), 1);
+    }
+
+    #[test]
+    fn unrelated_text_is_kept() {
+        assert_eq!(strip_overlap("", "hello there"), "hello there");
+        assert_eq!(strip_overlap("good morning", "hello there"), "hello there");
+        // only a prefix of `text` can repeat the previous words
+        assert_eq!(
+            strip_overlap("good morning", "hello morning"),
+            "hello morning"
+        );
+        assert_eq!(strip_overlap("the end", "the end"), "");
+    }
+}
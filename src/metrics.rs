@@ -0,0 +1,151 @@
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// clients get this long to send their request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Counters of the running pipeline
+///
+/// Updated with relaxed atomics from whatever thread notices something, they
+/// are only read when `--metrics` is scraped.
+pub struct Metrics {
+    segments: AtomicU64,
+    audio_ms: AtomicU64,
+    dropped_samples: AtomicU64,
+    /// sum of the real time factors of all segments in millionths
+    rtf_micros: AtomicU64,
+    queue_depth: AtomicU64,
+    started: OnceLock<Instant>,
+}
+
+pub static METRICS: Metrics = Metrics {
+    segments: AtomicU64::new(0),
+    audio_ms: AtomicU64::new(0),
+    dropped_samples: AtomicU64::new(0),
+    rtf_micros: AtomicU64::new(0),
+    queue_depth: AtomicU64::new(0),
+    started: OnceLock::new(),
+};
+
+impl Metrics {
+    /// Records a transcribed segment of `audio` that took `rtf` times its
+    /// length to transcribe
+    pub fn segment(&self, audio: Duration, rtf: f64) {
+        self.segments.fetch_add(1, Ordering::Relaxed);
+        self.audio_ms
+            .fetch_add(audio.as_millis() as u64, Ordering::Relaxed);
+        self.rtf_micros
+            .fetch_add((rtf * 1_000_000.0) as u64, Ordering::Relaxed);
+    }
+
+    /// segments transcribed so far
+    pub fn segments(&self) -> u64 {
+        self.segments.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self, samples: usize) {
+        self.dropped_samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    /// audio buffers waiting for voice detection
+    pub fn set_queue_depth(&self, buffers: usize) {
+        self.queue_depth.store(buffers as u64, Ordering::Relaxed);
+    }
+
+    pub fn queue_depth(&self) -> u64 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Renders all counters in the prometheus text format
+    fn render(&self) -> String {
+        let segments = self.segments.load(Ordering::Relaxed);
+        let mean_rtf = match segments {
+            0 => 0.0,
+            n => self.rtf_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0 / n as f64,
+        };
+        let uptime = self
+            .started
+            .get()
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        let metrics = [
+            (
+                "segments_total",
+                "counter",
+                "transcribed segments",
+                segments as f64,
+            ),
+            (
+                "audio_seconds_total",
+                "counter",
+                "seconds of transcribed audio",
+                self.audio_ms.load(Ordering::Relaxed) as f64 / 1000.0,
+            ),
+            (
+                "dropped_samples_total",
+                "counter",
+                "audio samples dropped because processing fell behind",
+                self.dropped_samples.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "real_time_factor",
+                "gauge",
+                "mean transcription time per second of audio",
+                mean_rtf,
+            ),
+            (
+                "input_queue_depth",
+                "gauge",
+                "audio buffers waiting for voice detection",
+                self.queue_depth.load(Ordering::Relaxed) as f64,
+            ),
+            ("uptime_seconds", "gauge", "seconds since startup", uptime),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP whisper_{name} {help}");
+            let _ = writeln!(out, "# TYPE whisper_{name} {kind}");
+            let _ = writeln!(out, "whisper_{name} {value}");
+        }
+        out
+    }
+}
+
+/// Answers every http request on `addr` with the current [`METRICS`]
+///
+/// runs on its own thread, the uptime counts from this call
+pub fn serve(addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!(
+        "serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    METRICS.started.get_or_init(Instant::now);
+    thread::spawn(move || {
+        for client in listener.incoming() {
+            let Ok(mut client) = client else {
+                continue;
+            };
+            // the request itself doesn't matter, every path gets the metrics
+            let mut request = [0; 1024];
+            if client.set_read_timeout(Some(REQUEST_TIMEOUT)).is_err()
+                || client.read(&mut request).is_err()
+            {
+                continue;
+            }
+            let body = METRICS.render();
+            let _ = write!(
+                client,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+                 {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+    });
+    Ok(())
+}
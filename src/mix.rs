@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use cpal::StreamConfig;
+
+use crate::events::WarningSink;
+use crate::vad::{self, get_resampler, AudioError, Downmixer, Resampler};
+use crate::whisper::SAMPLE_RATE;
+
+/// how far one input may run ahead of the others (200ms) before the lagging
+/// ones are filled up with silence
+///
+/// devices deliver buffers of different sizes at different times, a device
+/// that stops delivering altogether must not stall the others
+const MIX_MAX_LAG: usize = SAMPLE_RATE / 5;
+
+struct MixerInput {
+    downmix: Downmixer,
+    resample_from: Option<Resampler>,
+    /// 16kHz mono audio not yet mixed
+    pending: VecDeque<f32>,
+}
+
+/// Averages several devices into a single 16kHz mono stream
+///
+/// Every input is downmixed and resampled on its own, only audio all inputs
+/// have delivered gets mixed so the inputs stay sample aligned.
+pub struct Mixer {
+    inputs: Vec<MixerInput>,
+}
+
+impl Mixer {
+    /// fails if one of the devices delivers audio that can't be resampled
+    pub fn new(configs: &[StreamConfig], warnings: WarningSink) -> Result<Mixer, AudioError> {
+        let inputs = configs
+            .iter()
+            .map(|config| {
+                Ok(MixerInput {
+                    downmix: Downmixer::new(config.channels),
+                    resample_from: get_resampler(config.sample_rate.0, warnings.clone())?,
+                    pending: VecDeque::new(),
+                })
+            })
+            .collect::<Result<_, AudioError>>()?;
+        Ok(Mixer { inputs })
+    }
+
+    /// Queues interleaved device audio of `input`
+    pub fn push(&mut self, input: usize, data: &[f32]) {
+        let input = &mut self.inputs[input];
+        let mono = vad::to_mono(data, &mut input.downmix, &mut input.resample_from);
+        input.pending.extend(mono.iter());
+    }
+
+    /// Mixes as much queued audio as is available from all inputs
+    pub fn mix(&mut self) -> Vec<f32> {
+        let longest = self
+            .inputs
+            .iter()
+            .map(|input| input.pending.len())
+            .max()
+            .unwrap_or(0);
+        let available = self
+            .inputs
+            .iter()
+            .map(|input| input.pending.len())
+            .min()
+            .unwrap_or(0)
+            .max(longest.saturating_sub(MIX_MAX_LAG));
+        let mut mixed = vec![0.0; available];
+        for input in &mut self.inputs {
+            if input.pending.len() < available {
+                // the input fell too far behind, it missed this audio
+                input.pending.resize(available, 0.0);
+            }
+            for (out, sample) in mixed.iter_mut().zip(input.pending.drain(..available)) {
+                *out += sample;
+            }
+        }
+        let inputs = self.inputs.len() as f32;
+        mixed.iter_mut().for_each(|sample| *sample /= inputs);
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpal::{BufferSize, SampleRate};
+
+    use super::*;
+
+    fn config(channels: u16) -> StreamConfig {
+        StreamConfig {
+            channels,
+            sample_rate: SampleRate(SAMPLE_RATE as u32),
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    #[test]
+    fn only_audio_of_all_inputs_is_mixed() {
+        let mut mixer = Mixer::new(&[config(1), config(1)], WarningSink::Stderr).unwrap();
+        mixer.push(0, &[0.5; 100]);
+        assert!(mixer.mix().is_empty());
+        mixer.push(1, &[0.1; 60]);
+        let mixed = mixer.mix();
+        assert_eq!(mixed.len(), 60);
+        assert!(mixed.iter().all(|&sample| (sample - 0.3).abs() < 1e-6));
+        // the rest of the first input waits for the second one
+        mixer.push(1, &[-0.5; 40]);
+        assert!(mixer.mix().iter().all(|&sample| sample.abs() < 1e-6));
+    }
+
+    #[test]
+    fn inputs_are_downmixed_first() {
+        let mut mixer = Mixer::new(&[config(2), config(1)], WarningSink::Stderr).unwrap();
+        mixer.push(0, &[1.0, 0.0, 1.0, 0.0]);
+        mixer.push(1, &[0.5, 0.5]);
+        let mixed = mixer.mix();
+        assert_eq!(mixed.len(), 2);
+        assert!(mixed.iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn lagging_inputs_are_filled_with_silence() {
+        let mut mixer = Mixer::new(&[config(1), config(1)], WarningSink::Stderr).unwrap();
+        mixer.push(0, &vec![0.5; MIX_MAX_LAG + 100]);
+        let mixed = mixer.mix();
+        // the silent input halves the audio that can't wait any longer
+        assert_eq!(mixed.len(), 100);
+        assert!(mixed.iter().all(|&sample| (sample - 0.25).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Host, Stream};
+use ringbuf::traits::{Consumer, Producer, Split};
+use ringbuf::HeapRb;
+use samplerate::Samplerate;
+
+use whisper_real_time::vad::AudioError;
+use whisper_real_time::whisper::SAMPLE_RATE;
+
+/// Plays the captured audio back on an output device
+///
+/// Audio is fed in at 16kHz mono and converted to whatever the device wants.
+pub struct Monitor {
+    samples: Sender<Vec<i16>>,
+    // keeps playback running
+    _stream: Stream,
+}
+
+impl Monitor {
+    pub fn open(host: &Host, name: &str) -> Result<Monitor, AudioError> {
+        let unavailable =
+            |err: String| AudioError::OutputDeviceUnavailable(format!("{name}: {err}"));
+        let device = host
+            .output_devices()
+            .map_err(|err| unavailable(err.to_string()))?
+            .find(|device| device.name().is_ok_and(|dev_name| dev_name == name))
+            .ok_or_else(|| unavailable("no such output device".into()))?;
+        if host
+            .default_output_device()
+            .and_then(|default| default.name().ok())
+            .is_some_and(|default| default == name)
+        {
+            eprintln!(
+                "warning: monitoring on the default output device, use headphones to avoid feedback"
+            );
+        }
+        let config = device
+            .default_output_config()
+            .map_err(|err| unavailable(err.to_string()))?
+            .config();
+        let channels = config.channels as usize;
+        let rate = config.sample_rate.0;
+
+        // ~1s of latency headroom
+        let ring = HeapRb::<f32>::new(rate as usize * channels);
+        let (mut producer, mut consumer) = ring.split();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _info| {
+                    for sample in data.iter_mut() {
+                        *sample = consumer.try_pop().unwrap_or(0.0);
+                    }
+                },
+                move |err| {
+                    eprintln!("monitor error: {err}");
+                },
+                None,
+            )
+            .map_err(|err| unavailable(err.to_string()))?;
+        stream.play().map_err(|err| unavailable(err.to_string()))?;
+
+        let (samples, samples_rx) = mpsc::channel::<Vec<i16>>();
+        thread::spawn(move || {
+            let resampler = (rate != SAMPLE_RATE as u32).then(|| {
+                Samplerate::new(
+                    samplerate::ConverterType::SincFastest,
+                    SAMPLE_RATE as u32,
+                    rate,
+                    1,
+                )
+                .expect("should be able to build resampler")
+            });
+            while let Ok(chunk) = samples_rx.recv() {
+                let chunk: Vec<f32> = chunk
+                    .iter()
+                    .map(|&sample| sample as f32 / i16::MAX as f32)
+                    .collect();
+                let chunk = match &resampler {
+                    Some(resampler) => match resampler.process(&chunk) {
+                        Ok(chunk) => chunk,
+                        Err(_) => continue,
+                    },
+                    None => chunk,
+                };
+                for sample in chunk {
+                    for _ in 0..channels {
+                        // drop audio instead of adding latency when playback lags
+                        let _ = producer.try_push(sample);
+                    }
+                }
+            }
+        });
+
+        Ok(Monitor {
+            samples,
+            _stream: stream,
+        })
+    }
+
+    /// a handle to feed 16kHz mono audio into playback
+    pub fn sender(&self) -> Sender<Vec<i16>> {
+        self.samples.clone()
+    }
+}
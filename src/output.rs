@@ -0,0 +1,224 @@
+use std::io::{self, IsTerminal, Write};
+
+/// transcripts with a mean log probability above this are shown green
+const CONFIDENT_LOGPROB: f32 = -0.5;
+/// transcripts with a mean log probability above this are shown yellow,
+/// everything below red
+const UNSURE_LOGPROB: f32 = -1.0;
+
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+pub const RESET: &str = "\x1b[0m";
+/// moves to the start of the line and erases it
+const CLEAR_LINE: &str = "\r\x1b[K";
+/// words whose period doesn't end a sentence
+const ABBREVIATIONS: [&str; 7] = ["mr", "mrs", "ms", "dr", "prof", "vs", "etc"];
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum ColorMode {
+    /// color when stdout is a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// how transcripts are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// one line of text per transcript
+    #[default]
+    Text,
+    /// one json object per transcript
+    Jsonl,
+    /// a single json document, only for `devices`
+    Json,
+    /// SubRip subtitles with one cue per transcript
+    Srt,
+    /// WebVTT subtitles with one cue per transcript
+    Vtt,
+}
+
+/// The `--partial-ms` transcript currently shown in text mode
+///
+/// On a terminal every partial overwrites the previous one in place until the
+/// final transcript replaces it, other outputs get one line per partial.
+pub struct PartialLine {
+    in_place: bool,
+    /// characters of the partial on screen, 0 when there is none
+    shown: usize,
+}
+
+impl PartialLine {
+    pub fn for_stdout() -> PartialLine {
+        PartialLine {
+            in_place: io::stdout().is_terminal(),
+            shown: 0,
+        }
+    }
+
+    pub fn show(&mut self, out: &mut impl Write, text: &str) -> io::Result<()> {
+        if !self.in_place {
+            return writeln!(out, "{text}");
+        }
+        // erasing the line first so a shorter partial leaves nothing of a
+        // longer one behind
+        write!(out, "{CLEAR_LINE}{text}")?;
+        self.shown = text.chars().count();
+        Ok(())
+    }
+
+    /// Erases the partial on screen so the final transcript starts on a clean
+    /// line
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.shown > 0 {
+            write!(out, "{CLEAR_LINE}")?;
+            self.shown = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `text` in an ANSI color reflecting how confident whisper was
+pub fn colorize(text: &str, avg_logprob: f32) -> String {
+    let color = if avg_logprob > CONFIDENT_LOGPROB {
+        GREEN
+    } else if avg_logprob > UNSURE_LOGPROB {
+        YELLOW
+    } else {
+        RED
+    };
+    format!("{color}{text}{RESET}")
+}
+
+/// Splits `text` after sentence final punctuation followed by whitespace
+///
+/// text without such punctuation is returned as a single sentence, periods
+/// after initials (also dotted ones like `e.g.`) and [`ABBREVIATIONS`] don't
+/// end one
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends_sentence = matches!(c, '.' | '!' | '?' | '…')
+            && chars.peek().is_some_and(|(_, next)| next.is_whitespace())
+            && !(c == '.' && is_abbreviation(&text[start..i]));
+        if ends_sentence {
+            let end = i + c.len_utf8();
+            sentences.push(text[start..end].trim());
+            start = end;
+        }
+    }
+    sentences.push(text[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    if sentences.is_empty() {
+        sentences.push(text);
+    }
+    sentences
+}
+
+/// whether the last word of `text` is an abbreviation that a period follows
+fn is_abbreviation(text: &str) -> bool {
+    let word = text.rsplit(char::is_whitespace).next().unwrap_or_default();
+    let initials = word.split('.').all(|initial| {
+        let mut letters = initial.chars();
+        letters.next().is_some_and(char::is_alphabetic) && letters.next().is_none()
+    });
+    initials
+        || ABBREVIATIONS
+            .iter()
+            .any(|abbreviation| word.eq_ignore_ascii_case(abbreviation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partials_are_replaced_in_place() {
+        let mut partial = PartialLine {
+            in_place: true,
+            shown: 0,
+        };
+        let mut out = Vec::new();
+        partial.show(&mut out, "hello wor").unwrap();
+        partial.show(&mut out, "hello world").unwrap();
+        partial.clear(&mut out).unwrap();
+        writeln!(out, "Hello world.").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{CLEAR_LINE}hello wor{CLEAR_LINE}hello world{CLEAR_LINE}Hello world.\n")
+        );
+        // nothing is erased without a partial on screen
+        let mut out = Vec::new();
+        partial.clear(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn partials_get_lines_of_their_own_outside_terminals() {
+        let mut partial = PartialLine {
+            in_place: false,
+            shown: 0,
+        };
+        let mut out = Vec::new();
+        partial.show(&mut out, "hello").unwrap();
+        partial.clear(&mut out).unwrap();
+        assert_eq!(out, b"hello\n");
+    }
+
+    #[test]
+    fn sentences_get_a_line_each() {
+        assert_eq!(
+            split_sentences("It works. Does it? Yes! Great"),
+            ["It works.", "Does it?", "Yes!", "Great"]
+        );
+    }
+
+    #[test]
+    fn text_without_punctuation_is_kept() {
+        assert_eq!(
+            split_sentences("no punctuation here"),
+            ["no punctuation here"]
+        );
+        // nothing but whitespace stays as it was too
+        assert_eq!(split_sentences("  "), ["  "]);
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_dropped() {
+        assert_eq!(
+            split_sentences(" First one.  Second one. "),
+            ["First one.", "Second one."]
+        );
+    }
+
+    #[test]
+    fn abbreviations_and_ellipses() {
+        assert_eq!(
+            split_sentences("Dr. Smith met J. Doe at 5 p.m. today. Then e.g. lunch."),
+            ["Dr. Smith met J. Doe at 5 p.m. today.", "Then e.g. lunch."]
+        );
+        assert_eq!(
+            split_sentences("Wait... what? So… yes"),
+            ["Wait...", "what?", "So…", "yes"]
+        );
+        // a period inside a number or word doesn't end anything
+        assert_eq!(
+            split_sentences("Version 2.5 is out"),
+            ["Version 2.5 is out"]
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// what happens when audio arrives while the queue is full
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum OverflowPolicy {
+    /// discard the oldest queued buffer to make room (keeps latency low)
+    DropOldest,
+    /// discard the incoming buffer
+    #[default]
+    DropNewest,
+    /// wait until there is room again
+    Block,
+}
+
+pub enum SendOutcome {
+    Queued,
+    /// the item was queued but the oldest queued item got discarded
+    DroppedOldest,
+    /// the item was discarded
+    DroppedNewest,
+    /// the receiver is gone
+    Disconnected,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+/// Bounded single producer single consumer queue with a configurable
+/// [`OverflowPolicy`]
+pub fn bounded<T>(capacity: usize, policy: OverflowPolicy) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            sender_alive: true,
+            receiver_alive: true,
+        }),
+        changed: Condvar::new(),
+        capacity,
+        policy,
+    });
+    (QueueSender(shared.clone()), QueueReceiver(shared))
+}
+
+pub struct QueueSender<T>(Arc<Shared<T>>);
+
+impl<T> QueueSender<T> {
+    pub fn send(&self, item: T) -> SendOutcome {
+        let shared = &self.0;
+        let mut state = shared.state.lock().expect("queue lock poisoned");
+        let mut outcome = SendOutcome::Queued;
+        while state.receiver_alive && state.items.len() >= shared.capacity {
+            match shared.policy {
+                OverflowPolicy::DropOldest => {
+                    state.items.pop_front();
+                    outcome = SendOutcome::DroppedOldest;
+                }
+                OverflowPolicy::DropNewest => return SendOutcome::DroppedNewest,
+                OverflowPolicy::Block => {
+                    state = shared.changed.wait(state).expect("queue lock poisoned");
+                }
+            }
+        }
+        if !state.receiver_alive {
+            return SendOutcome::Disconnected;
+        }
+        state.items.push_back(item);
+        shared.changed.notify_all();
+        outcome
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().expect("queue lock poisoned");
+        state.sender_alive = false;
+        self.0.changed.notify_all();
+    }
+}
+
+pub struct QueueReceiver<T>(Arc<Shared<T>>);
+
+impl<T> QueueReceiver<T> {
+    /// Waits for the next item, `None` once the sender is gone and the queue
+    /// is drained
+    pub fn recv(&self) -> Option<T> {
+        let shared = &self.0;
+        let mut state = shared.state.lock().expect("queue lock poisoned");
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                shared.changed.notify_all();
+                return Some(item);
+            }
+            if !state.sender_alive {
+                return None;
+            }
+            state = shared.changed.wait(state).expect("queue lock poisoned");
+        }
+    }
+
+    /// Like [`recv`](Self::recv) but gives up once `timeout` passed without
+    /// an item
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let shared = &self.0;
+        let deadline = Instant::now() + timeout;
+        let mut state = shared.state.lock().expect("queue lock poisoned");
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                shared.changed.notify_all();
+                return Ok(item);
+            }
+            if !state.sender_alive {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = shared
+                .changed
+                .wait_timeout(state, left)
+                .expect("queue lock poisoned")
+                .0;
+        }
+    }
+
+    /// amount of items currently waiting
+    pub fn queued(&self) -> usize {
+        self.0
+            .state
+            .lock()
+            .expect("queue lock poisoned")
+            .items
+            .len()
+    }
+
+    /// Discards all waiting items
+    pub fn clear(&self) {
+        let mut state = self.0.state.lock().expect("queue lock poisoned");
+        state.items.clear();
+        self.0.changed.notify_all();
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().expect("queue lock poisoned");
+        state.receiver_alive = false;
+        self.0.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn drain<T>(receiver: &QueueReceiver<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Ok(item) = receiver.recv_timeout(Duration::ZERO) {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_items() {
+        let (sender, receiver) = bounded(2, OverflowPolicy::DropOldest);
+        assert!(matches!(sender.send(1), SendOutcome::Queued));
+        assert!(matches!(sender.send(2), SendOutcome::Queued));
+        assert!(matches!(sender.send(3), SendOutcome::DroppedOldest));
+        assert_eq!(drain(&receiver), [2, 3]);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_queued_items() {
+        let (sender, receiver) = bounded(2, OverflowPolicy::DropNewest);
+        sender.send(1);
+        sender.send(2);
+        assert!(matches!(sender.send(3), SendOutcome::DroppedNewest));
+        assert_eq!(receiver.queued(), 2);
+        assert_eq!(drain(&receiver), [1, 2]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (sender, receiver) = bounded(1, OverflowPolicy::Block);
+        sender.send(1);
+        let blocked = thread::spawn(move || sender.send(2));
+        // the sender can't get rid of its item before the receiver takes one
+        thread::sleep(Duration::from_millis(50));
+        assert!(!blocked.is_finished());
+        assert_eq!(receiver.recv(), Some(1));
+        assert!(matches!(blocked.join().unwrap(), SendOutcome::Queued));
+        assert_eq!(receiver.recv(), Some(2));
+        // the sender is gone with the thread
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn disconnects_are_seen_on_both_ends() {
+        let (sender, receiver) = bounded(1, OverflowPolicy::Block);
+        sender.send(1);
+        drop(sender);
+        // queued items still arrive after the sender is gone
+        assert_eq!(receiver.recv(), Some(1));
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+
+        let (sender, receiver) = bounded(1, OverflowPolicy::Block);
+        sender.send(1);
+        let blocked = thread::spawn(move || sender.send(2));
+        thread::sleep(Duration::from_millis(50));
+        // a blocked sender wakes up once the receiver is dropped
+        drop(receiver);
+        assert!(matches!(blocked.join().unwrap(), SendOutcome::Disconnected));
+    }
+
+    #[test]
+    fn recv_timeout_gives_up() {
+        let (_sender, receiver) = bounded::<u8>(1, OverflowPolicy::DropNewest);
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::{self, Read};
+use std::ops::ControlFlow;
+use std::str::FromStr;
+
+/// sample encoding of raw audio
+#[derive(Debug, Clone, Copy)]
+pub enum RawEncoding {
+    /// signed 16 bit little endian
+    S16le,
+    /// 32 bit float little endian
+    F32le,
+}
+
+impl RawEncoding {
+    fn bytes(self) -> usize {
+        match self {
+            RawEncoding::S16le => 2,
+            RawEncoding::F32le => 4,
+        }
+    }
+
+    fn decode(self, sample: &[u8]) -> f32 {
+        match self {
+            RawEncoding::S16le => i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0,
+            RawEncoding::F32le => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+        }
+    }
+}
+
+/// layout of headerless audio, written as `<encoding>:<sample rate>:<channels>`
+#[derive(Debug, Clone, Copy)]
+pub struct RawFormat {
+    pub encoding: RawEncoding,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl FromStr for RawFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<RawFormat, String> {
+        let parts = format.split(':').collect::<Vec<_>>();
+        let [encoding, sample_rate, channels] = parts[..] else {
+            return Err("expected <encoding>:<sample rate>:<channels> like s16le:16000:1".into());
+        };
+        let encoding = match encoding {
+            "s16le" => RawEncoding::S16le,
+            "f32le" => RawEncoding::F32le,
+            _ => return Err(format!("unknown encoding '{encoding}', use s16le or f32le")),
+        };
+        let sample_rate = sample_rate
+            .parse()
+            .ok()
+            .filter(|&rate: &u32| rate > 0)
+            .ok_or_else(|| format!("invalid sample rate '{sample_rate}'"))?;
+        let channels = channels
+            .parse()
+            .ok()
+            .filter(|&channels: &u16| channels > 0)
+            .ok_or_else(|| format!("invalid channel count '{channels}'"))?;
+        Ok(RawFormat {
+            encoding,
+            sample_rate,
+            channels,
+        })
+    }
+}
+
+/// Reads raw audio until `reader` ends or `on_chunk` breaks, handing
+/// interleaved chunks of about 30ms to `on_chunk`
+///
+/// chunks always hold whole samples but not necessarily whole frames
+pub fn read(
+    mut reader: impl Read,
+    format: RawFormat,
+    mut on_chunk: impl FnMut(&[f32]) -> ControlFlow<()>,
+) -> io::Result<()> {
+    let sample_bytes = format.encoding.bytes();
+    let frame_bytes = sample_bytes * format.channels as usize;
+    let mut buf = vec![0u8; (format.sample_rate as usize / 30).max(1) * frame_bytes];
+    // bytes of a sample cut in half by the previous read are kept at the start
+    let mut filled = 0;
+    loop {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(()),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+        let whole = filled - filled % sample_bytes;
+        let samples = buf[..whole]
+            .chunks_exact(sample_bytes)
+            .map(|sample| format.encoding.decode(sample))
+            .collect::<Vec<_>>();
+        if on_chunk(&samples).is_break() {
+            return Ok(());
+        }
+        buf.copy_within(whole..filled, 0);
+        filled -= whole;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a reader handing out at most `step` bytes per read
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read_all(data: &[u8], step: usize, format: &str) -> Vec<f32> {
+        let mut samples = Vec::new();
+        let reader = Trickle { data, step };
+        read(reader, format.parse().unwrap(), |chunk| {
+            samples.extend_from_slice(chunk);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        samples
+    }
+
+    #[test]
+    fn formats_parse() {
+        let format: RawFormat = "f32le:48000:2".parse().unwrap();
+        assert!(matches!(format.encoding, RawEncoding::F32le));
+        assert_eq!((format.sample_rate, format.channels), (48000, 2));
+        assert!("s16le:16000".parse::<RawFormat>().is_err());
+        assert!("u8:16000:1".parse::<RawFormat>().is_err());
+        assert!("s16le:0:1".parse::<RawFormat>().is_err());
+        assert!("s16le:16000:0".parse::<RawFormat>().is_err());
+    }
+
+    #[test]
+    fn samples_split_between_reads_are_joined() {
+        let data = [0i16, 16384, -32768]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+        // an odd step cuts every other sample in half
+        assert_eq!(read_all(&data, 3, "s16le:16000:1"), [0.0, 0.5, -1.0]);
+        let data = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(read_all(&data, 5, "f32le:16000:2"), [0.25, -0.75]);
+    }
+
+    #[test]
+    fn breaking_stops_reading() {
+        let data = [0u8; 64];
+        let mut chunks = 0;
+        let reader = Trickle {
+            data: &data,
+            step: 2,
+        };
+        read(reader, "s16le:16000:1".parse().unwrap(), |_| {
+            chunks += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(chunks, 1);
+    }
+}
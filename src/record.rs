@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use whisper_real_time::whisper::SAMPLE_RATE;
+
+/// how often the wav header is updated so the file stays playable if we get
+/// killed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const HEADER_LEN: u32 = 44;
+/// how often the writer checks whether the recording was finished while no
+/// audio arrives
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Writes the 16kHz mono stream into a single wav file on its own thread
+pub struct Recorder {
+    samples: Sender<Vec<i16>>,
+    /// set by [`Recorder::finish`], senders still alive are ignored from then
+    /// on
+    closed: Arc<AtomicBool>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write_header(&mut out, 0)?;
+        let (samples, samples_rx) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let writer = thread::spawn({
+            let closed = closed.clone();
+            move || write_samples(out, samples_rx, &closed)
+        });
+        Ok(Recorder {
+            samples,
+            closed,
+            writer,
+        })
+    }
+
+    /// a handle to feed audio into the recording
+    pub fn sender(&self) -> Sender<Vec<i16>> {
+        self.samples.clone()
+    }
+
+    /// Writes the audio sent so far and finalizes the file
+    ///
+    /// doesn't wait for the other senders to go away, an audio thread that is
+    /// still running would keep the recording open forever
+    pub fn finish(self) -> io::Result<()> {
+        self.closed.store(true, Ordering::Relaxed);
+        drop(self.samples);
+        self.writer.join().expect("recording thread panicked")
+    }
+}
+
+fn write_samples(
+    mut out: BufWriter<File>,
+    samples: Receiver<Vec<i16>>,
+    closed: &AtomicBool,
+) -> io::Result<()> {
+    let mut data_len = 0u32;
+    let mut last_flush = Instant::now();
+    while !closed.load(Ordering::Relaxed) {
+        let chunk = match samples.recv_timeout(CLOSE_POLL_INTERVAL) {
+            Ok(chunk) => chunk,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        write_chunk(&mut out, &chunk, &mut data_len)?;
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            finalize(&mut out, data_len)?;
+            last_flush = Instant::now();
+        }
+    }
+    // audio that was already sent still ends up in the file
+    for chunk in samples.try_iter() {
+        write_chunk(&mut out, &chunk, &mut data_len)?;
+    }
+    finalize(&mut out, data_len)
+}
+
+fn write_chunk(out: &mut BufWriter<File>, chunk: &[i16], data_len: &mut u32) -> io::Result<()> {
+    for sample in chunk {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    *data_len = data_len.saturating_add(chunk.len() as u32 * 2);
+    Ok(())
+}
+
+/// patches the sizes in the header and moves back to the end of the file
+fn finalize(out: &mut BufWriter<File>, data_len: u32) -> io::Result<()> {
+    out.seek(SeekFrom::Start(0))?;
+    write_header(out, data_len)?;
+    out.seek(SeekFrom::End(0))?;
+    out.flush()
+}
+
+/// canonical 16bit mono pcm wav header
+fn write_header(out: &mut impl Write, data_len: u32) -> io::Result<()> {
+    let sample_rate = SAMPLE_RATE as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    out.write_all(&1u16.to_le_bytes())?; // pcm
+    out.write_all(&1u16.to_le_bytes())?; // channels
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+    out.write_all(&2u16.to_le_bytes())?; // block align
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn finish_does_not_wait_for_other_senders() {
+        let path = std::env::temp_dir().join(format!("record-test-{}.wav", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let tap = recorder.sender();
+        tap.send(vec![1; 100]).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), HEADER_LEN as u64 + 200);
+        drop(tap);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use whisper_real_time::vad::Segment;
+use whisper_real_time::whisper::{Whisper, WhisperOptions, WhisperSetupError};
+
+/// a segment the refining model transcribes again
+struct Job {
+    segment_id: u64,
+    segment: Segment,
+    samples: Vec<i16>,
+    draft: Option<String>,
+    queued: Instant,
+}
+
+/// the refining model's transcript of a segment that already got a draft
+pub struct Revision {
+    pub segment_id: u64,
+    pub segment: Segment,
+    pub draft: Option<String>,
+    pub text: Option<String>,
+    pub language: Option<String>,
+    /// time from handing the segment over until it was transcribed again
+    pub latency: Duration,
+}
+
+/// Transcribes segments a second time with a larger model on its own thread
+///
+/// the draft model keeps transcribing right away, revisions arrive whenever
+/// the larger model catches up
+pub struct Refiner {
+    jobs: Sender<Job>,
+    revisions: Receiver<Revision>,
+    /// segments handed over without a revision yet
+    pending: usize,
+}
+
+impl Refiner {
+    /// Loads `model` on the refining thread, returns once it's ready
+    pub fn spawn(model: PathBuf, options: WhisperOptions) -> Result<Refiner, WhisperSetupError> {
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        let (revisions_tx, revisions) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::channel();
+        // whisper's parameters can't be sent between threads, so the model
+        // is loaded where it runs
+        thread::spawn(move || {
+            let mut whisper = match Whisper::with_options(&model, options) {
+                Ok(whisper) => {
+                    let _ = ready_tx.send(Ok(()));
+                    whisper
+                }
+                Err(err) => {
+                    let _ = ready_tx.send(Err(err));
+                    return;
+                }
+            };
+            for job in jobs_rx {
+                let transcription =
+                    whisper
+                        .transcribe_samples(&job.samples)
+                        .unwrap_or_else(|err| {
+                            eprintln!("warning: {err}, keeping the draft");
+                            None
+                        });
+                let (text, language) = match transcription {
+                    Some(transcription) => (Some(transcription.text), Some(transcription.language)),
+                    None => (None, None),
+                };
+                let revision = Revision {
+                    segment_id: job.segment_id,
+                    segment: job.segment,
+                    draft: job.draft,
+                    text,
+                    language,
+                    latency: job.queued.elapsed(),
+                };
+                if revisions_tx.send(revision).is_err() {
+                    break;
+                }
+            }
+        });
+        ready
+            .recv()
+            .expect("the refining thread reports whether the model loaded")?;
+        Ok(Refiner {
+            jobs,
+            revisions,
+            pending: 0,
+        })
+    }
+
+    /// Queues `samples` for another transcription, `draft` is what the draft
+    /// model made of them
+    pub fn submit(
+        &mut self,
+        segment_id: u64,
+        segment: Segment,
+        samples: Vec<i16>,
+        draft: Option<String>,
+    ) {
+        let job = Job {
+            segment_id,
+            segment,
+            samples,
+            draft,
+            queued: Instant::now(),
+        };
+        if self.jobs.send(job).is_ok() {
+            self.pending += 1;
+        }
+    }
+
+    /// segments still being transcribed again
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Revisions that are ready, doesn't block
+    pub fn revisions(&mut self) -> Vec<Revision> {
+        let revisions = self.revisions.try_iter().collect::<Vec<_>>();
+        self.pending -= revisions.len();
+        revisions
+    }
+
+    /// Waits for the revisions of all submitted segments
+    pub fn finish(self) -> impl Iterator<Item = Revision> {
+        drop(self.jobs);
+        self.revisions.into_iter()
+    }
+}
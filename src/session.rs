@@ -0,0 +1,40 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use time::OffsetDateTime;
+
+/// Transcript log with an automatically generated name
+pub struct SessionLog {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl SessionLog {
+    /// Creates `dir/transcript-YYYYMMDD-HHMMSS.txt` named after `started`
+    pub fn create(dir: impl AsRef<Path>, started: OffsetDateTime) -> io::Result<SessionLog> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "transcript-{:04}{:02}{:02}-{:02}{:02}{:02}.txt",
+            started.year(),
+            started.month() as u8,
+            started.day(),
+            started.hour(),
+            started.minute(),
+            started.second()
+        ));
+        let out = BufWriter::new(File::options().create(true).append(true).open(&path)?);
+        Ok(SessionLog { path, out })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a finalized segment and flushes it to disk right away
+    pub fn append(&mut self, timestamp: &str, text: &str) -> io::Result<()> {
+        writeln!(self.out, "{timestamp} {text}")?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,134 @@
+//! [Silero VAD](https://github.com/snakers4/silero-vad) as a [`VoiceDetector`]
+//!
+//! The onnx model runs in process with [ort](https://ort.pyke.io), built with
+//! the `silero` feature. onnxruntime itself is loaded when the first detector
+//! is created, from `ORT_DYLIB_PATH` or the library path.
+
+use std::panic;
+use std::path::Path;
+
+use ort::session::Session;
+use ort::value::Tensor;
+
+use crate::vad::{VoiceDetector, VAD_FRAME};
+use crate::whisper::SAMPLE_RATE;
+
+/// samples the model looks at at once (32ms at 16kHz)
+pub const CHUNK: usize = 512;
+/// samples of the previous chunk the model sees in front of every chunk
+const CONTEXT: usize = 64;
+/// size of the state the model carries from chunk to chunk
+const STATE: [usize; 3] = [2, 1, 128];
+/// chunks with a higher speech probability are speech
+const THRESHOLD: f32 = 0.5;
+
+pub struct SileroDetector {
+    session: Session,
+    state: Vec<f32>,
+    context: Vec<f32>,
+    /// samples that don't fill a whole chunk yet
+    pending: Vec<i16>,
+    /// decision of the last chunk, frames without a new chunk keep it
+    speech: bool,
+    /// the model failed on a chunk before, which was reported already
+    failed: bool,
+}
+
+impl SileroDetector {
+    /// Loads the model at `model`, checks it with a chunk of silence
+    pub fn new(model: &Path) -> Result<SileroDetector, ort::Error> {
+        // ort panics instead of returning an error when onnxruntime is missing
+        let session = panic::catch_unwind(|| {
+            Session::builder()?
+                .with_intra_threads(1)?
+                .with_inter_threads(1)?
+                .commit_from_file(model)
+        })
+        .map_err(|_| {
+            ort::Error::new("could not load onnxruntime, point ORT_DYLIB_PATH at the library")
+        })??;
+        let mut detector = SileroDetector {
+            session,
+            state: vec![0.0; STATE.iter().product()],
+            context: vec![0.0; CONTEXT],
+            pending: Vec::with_capacity(CHUNK + VAD_FRAME),
+            speech: false,
+            failed: false,
+        };
+        // a model with other inputs fails here instead of on the audio thread
+        detector.probability(&[0; CHUNK])?;
+        detector.state.fill(0.0);
+        detector.context.fill(0.0);
+        Ok(detector)
+    }
+
+    fn probability(&mut self, chunk: &[i16]) -> Result<f32, ort::Error> {
+        let mut input = Vec::with_capacity(CONTEXT + CHUNK);
+        input.extend_from_slice(&self.context);
+        input.extend(chunk.iter().map(|&sample| sample as f32 / 32768.0));
+        self.context.copy_from_slice(&input[CHUNK..]);
+        let outputs = self.session.run(ort::inputs![
+            "input" => Tensor::from_array(([1, CONTEXT + CHUNK], input))?,
+            "state" => Tensor::from_array((STATE, self.state.clone()))?,
+            "sr" => Tensor::from_array(((), vec![SAMPLE_RATE as i64]))?,
+        ])?;
+        let output = |name| {
+            outputs
+                .get(name)
+                .ok_or_else(|| ort::Error::new(format!("the model has no output '{name}'")))?
+                .try_extract_tensor::<f32>()
+        };
+        let (_, state) = output("stateN")?;
+        if state.len() != self.state.len() {
+            return Err(ort::Error::new("the model's state has an unexpected size"));
+        }
+        self.state.copy_from_slice(state);
+        let (_, probability) = output("output")?;
+        probability
+            .first()
+            .copied()
+            .ok_or_else(|| ort::Error::new("the model returned no probability"))
+    }
+}
+
+impl VoiceDetector for SileroDetector {
+    fn is_speech(&mut self, frame: &[i16; VAD_FRAME]) -> bool {
+        self.pending.extend_from_slice(frame);
+        while self.pending.len() >= CHUNK {
+            let chunk: Vec<i16> = self.pending.drain(..CHUNK).collect();
+            match self.probability(&chunk) {
+                Ok(probability) => self.speech = probability > THRESHOLD,
+                // the last decision holds until the model works again
+                Err(err) if !self.failed => {
+                    eprintln!("warning: the silero vad failed: {err}");
+                    self.failed = true;
+                }
+                Err(_) => (),
+            }
+        }
+        self.speech
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn missing_model_is_an_error() {
+        assert!(SileroDetector::new(Path::new("/nonexistent/silero_vad.onnx")).is_err());
+    }
+
+    #[test]
+    fn silence_is_not_speech() {
+        let Some(model) = env::var_os("SILERO_TEST_MODEL") else {
+            eprintln!("skipped, set SILERO_TEST_MODEL to silero_vad.onnx to run it");
+            return;
+        };
+        let mut detector = SileroDetector::new(Path::new(&model)).unwrap();
+        assert!((0..20).all(|_| !detector.is_speech(&[0; VAD_FRAME])));
+        assert!(!detector.failed);
+    }
+}
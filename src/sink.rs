@@ -0,0 +1,288 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Stdout, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::clipboard::{Clipboard, ClipboardBackend, ClipboardMode};
+use crate::tcp::TcpBroadcaster;
+use crate::typing::{TypeBackend, TypeSuffix, Typist};
+use crate::websocket::WebSocketBroadcaster;
+
+/// stdout shared by the printed transcripts and the `stdout` sink, so lines
+/// keep their order and get flushed together
+pub type SharedStdout = Rc<RefCell<BufWriter<Stdout>>>;
+
+/// A partial or final transcript of an utterance
+pub struct Transcript<'a> {
+    pub segment_id: u64,
+    pub text: &'a str,
+    /// device it was heard on with `--tag-devices`
+    pub device: Option<&'a str>,
+    /// the `partial` or `final` event `--tcp` clients get
+    pub event: &'a Value,
+}
+
+impl Transcript<'_> {
+    /// the text prefixed with its device, like printed transcripts
+    pub fn labeled(&self) -> String {
+        match self.device {
+            Some(device) => format!("[{device}] {}", self.text),
+            None => self.text.to_string(),
+        }
+    }
+}
+
+/// Somewhere transcripts go
+///
+/// Sinks are called from the main loop in the order utterances happen, a
+/// slow sink holds up transcription. Only the final transcript has to be
+/// handled, starts and partials are ignored unless a sink cares about them.
+/// Revisions of `--draft-model` aren't passed on, sinks keep the draft.
+pub trait TranscriptSink {
+    /// `event` is the `speech_start` event `--tcp` clients get, with the
+    /// segment id, its offset and device
+    fn on_speech_start(&mut self, _event: &Value) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_partial(&mut self, _partial: &Transcript) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()>;
+}
+
+/// All active sinks, a failing sink only gets a warning
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<(String, Box<dyn TranscriptSink>)>,
+}
+
+impl Sinks {
+    /// Adds `sink`, `name` tells it apart in warnings
+    pub fn push(&mut self, name: &str, sink: Box<dyn TranscriptSink>) {
+        self.sinks.push((name.to_string(), sink));
+    }
+
+    pub fn speech_start(&mut self, event: &Value) {
+        self.each(|sink| sink.on_speech_start(event));
+    }
+
+    pub fn partial(&mut self, partial: &Transcript) {
+        self.each(|sink| sink.on_partial(partial));
+    }
+
+    pub fn finished(&mut self, transcript: &Transcript) {
+        self.each(|sink| sink.on_final(transcript));
+    }
+
+    fn each(&mut self, mut call: impl FnMut(&mut dyn TranscriptSink) -> io::Result<()>) {
+        for (name, sink) in &mut self.sinks {
+            if let Err(err) = call(sink.as_mut()) {
+                eprintln!("warning: {name} sink failed: {err}");
+            }
+        }
+    }
+}
+
+/// A sink given to `--sink`
+#[derive(Debug, Clone)]
+pub enum SinkSpec {
+    /// final transcripts as lines on stdout, in place of the usual output
+    Stdout,
+    /// final transcripts appended as lines to a file
+    File(PathBuf),
+    /// a shell command run for every final transcript, which it reads from
+    /// stdin
+    Command(String),
+    /// the latest transcript on the clipboard
+    Clipboard,
+    /// transcripts typed into the focused window
+    Type,
+    /// `speech_start`, `partial` and `final` events as json lines to tcp
+    /// clients
+    Tcp(String),
+    /// the same events as WebSocket messages
+    WebSocket(String),
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<SinkSpec, String> {
+        let (kind, arg) = match spec.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (spec, None),
+        };
+        let sink = match (kind, arg) {
+            ("stdout", None) => SinkSpec::Stdout,
+            ("clipboard", None) => SinkSpec::Clipboard,
+            ("type", None) => SinkSpec::Type,
+            ("file", Some(path)) if !path.is_empty() => SinkSpec::File(path.into()),
+            ("command", Some(command)) if !command.is_empty() => {
+                SinkSpec::Command(command.to_string())
+            }
+            ("tcp", Some(addr)) if !addr.is_empty() => SinkSpec::Tcp(addr.to_string()),
+            ("websocket", Some(addr)) if !addr.is_empty() => SinkSpec::WebSocket(addr.to_string()),
+            ("stdout" | "clipboard" | "type", Some(_)) => {
+                return Err(format!("'{kind}' takes no argument"));
+            }
+            ("file" | "command" | "tcp" | "websocket", _) => {
+                return Err(format!("'{kind}' needs an argument, like '{kind}:...'"));
+            }
+            _ => {
+                return Err(format!(
+                    "unknown sink '{kind}', expected stdout, file:PATH, command:CMD, \
+                     clipboard, type, tcp:ADDR or websocket:ADDR"
+                ));
+            }
+        };
+        Ok(sink)
+    }
+}
+
+impl SinkSpec {
+    /// what `--sink` calls this kind of sink
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkSpec::Stdout => "stdout",
+            SinkSpec::File(_) => "file",
+            SinkSpec::Command(_) => "command",
+            SinkSpec::Clipboard => "clipboard",
+            SinkSpec::Type => "type",
+            SinkSpec::Tcp(_) => "tcp",
+            SinkSpec::WebSocket(_) => "websocket",
+        }
+    }
+
+    /// Sets the sink up, clipboard and typing use the backend fitting the
+    /// session and `stdout` writes to `stdout`
+    pub fn open(self, stdout: &SharedStdout) -> io::Result<Box<dyn TranscriptSink>> {
+        let sink: Box<dyn TranscriptSink> = match self {
+            SinkSpec::Stdout => Box::new(StdoutSink(stdout.clone())),
+            SinkSpec::File(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                eprintln!("appending transcripts to '{}'", path.display());
+                Box::new(file)
+            }
+            SinkSpec::Command(command) => Box::new(ShellCommand(command)),
+            SinkSpec::Clipboard => {
+                let clipboard = Clipboard::new(ClipboardBackend::detect(), ClipboardMode::Segment);
+                eprintln!("copying transcripts with {:?}", clipboard.backend());
+                Box::new(clipboard)
+            }
+            SinkSpec::Type => {
+                let typist = Typist::new(TypeBackend::detect(), TypeSuffix::Space);
+                eprintln!("typing transcripts with {:?}", typist.backend());
+                Box::new(typist)
+            }
+            SinkSpec::Tcp(addr) => Box::new(TcpBroadcaster::bind(addr)?),
+            SinkSpec::WebSocket(addr) => Box::new(WebSocketBroadcaster::bind(addr)?),
+        };
+        Ok(sink)
+    }
+}
+
+/// the `stdout` sink, flushed along with everything else on stdout
+struct StdoutSink(SharedStdout);
+
+impl TranscriptSink for StdoutSink {
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        writeln!(self.0.borrow_mut(), "{}", transcript.labeled())
+    }
+}
+
+impl TranscriptSink for File {
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        writeln!(self, "{}", transcript.labeled())
+    }
+}
+
+/// `sh -c` command of a `command:` sink
+struct ShellCommand(String);
+
+impl TranscriptSink for ShellCommand {
+    /// Runs the command with the transcript on stdin and its segment id and
+    /// device in the environment, returns once it exited
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", &self.0])
+            .env("WHISPER_SEGMENT_ID", transcript.segment_id.to_string())
+            .stdin(Stdio::piped());
+        if let Some(device) = transcript.device {
+            command.env("WHISPER_DEVICE", device);
+        }
+        let mut child = command.spawn()?;
+        let written = child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(format!("{}\n", transcript.text).as_bytes());
+        let status = child.wait()?;
+        // commands that don't read their input are fine
+        if let Err(err) = written {
+            if err.kind() != io::ErrorKind::BrokenPipe {
+                return Err(err);
+            }
+        }
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "'{}' exited with {status}",
+                self.0
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl TranscriptSink for Clipboard {
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        self.copy(&transcript.labeled())
+    }
+}
+
+impl TranscriptSink for Typist {
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        self.type_text(transcript.text)
+    }
+}
+
+impl TranscriptSink for TcpBroadcaster {
+    fn on_speech_start(&mut self, event: &Value) -> io::Result<()> {
+        self.broadcast(&event.to_string());
+        Ok(())
+    }
+
+    fn on_partial(&mut self, partial: &Transcript) -> io::Result<()> {
+        self.broadcast(&partial.event.to_string());
+        Ok(())
+    }
+
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        self.broadcast(&transcript.event.to_string());
+        Ok(())
+    }
+}
+
+impl TranscriptSink for WebSocketBroadcaster {
+    fn on_speech_start(&mut self, event: &Value) -> io::Result<()> {
+        self.broadcast(&event.to_string());
+        Ok(())
+    }
+
+    fn on_partial(&mut self, partial: &Transcript) -> io::Result<()> {
+        self.broadcast(&partial.event.to_string());
+        Ok(())
+    }
+
+    fn on_final(&mut self, transcript: &Transcript) -> io::Result<()> {
+        self.broadcast(&transcript.event.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use whisper_real_time::vad::Segment;
+use whisper_real_time::whisper::Word;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SubtitleFormat {
+    /// WebVTT (`HH:MM:SS.mmm` cue timings)
+    Vtt,
+    /// SubRip (numbered cues, `HH:MM:SS,mmm` cue timings)
+    Srt,
+}
+
+impl SubtitleFormat {
+    /// the character separating seconds from milliseconds in cue timings
+    fn millis_separator(self) -> char {
+        match self {
+            SubtitleFormat::Vtt => '.',
+            SubtitleFormat::Srt => ',',
+        }
+    }
+}
+
+/// Formats `time` as a cue timestamp `HH:MM:SS<sep>mmm`
+pub fn format_timestamp(time: Duration, millis_separator: char) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{millis_separator}{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+/// Writes one cue per transcribed segment into a subtitle file
+pub struct SubtitleWriter<W: Write = BufWriter<File>> {
+    format: SubtitleFormat,
+    out: W,
+    /// cues written so far
+    cues: usize,
+}
+
+impl SubtitleWriter {
+    pub fn create(path: impl AsRef<Path>, format: SubtitleFormat) -> io::Result<SubtitleWriter> {
+        SubtitleWriter::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write> SubtitleWriter<W> {
+    /// Writes the subtitles to `out` instead of a file
+    pub fn new(mut out: W, format: SubtitleFormat) -> io::Result<SubtitleWriter<W>> {
+        match format {
+            SubtitleFormat::Vtt => out.write_all(b"WEBVTT\n\n")?,
+            SubtitleFormat::Srt => (),
+        }
+        out.flush()?;
+        Ok(SubtitleWriter {
+            format,
+            out,
+            cues: 0,
+        })
+    }
+
+    /// Appends a cue spanning `segment`
+    ///
+    /// The file is flushed after every cue so it stays usable if the process
+    /// gets killed.
+    pub fn write_cue(&mut self, segment: &Segment, text: &str) -> io::Result<()> {
+        self.write_span(segment.start_time(), segment.end_time(), text)
+    }
+
+    /// Appends a cue per word, the word times are offsets from `start`
+    pub fn write_words(&mut self, start: Duration, words: &[Word]) -> io::Result<()> {
+        for word in words {
+            self.write_span(start + word.start, start + word.end, &word.text)?;
+        }
+        Ok(())
+    }
+
+    fn write_span(&mut self, start: Duration, end: Duration, text: &str) -> io::Result<()> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.cues += 1;
+        if let SubtitleFormat::Srt = self.format {
+            writeln!(self.out, "{}", self.cues)?;
+        }
+        let sep = self.format.millis_separator();
+        writeln!(
+            self.out,
+            "{} --> {}",
+            format_timestamp(start, sep),
+            format_timestamp(end, sep)
+        )?;
+        match self.format {
+            // cue text may not contain "-->" or raw markup characters
+            SubtitleFormat::Vtt => writeln!(
+                self.out,
+                "{}",
+                text.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            )?,
+            SubtitleFormat::Srt => writeln!(self.out, "{text}")?,
+        }
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_ms: usize, end_ms: usize) -> Segment {
+        Segment {
+            start: start_ms * 16,
+            end: end_ms * 16,
+            samples: (end_ms - start_ms) * 16,
+            overlap: 0,
+            source: 0,
+        }
+    }
+
+    fn written(format: SubtitleFormat, cues: &[(Segment, &str)]) -> String {
+        let mut writer = SubtitleWriter::new(Vec::new(), format).unwrap();
+        for (segment, text) in cues {
+            writer.write_cue(segment, text).unwrap();
+        }
+        String::from_utf8(writer.out).unwrap()
+    }
+
+    #[test]
+    fn timestamps_carry_into_hours() {
+        let time = Duration::from_millis(3_723_004);
+        assert_eq!(format_timestamp(time, '.'), "01:02:03.004");
+        assert_eq!(format_timestamp(time, ','), "01:02:03,004");
+    }
+
+    #[test]
+    fn vtt_cues_parse() {
+        let vtt = written(
+            SubtitleFormat::Vtt,
+            &[
+                (segment(500, 2_250), " hello there "),
+                (segment(3_000, 3_500), ""),
+                (segment(61_000, 62_000), "a <b> & c"),
+            ],
+        );
+        let mut blocks = vtt.split("\n\n");
+        assert_eq!(blocks.next(), Some("WEBVTT"));
+        let cues: Vec<Vec<&str>> = blocks
+            .filter(|block| !block.is_empty())
+            .map(|block| block.lines().collect())
+            .collect();
+        assert_eq!(
+            cues,
+            [
+                ["00:00:00.500 --> 00:00:02.250", "hello there"],
+                ["00:01:01.000 --> 00:01:02.000", "a &lt;b&gt; &amp; c"],
+            ]
+        );
+        assert!(vtt.ends_with("\n\n"));
+    }
+
+    #[test]
+    fn srt_cues_are_numbered() {
+        let srt = written(
+            SubtitleFormat::Srt,
+            &[(segment(0, 1_000), "one"), (segment(1_000, 2_000), "two")],
+        );
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,000\none\n\n2\n00:00:01,000 --> 00:00:02,000\ntwo\n\n"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// clients that can't keep up for this long get disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends lines to every connected TCP client
+///
+/// Accepting and writing happen on their own threads, so [`broadcast`]
+/// never blocks. Clients only receive lines sent after they connected.
+///
+/// [`broadcast`]: TcpBroadcaster::broadcast
+pub struct TcpBroadcaster {
+    lines: Sender<String>,
+}
+
+impl TcpBroadcaster {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<TcpBroadcaster> {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!("serving transcripts on tcp://{}", listener.local_addr()?);
+        let clients = Arc::new(Mutex::new(Vec::<TcpStream>::new()));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = match client {
+                    Ok(client) => client,
+                    Err(err) => {
+                        eprintln!("tcp: could not accept client: {err}");
+                        continue;
+                    }
+                };
+                if client
+                    .set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))
+                    .is_err()
+                {
+                    continue;
+                }
+                accepted.lock().expect("tcp clients poisoned").push(client);
+            }
+        });
+
+        let (lines, lines_rx) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            while let Ok(line) = lines_rx.recv() {
+                clients
+                    .lock()
+                    .expect("tcp clients poisoned")
+                    .retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+            }
+        });
+        Ok(TcpBroadcaster { lines })
+    }
+
+    /// Queues `line` (a trailing newline gets added) for all connected
+    /// clients
+    pub fn broadcast(&self, line: &str) {
+        // the writer thread only exits if this sender is gone
+        let _ = self.lines.send(format!("{line}\n"));
+    }
+}
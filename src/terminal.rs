@@ -0,0 +1,134 @@
+use std::os::fd::RawFd;
+use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, OnceLock, PoisonError};
+use std::thread;
+
+/// shows the cursor again and goes back to the normal screen, like
+/// crossterm's `Show` and `LeaveAlternateScreen`
+const LEAVE_SCREEN: &str = "\x1b[?25h\x1b[?1049l";
+
+/// terminal put into raw mode and its settings from before
+static TERMIOS: OnceLock<(RawFd, libc::termios)> = OnceLock::new();
+/// whether the terminal in [`TERMIOS`] is still in raw mode
+static RAW: AtomicBool = AtomicBool::new(false);
+/// whether stdout shows the alternate screen
+static SCREEN: AtomicBool = AtomicBool::new(false);
+/// stderr from before it was redirected, -1 while it isn't
+static STDERR: AtomicI32 = AtomicI32::new(-1);
+/// runs at exit after the terminal was put back, see [`on_exit`]
+static ON_EXIT: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+static HOOKS: Once = Once::new();
+
+/// Remembers that `fd` left line buffered mode, [`restore`] sets `original`
+/// again
+///
+/// only the first terminal of the process is remembered
+pub fn raw_mode(fd: RawFd, original: libc::termios) {
+    install_hooks();
+    let _ = TERMIOS.set((fd, original));
+    if TERMIOS.get().is_some_and(|(saved, _)| *saved == fd) {
+        RAW.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Forgets about raw mode once whoever changed the terminal put it back
+pub fn cooked() {
+    RAW.store(false, Ordering::SeqCst);
+}
+
+/// Remembers that stdout switched to the alternate screen and that stderr got
+/// redirected, [`restore`] points stderr back at `stderr`
+///
+/// `stderr` has to stay open until [`left_screen`]
+pub fn alternate_screen(stderr: RawFd) {
+    install_hooks();
+    STDERR.store(stderr, Ordering::SeqCst);
+    SCREEN.store(true, Ordering::SeqCst);
+}
+
+/// whether the alternate screen is still up
+pub fn on_screen() -> bool {
+    SCREEN.load(Ordering::Relaxed)
+}
+
+/// Forgets about the alternate screen and the [`on_exit`] hook once whoever
+/// took over the screen gives it back
+pub fn left_screen() {
+    SCREEN.store(false, Ordering::SeqCst);
+    STDERR.store(-1, Ordering::SeqCst);
+    on_exit_hook().take();
+}
+
+/// Runs `hook` when the process exits without calling [`left_screen`], after
+/// the terminal was put back
+pub fn on_exit(hook: impl FnOnce() + Send + 'static) {
+    install_hooks();
+    *on_exit_hook() = Some(Box::new(hook));
+}
+
+fn on_exit_hook() -> MutexGuard<'static, Option<Box<dyn FnOnce() + Send>>> {
+    ON_EXIT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Puts the terminal back the way it was before the process changed it
+///
+/// Destructors don't run on `process::exit`, a panic on the main thread
+/// or a second Ctrl-C, this runs instead. Only calls async signal safe
+/// functions so signal handlers can use it too.
+pub fn restore() {
+    if SCREEN.swap(false, Ordering::SeqCst) {
+        // SAFETY: writing a static string to stdout
+        unsafe {
+            libc::write(
+                libc::STDOUT_FILENO,
+                LEAVE_SCREEN.as_ptr().cast(),
+                LEAVE_SCREEN.len(),
+            );
+        }
+    }
+    let stderr = STDERR.swap(-1, Ordering::SeqCst);
+    if stderr >= 0 {
+        // SAFETY: `stderr` stays open until `left_screen` forgets it
+        unsafe {
+            libc::dup2(stderr, libc::STDERR_FILENO);
+        }
+    }
+    if RAW.swap(false, Ordering::SeqCst) {
+        if let Some((fd, original)) = TERMIOS.get() {
+            // SAFETY: `fd` stays open while `RAW` is set and `original` came
+            // from tcgetattr
+            unsafe {
+                libc::tcsetattr(*fd, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+extern "C" fn restore_at_exit() {
+    restore();
+    // a hook can't run twice, and exiting from within it doesn't deadlock
+    let hook = ON_EXIT.try_lock().ok().and_then(|mut hook| hook.take());
+    if let Some(hook) = hook {
+        hook();
+    }
+}
+
+/// Runs [`restore`] on exit and before the panic message of the main thread,
+/// which stays readable that way
+fn install_hooks() {
+    HOOKS.call_once(|| {
+        // SAFETY: `restore_at_exit` doesn't unwind
+        unsafe {
+            libc::atexit(restore_at_exit);
+        }
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // other threads panicking don't end the process
+            if thread::current().name() == Some("main") {
+                restore();
+            }
+            previous(info);
+        }));
+    });
+}
@@ -0,0 +1,443 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use cpal::{BufferSize, SampleRate, StreamConfig};
+use ringbuf::traits::{Consumer, Split};
+use ringbuf::{HeapProd, HeapRb};
+
+use crate::events::WarningSink;
+use crate::vad::{
+    self, get_resampler, AudioError, ClipDetector, Downmixer, Resampler, Segment, Vad, VadActivity,
+    VadOptions,
+};
+use crate::whisper::{
+    History, TranscribeError, Transcription, Whisper, WhisperOptions, WhisperSetupError,
+    SAMPLE_RATE,
+};
+
+/// Voice detection fed with audio by the caller instead of an audio device
+///
+/// Works with any audio source. Speech ends up in the ring and gets announced
+/// on the activity channel just like audio captured from a microphone, popping
+/// it off the ring and transcribing it is up to the caller. [`Transcriber`]
+/// does both.
+pub struct AudioFeed {
+    downmix: Downmixer,
+    /// channel count `downmix` was built for
+    channels: u16,
+    resample_from: Option<Resampler>,
+    ring: HeapProd<i16>,
+    vad: Vad,
+    activity: Sender<VadActivity>,
+    clipping: Option<ClipDetector>,
+}
+
+impl AudioFeed {
+    /// Doesn't open any audio device, audio pushed with
+    /// [`AudioFeed::push_f32`] has to be sampled at `sample_rate`
+    ///
+    /// fails if audio of `sample_rate` can't be resampled
+    pub fn manual(
+        sample_rate: u32,
+        vad: Vad,
+        ring: HeapProd<i16>,
+        activity: Sender<VadActivity>,
+        warnings: WarningSink,
+    ) -> Result<AudioFeed, AudioError> {
+        Ok(AudioFeed {
+            downmix: Downmixer::new(1),
+            channels: 1,
+            resample_from: get_resampler(sample_rate, warnings)?,
+            ring,
+            vad,
+            activity,
+            clipping: None,
+        })
+    }
+
+    /// Warns about clipping in the audio pushed with [`AudioFeed::push_f32`]
+    pub fn set_clip_detector(&mut self, clipping: ClipDetector) {
+        self.clipping = Some(clipping);
+    }
+
+    /// see [`Vad::set_activity_callback`]
+    pub fn set_activity_callback(&mut self, callback: impl FnMut(&VadActivity) + Send + 'static) {
+        self.vad.set_activity_callback(callback);
+    }
+
+    /// Feeds interleaved audio with `channels` channels
+    pub fn push_f32(&mut self, data: &[f32], channels: u16) {
+        if channels != self.channels {
+            if channels > 1 {
+                eprintln!("converting {channels} channel audio to mono");
+            }
+            self.downmix = Downmixer::new(channels);
+            self.channels = channels;
+        }
+        vad::audio_loop(
+            data,
+            &mut self.downmix,
+            &mut self.resample_from,
+            &mut self.ring,
+            &mut self.vad,
+            &mut self.activity,
+            self.clipping.as_mut(),
+        );
+    }
+
+    /// Ends open speech and reports the end of the stream
+    pub fn finish(mut self) {
+        vad::end_stream(&mut self.vad, &mut self.activity);
+    }
+
+    /// Feeds audio that already is 16kHz mono
+    pub fn push_i16(&mut self, data: &[i16]) {
+        vad::detect(data, &mut self.ring, &mut self.vad, &mut self.activity);
+    }
+}
+
+/// how often the transcription worker looks for a model to switch to while
+/// nobody speaks
+const MODEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// a model for the transcription worker and where to report whether it loaded
+type ModelSwitch = (PathBuf, Sender<Result<(), WhisperSetupError>>);
+
+/// what [`Transcriber::manual`] sets up
+pub struct TranscriberConfig {
+    /// rate of the audio pushed with [`Transcriber::push_f32`]
+    pub sample_rate: u32,
+    pub vad: VadOptions,
+    pub model: PathBuf,
+    pub whisper: WhisperOptions,
+    /// seconds of speech the ring holds while whisper is busy
+    pub ring_seconds: usize,
+}
+
+/// an utterance and what whisper made of it
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    pub segment: Segment,
+    pub transcription: Transcription,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscriberError {
+    #[error(transparent)]
+    Audio(#[from] AudioError),
+    #[error("could not set up the vad: {0}")]
+    Vad(&'static str),
+    #[error(transparent)]
+    Model(#[from] WhisperSetupError),
+    /// the transcript channel was closed, so the worker stopped
+    #[error("the transcription worker has stopped")]
+    Stopped,
+}
+
+/// Voice detection and whisper fed with audio by the caller
+///
+/// Audio pushed with [`Transcriber::push_f32`] or [`Transcriber::push_i16`]
+/// goes through the same resampling and voice detection as audio from a
+/// microphone. Every utterance is transcribed on a worker thread that owns
+/// the model and sent to the transcript channel, so pushing never waits for
+/// whisper.
+pub struct Transcriber {
+    feed: AudioFeed,
+    /// the last [`WhisperOptions::history`] transcriptions, only the worker
+    /// writes to it
+    recent: Arc<Mutex<History<Transcription>>>,
+    models: Sender<ModelSwitch>,
+}
+
+impl Transcriber {
+    /// Loads the model on the transcription worker, returns once it's ready
+    ///
+    /// Doesn't open any audio device. Transcripts are sent to `transcripts`
+    /// in the order they were spoken, the channel closes once the worker is
+    /// through with everything before [`Transcriber::finish`] or after
+    /// whisper failed.
+    pub fn manual(
+        mut config: TranscriberConfig,
+        transcripts: Sender<Result<Transcript, TranscribeError>>,
+    ) -> Result<Transcriber, TranscriberError> {
+        let warnings = config.vad.warnings.clone();
+        let stream = StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(config.sample_rate),
+            buffer_size: BufferSize::Default,
+        };
+        // every segment has to fit into whisper's window
+        let max_frame = config.whisper.max_frame();
+        config.vad.max_samples = Some(
+            config
+                .vad
+                .max_samples
+                .map_or(max_frame, |max| max.min(max_frame)),
+        );
+        let vad = Vad::try_new(&stream, config.vad).map_err(TranscriberError::Vad)?;
+        let (producer, mut consumer) =
+            HeapRb::<i16>::new(config.ring_seconds * SAMPLE_RATE).split();
+        let (activity_tx, activity) = mpsc::channel();
+        let feed = AudioFeed::manual(config.sample_rate, vad, producer, activity_tx, warnings)
+            .map_err(TranscriberError::Audio)?;
+        let (ready_tx, ready) = mpsc::channel();
+        // whisper's parameters can't be sent between threads, so the model
+        // is loaded where it runs
+        let (model, options) = (config.model, config.whisper);
+        let recent = Arc::new(Mutex::new(History::new(options.history)));
+        let (models, models_rx) = mpsc::channel::<ModelSwitch>();
+        thread::spawn({
+            let recent = recent.clone();
+            move || {
+                let mut whisper = match Whisper::with_options(&model, options) {
+                    Ok(whisper) => {
+                        let _ = ready_tx.send(Ok(()));
+                        whisper
+                    }
+                    Err(err) => {
+                        let _ = ready_tx.send(Err(err));
+                        return;
+                    }
+                };
+                loop {
+                    // only between segments, speech arriving meanwhile waits
+                    // in the ring
+                    for (model, loaded) in models_rx.try_iter() {
+                        let _ = loaded.send(whisper.set_model(model));
+                    }
+                    let segment = match activity.recv_timeout(MODEL_POLL_INTERVAL) {
+                        Ok(VadActivity::SpeechEnd(segment)) => segment,
+                        Ok(VadActivity::End(_)) | Err(RecvTimeoutError::Disconnected) => return,
+                        Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+                    };
+                    let mut samples = vec![0; segment.samples];
+                    if consumer.pop_slice(&mut samples) != segment.samples {
+                        panic!("logic error: not enough samples could be fetched");
+                    }
+                    let transcription = match whisper.transcribe_samples(&samples) {
+                        Ok(Some(transcription)) => transcription,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            let _ = transcripts.send(Err(err));
+                            return;
+                        }
+                    };
+                    recent
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(transcription.clone());
+                    let transcript = Transcript {
+                        segment,
+                        transcription,
+                    };
+                    if transcripts.send(Ok(transcript)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        ready
+            .recv()
+            .expect("the transcription worker reports whether the model loaded")
+            .map_err(TranscriberError::Model)?;
+        Ok(Transcriber {
+            feed,
+            recent,
+            models,
+        })
+    }
+
+    /// Warns about clipping in the audio pushed with
+    /// [`Transcriber::push_f32`]
+    pub fn set_clip_detector(&mut self, clipping: ClipDetector) {
+        self.feed.set_clip_detector(clipping);
+    }
+
+    /// Registers a callback that sees every [`VadActivity`] as soon as it's
+    /// detected
+    ///
+    /// It runs on the thread pushing audio, right after the audio that
+    /// started or ended the speech was pushed and independent of whisper, so
+    /// onsets and ends come without waiting for transcription. Pushing waits
+    /// for it, keep it cheap and never block in it.
+    pub fn set_activity_callback(&mut self, callback: impl FnMut(&VadActivity) + Send + 'static) {
+        self.feed.set_activity_callback(callback);
+    }
+
+    /// Feeds interleaved audio with `channels` channels
+    pub fn push_f32(&mut self, data: &[f32], channels: u16) {
+        self.feed.push_f32(data, channels);
+    }
+
+    /// Feeds audio that already is 16kHz mono
+    pub fn push_i16(&mut self, data: &[i16]) {
+        self.feed.push_i16(data);
+    }
+
+    /// The last [`WhisperOptions::history`] transcriptions, oldest first
+    ///
+    /// a copy, taken without waiting for the segment whisper is busy with
+    pub fn recent(&self) -> Vec<Transcription> {
+        self.recent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_slice()
+            .to_vec()
+    }
+
+    /// Switches to the model at `model` once whisper is done with the current
+    /// segment, returns when it's loaded
+    ///
+    /// Audio keeps being detected meanwhile, speech waits in the ring and
+    /// gets transcribed by the new model. The old model stays active if the
+    /// new one can't be loaded.
+    pub fn set_model(&self, model: impl Into<PathBuf>) -> Result<(), TranscriberError> {
+        let (loaded_tx, loaded) = mpsc::channel();
+        self.models
+            .send((model.into(), loaded_tx))
+            .map_err(|_| TranscriberError::Stopped)?;
+        match loaded.recv() {
+            Ok(loaded) => loaded.map_err(TranscriberError::Model),
+            Err(_) => Err(TranscriberError::Stopped),
+        }
+    }
+
+    /// Ends open speech, it is still transcribed before the transcript
+    /// channel closes
+    pub fn finish(self) {
+        self.feed.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::vad::VadMode;
+    use crate::whisper::Translate;
+
+    fn config(model: PathBuf) -> TranscriberConfig {
+        TranscriberConfig {
+            sample_rate: 48000,
+            vad: VadOptions {
+                mode: VadMode::default(),
+                silero_model: None,
+                language: "en".to_string(),
+                end_silence: None,
+                tail: None,
+                overlap: Duration::ZERO,
+                idle_interval: None,
+                max_samples: None,
+                onset_lookahead: Duration::ZERO,
+                min_speech: Duration::ZERO,
+                warnings: WarningSink::Stderr,
+            },
+            model,
+            whisper: WhisperOptions {
+                translate: Translate::Off,
+                // fixed languages are looked up in whisper.cpp before the model
+                language: "auto".to_string(),
+                languages: Vec::new(),
+                detect_window: None,
+                redetect_every: None,
+                best_of: 1,
+                beam_size: None,
+                threads: 1,
+                temperature: 0.0,
+                temperature_inc: 0.2,
+                entropy_threshold: 2.4,
+                logprob_threshold: -1.0,
+                initial_prompt: None,
+                carry_context: 0,
+                suppress_non_speech: true,
+                single_segment: true,
+                timestamps: false,
+                word_timestamps: false,
+                audio_ctx: None,
+                max_tokens: None,
+                max_text_ctx: None,
+                trim_silence: true,
+                min_audio: Duration::ZERO,
+                history: 2,
+                max_window: Duration::from_secs(30),
+                load_retries: 0,
+                gpu_device: None,
+            },
+            ring_seconds: 10,
+        }
+    }
+
+    #[test]
+    fn missing_model_fails_before_any_audio_is_taken() {
+        let (transcripts, _) = mpsc::channel();
+        let result = Transcriber::manual(config("no/such/model.bin".into()), transcripts);
+        assert!(matches!(
+            result,
+            Err(TranscriberError::Model(
+                WhisperSetupError::ModelFileNotFound
+            ))
+        ));
+    }
+
+    #[test]
+    fn failed_model_switch_keeps_the_old_model() {
+        let Some(model) = std::env::var_os("WHISPER_TEST_MODEL") else {
+            eprintln!("skipped, set WHISPER_TEST_MODEL to a ggml model to run it");
+            return;
+        };
+        let (transcripts, transcripts_rx) = mpsc::channel();
+        let mut config = config(model.into());
+        config.sample_rate = SAMPLE_RATE as u32;
+        let mut transcriber = Transcriber::manual(config, transcripts).expect("test model loads");
+        assert!(matches!(
+            transcriber.set_model("no/such/model.bin"),
+            Err(TranscriberError::Model(
+                WhisperSetupError::ModelFileNotFound
+            ))
+        ));
+        let tone: Vec<i16> = (0..3 * SAMPLE_RATE)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16
+            })
+            .collect();
+        transcriber.push_i16(&tone);
+        transcriber.finish();
+        // the worker is still alive and drains the channel
+        for _ in transcripts_rx {}
+    }
+
+    #[test]
+    fn activity_callback_runs_while_pushing() {
+        let Some(model) = std::env::var_os("WHISPER_TEST_MODEL") else {
+            eprintln!("skipped, set WHISPER_TEST_MODEL to a ggml model to run it");
+            return;
+        };
+        let (transcripts, _transcripts) = mpsc::channel();
+        let mut config = config(model.into());
+        config.sample_rate = SAMPLE_RATE as u32;
+        let mut transcriber = Transcriber::manual(config, transcripts).expect("test model loads");
+        let (seen_tx, seen) = mpsc::channel();
+        transcriber.set_activity_callback(move |activity| {
+            let _ = seen_tx.send(matches!(activity, VadActivity::End(_)));
+        });
+        transcriber.push_i16(&vec![0; SAMPLE_RATE]);
+        transcriber.finish();
+        // the end is reported before `finish` returns, whisper isn't waited for
+        assert_eq!(seen.try_iter().last(), Some(true));
+    }
+
+    #[test]
+    fn invalid_vad_options_are_reported() {
+        let (transcripts, _) = mpsc::channel();
+        let mut config = config("no/such/model.bin".into());
+        config.vad.end_silence = Some(Duration::from_millis(100));
+        config.vad.tail = Some(Duration::from_millis(200));
+        let result = Transcriber::manual(config, transcripts);
+        assert!(matches!(result, Err(TranscriberError::Vad(_))));
+    }
+}
@@ -0,0 +1,482 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Stdout, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::{Hide, Show};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::symbols;
+use ratatui::text::{Line as TextLine, Span};
+use ratatui::widgets::{Block, Borders, LineGauge, Paragraph};
+use ratatui::{Frame, Terminal};
+use whisper_real_time::metrics;
+use whisper_real_time::vad::VadActivity;
+
+use crate::terminal;
+
+/// time between two redraws
+const FRAME: Duration = Duration::from_millis(100);
+/// lines of stderr shown below the transcript
+const LOG_LINES: u16 = 3;
+/// lines of stderr kept to repeat warnings and errors once the screen is left
+const LOG_KEEP: usize = 200;
+/// how long the log is waited for when the screen goes away
+const LOG_DRAIN: Duration = Duration::from_millis(200);
+/// the meter reaches from this level in dBFS up to 0
+const METER_FLOOR: f32 = -60.0;
+/// width of the latency column in front of transcripts
+const LATENCY_WIDTH: usize = 8;
+
+/// a transcript on screen
+struct Line {
+    segment_id: u64,
+    text: String,
+    latency: Duration,
+}
+
+/// what the screen shows, filled in by the audio thread and the main loop
+#[derive(Default)]
+struct State {
+    /// loudest input since the last redraw, as rms between 0 and 1
+    level: f32,
+    /// whether each source is in the middle of speech
+    speaking: Vec<bool>,
+    transcript: Vec<Line>,
+    /// the `--partial-ms` transcript of each source
+    partials: Vec<Option<String>>,
+    /// lines written to stderr
+    log: Vec<String>,
+    /// whether everything written to stderr reached the log
+    log_closed: bool,
+}
+
+/// the terminal while the tui owns it
+struct Screen {
+    /// stderr from before it was redirected into the log
+    stderr: OwnedFd,
+    stopping: Arc<AtomicBool>,
+    renderer: JoinHandle<()>,
+}
+
+/// Full screen view of the running transcription for `--tui`
+///
+/// Shows the input level, whether someone speaks, the transcripts with their
+/// latency and the audio queue. Everything the program writes to stderr ends
+/// up in a small log below the transcripts instead of breaking the layout.
+pub struct Tui {
+    state: Arc<Mutex<State>>,
+    screen: Option<Screen>,
+}
+
+impl Tui {
+    /// fails when stdout is not a terminal
+    pub fn new(sources: usize) -> io::Result<Tui> {
+        if !io::stdout().is_terminal() {
+            return Err(io::Error::other("stdout is not a terminal"));
+        }
+        let state = State {
+            speaking: vec![false; sources],
+            partials: vec![None; sources],
+            ..State::default()
+        };
+        Ok(Tui {
+            state: Arc::new(Mutex::new(state)),
+            screen: None,
+        })
+    }
+
+    /// Sender for a copy of the audio, drives the level meter
+    pub fn level_tap(&self) -> Sender<Vec<i16>> {
+        let (tap, samples) = mpsc::channel::<Vec<i16>>();
+        let state = self.state.clone();
+        thread::spawn(move || {
+            for chunk in samples {
+                if chunk.is_empty() {
+                    continue;
+                }
+                let square_sum = chunk
+                    .iter()
+                    .map(|&sample| (sample as f32 / i16::MAX as f32).powi(2))
+                    .sum::<f32>();
+                let rms = (square_sum / chunk.len() as f32).sqrt();
+                let mut state = lock(&state);
+                state.level = state.level.max(rms);
+            }
+        });
+        tap
+    }
+
+    /// Activity callback of the detector of `source`, see
+    /// [`Vad::set_activity_callback`](whisper_real_time::vad::Vad::set_activity_callback)
+    pub fn vad_state(&self, source: usize) -> impl FnMut(&VadActivity) + Send + 'static {
+        let state = self.state.clone();
+        move |activity| {
+            let speaking = match activity {
+                VadActivity::SpeechStart { .. } => true,
+                VadActivity::SpeechEnd(_) => false,
+                _ => return,
+            };
+            lock(&state).speaking[source] = speaking;
+        }
+    }
+
+    /// Takes over the terminal until the tui is dropped
+    ///
+    /// `paused` is shown as the state of the voice detection while it's set
+    pub fn start(&mut self, paused: Arc<AtomicBool>) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        let stderr = capture_stderr(self.state.clone())?;
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+        terminal::alternate_screen(stderr.as_raw_fd());
+        let mut screen = Terminal::new(CrosstermBackend::new(stdout))?;
+        // errors right before `process::exit` would be lost in the log
+        terminal::on_exit({
+            let state = self.state.clone();
+            move || replay_log(&state)
+        });
+        let stopping = Arc::new(AtomicBool::new(false));
+        let renderer = thread::spawn({
+            let state = self.state.clone();
+            let stopping = stopping.clone();
+            move || {
+                while !stopping.load(Ordering::Relaxed) && terminal::on_screen() {
+                    let _ = screen.draw(|frame| {
+                        let mut state = lock(&state);
+                        draw(frame, &state, paused.load(Ordering::Relaxed));
+                        state.level = 0.0;
+                    });
+                    thread::sleep(FRAME);
+                }
+                leave_screen(&mut screen);
+            }
+        });
+        self.screen = Some(Screen {
+            stderr,
+            stopping,
+            renderer,
+        });
+        Ok(())
+    }
+
+    /// Shows `text` as what `source` said so far
+    pub fn partial(&self, source: usize, text: &str) {
+        lock(&self.state).partials[source] = Some(text.to_string());
+    }
+
+    /// Adds the transcript of a segment that took `latency` to transcribe
+    pub fn transcript(&self, segment_id: u64, text: &str, latency: Duration) {
+        lock(&self.state).transcript.push(Line {
+            segment_id,
+            text: text.to_string(),
+            latency,
+        });
+    }
+
+    /// Removes the partial of `source` once its segment is done
+    pub fn clear_partial(&self, source: usize) {
+        lock(&self.state).partials[source] = None;
+    }
+
+    /// Replaces the transcript of `segment_id`, the screen can take it back
+    /// unlike printed lines
+    pub fn revise(&self, segment_id: u64, text: &str) {
+        let mut state = lock(&self.state);
+        if let Some(line) = state
+            .transcript
+            .iter_mut()
+            .rev()
+            .find(|line| line.segment_id == segment_id)
+        {
+            line.text = text.to_string();
+        }
+    }
+}
+
+impl Drop for Tui {
+    /// Gives the terminal back, prints the transcripts to stdout and repeats
+    /// the log on stderr so it outlives the screen
+    fn drop(&mut self) {
+        let Some(screen) = self.screen.take() else {
+            return;
+        };
+        screen.stopping.store(true, Ordering::Relaxed);
+        let _ = screen.renderer.join();
+        // SAFETY: both are open file descriptors, dup2 replaces the pipe
+        unsafe {
+            libc::dup2(screen.stderr.as_raw_fd(), libc::STDERR_FILENO);
+        }
+        terminal::left_screen();
+        replay_log(&self.state);
+        let state = lock(&self.state);
+        let mut stdout = io::stdout().lock();
+        for line in &state.transcript {
+            let _ = writeln!(stdout, "{}", line.text);
+        }
+        let _ = stdout.flush();
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Writes the log to stderr once it was pointed back at the terminal
+///
+/// waits a moment for lines still in the pipe, children that inherited the
+/// pipe can keep it open longer
+fn replay_log(state: &Mutex<State>) {
+    let deadline = Instant::now() + LOG_DRAIN;
+    while !lock(state).log_closed && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut stderr = io::stderr().lock();
+    for line in &lock(state).log {
+        let _ = writeln!(stderr, "{line}");
+    }
+}
+
+/// Points stderr at a pipe whose lines are appended to the log, returns the
+/// original stderr
+fn capture_stderr(state: Arc<Mutex<State>>) -> io::Result<OwnedFd> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for both ends of the pipe
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe just opened both ends and nothing else owns them
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    // SAFETY: stderr is open for the whole process
+    let original = unsafe { libc::dup(libc::STDERR_FILENO) };
+    if original < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: dup returned a new descriptor nothing else owns
+    let original = unsafe { OwnedFd::from_raw_fd(original) };
+    // SAFETY: both are open file descriptors
+    if unsafe { libc::dup2(write.as_raw_fd(), libc::STDERR_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the log ends once stderr is restored and children holding it exited
+    thread::spawn(move || {
+        for line in BufReader::new(read).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let mut state = lock(&state);
+            if state.log.len() == LOG_KEEP {
+                state.log.remove(0);
+            }
+            state.log.push(line);
+        }
+        lock(&state).log_closed = true;
+    });
+    Ok(original)
+}
+
+/// Gives the normal screen and the cursor back
+fn leave_screen(screen: &mut Terminal<CrosstermBackend<Stdout>>) {
+    let _ = execute!(screen.backend_mut(), LeaveAlternateScreen, Show);
+}
+
+/// Breaks `text` into lines of at most `width` characters, at spaces where
+/// possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_string();
+        let length = line.chars().count();
+        if length > 0 && length + 1 + word.chars().count() <= width {
+            line.push(' ');
+            line.push_str(&word);
+            continue;
+        }
+        if length > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        // words longer than a line are cut
+        while word.chars().count() > width {
+            let rest = word.split_off(word.char_indices().nth(width).map_or(0, |(at, _)| at));
+            lines.push(std::mem::replace(&mut word, rest));
+        }
+        line = word;
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draws the whole screen: level meter, status, transcripts and log
+fn draw(frame: &mut Frame, state: &State, paused: bool) {
+    let [level, status, transcript, log] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(LOG_LINES + 1),
+    ])
+    .areas(frame.area());
+    let speaking = state.speaking.iter().any(|&speaking| speaking);
+
+    let db = 20.0 * state.level.max(f32::MIN_POSITIVE).log10();
+    frame.render_widget(
+        LineGauge::default()
+            .label(format!("level {:>4.0} dB ", db.max(METER_FLOOR)))
+            .ratio((1.0 - db / METER_FLOOR).clamp(0.0, 1.0).into())
+            .line_set(symbols::line::THICK)
+            .filled_style(match speaking {
+                true => Style::new().green(),
+                false => Style::new(),
+            })
+            .unfilled_style(Style::new().dim()),
+        level,
+    );
+
+    let vad = match (paused, speaking) {
+        (true, _) => "paused ".yellow(),
+        (false, true) => "speech ".green(),
+        (false, false) => Span::raw("silence"),
+    };
+    let latency = state
+        .transcript
+        .last()
+        .map_or("-".to_string(), |line| format!("{:.0?}", line.latency));
+    frame.render_widget(
+        TextLine::from(vec![
+            Span::raw("vad    "),
+            vad,
+            Span::raw(format!(
+                "   queue {:<3}   latency {latency}",
+                metrics::METRICS.queue_depth()
+            )),
+        ]),
+        status,
+    );
+
+    let block = Block::new()
+        .borders(Borders::TOP)
+        .border_style(Style::new().dim());
+    let inner = block.inner(transcript);
+    frame.render_widget(block.clone(), transcript);
+    frame.render_widget(transcript_lines(state, inner), inner);
+
+    let lines = state.log[state.log.len().saturating_sub(LOG_LINES.into())..]
+        .iter()
+        .map(|line| TextLine::from(line.as_str()).dim())
+        .collect::<Vec<_>>();
+    frame.render_widget(Paragraph::new(lines).block(block), log);
+}
+
+/// The newest transcripts that fit into `area` with their latency, the
+/// partials below them
+fn transcript_lines(state: &State, area: Rect) -> Paragraph<'static> {
+    let rows = area.height as usize;
+    let text_width = (area.width as usize).saturating_sub(LATENCY_WIDTH).max(1);
+    let indent = " ".repeat(LATENCY_WIDTH);
+    let mut lines = Vec::new();
+    for line in state.transcript.iter().rev() {
+        if lines.len() >= rows {
+            break;
+        }
+        let latency = format!("{:.0?}", line.latency);
+        for (row, text) in wrap(&line.text, text_width).into_iter().enumerate().rev() {
+            lines.push(TextLine::from(match row {
+                0 => vec![
+                    format!("{latency:>width$}  ", width = LATENCY_WIDTH - 2).dim(),
+                    Span::raw(text),
+                ],
+                _ => vec![Span::raw(indent.clone()), Span::raw(text)],
+            }));
+        }
+    }
+    lines.truncate(rows);
+    lines.reverse();
+    for partial in state.partials.iter().flatten() {
+        for text in wrap(partial, text_width) {
+            lines.push(TextLine::from(vec![
+                Span::raw(indent.clone()),
+                Span::styled(text, Style::new().fg(Color::Yellow)),
+            ]));
+        }
+    }
+    let skip = lines.len().saturating_sub(rows);
+    Paragraph::new(lines.split_off(skip))
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+
+    fn screen(state: &State, paused: bool, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| draw(frame, state, paused)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn transcript(texts: &[&str]) -> State {
+        State {
+            speaking: vec![false],
+            partials: vec![None],
+            transcript: texts
+                .iter()
+                .enumerate()
+                .map(|(segment_id, text)| Line {
+                    segment_id: segment_id as u64,
+                    text: text.to_string(),
+                    latency: Duration::from_millis(300),
+                })
+                .collect(),
+            ..State::default()
+        }
+    }
+
+    #[test]
+    fn long_words_are_cut() {
+        assert_eq!(wrap("a bb ccc", 4), ["a bb", "ccc"]);
+        assert_eq!(wrap("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("", 4), [""]);
+    }
+
+    #[test]
+    fn newest_transcripts_stay_on_screen() {
+        let mut state = transcript(&["first", "second", "third"]);
+        state.partials[0] = Some("and then".to_string());
+        state.log.push("warning: something".to_string());
+        let lines = screen(&state, true, 60, 10);
+        assert!(lines[0].starts_with("level  -60 dB"));
+        assert!(lines[1].starts_with("vad    paused"));
+        assert!(lines[1].contains("latency 300ms"));
+        // three rows below the rule, the partial takes the last one
+        assert_eq!(lines[3].trim(), "300ms  second");
+        assert_eq!(lines[4].trim(), "300ms  third");
+        assert_eq!(lines[5].trim(), "and then");
+        assert_eq!(lines[7].trim(), "warning: something");
+    }
+
+    #[test]
+    fn wrapped_transcripts_keep_their_latency_on_the_first_row() {
+        let state = transcript(&["one two three four"]);
+        let lines = screen(&state, false, 20, 10);
+        assert_eq!(lines[3], " 300ms  one two");
+        assert_eq!(lines[4], "        three four");
+    }
+}
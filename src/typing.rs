@@ -0,0 +1,105 @@
+use std::env;
+use std::io;
+use std::process::{Command, Stdio};
+
+/// program injecting keystrokes for `--type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TypeBackend {
+    /// `wtype`, wayland compositors supporting virtual keyboards
+    Wtype,
+    /// `ydotool`, any session through uinput (needs a running ydotoold)
+    Ydotool,
+    /// `xdotool`, X11 sessions
+    Xdotool,
+}
+
+impl TypeBackend {
+    /// The backend fitting the running graphical session, ydotool outside of
+    /// one
+    pub fn detect() -> TypeBackend {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            TypeBackend::Wtype
+        } else if env::var_os("DISPLAY").is_some() {
+            TypeBackend::Xdotool
+        } else {
+            TypeBackend::Ydotool
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            TypeBackend::Wtype => "wtype",
+            TypeBackend::Ydotool => "ydotool",
+            TypeBackend::Xdotool => "xdotool",
+        }
+    }
+
+    fn command(self, text: &str) -> Command {
+        let mut command = Command::new(self.program());
+        match self {
+            TypeBackend::Wtype => command.arg("--"),
+            TypeBackend::Ydotool => command.args(["type", "--"]),
+            // without clearing, held modifiers turn the text into shortcuts
+            TypeBackend::Xdotool => command.args(["type", "--clearmodifiers", "--"]),
+        };
+        command.arg(text);
+        command
+    }
+}
+
+/// what gets typed after every transcript
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TypeSuffix {
+    /// a space, so consecutive transcripts read as one text
+    #[default]
+    Space,
+    /// enter, which also submits single line inputs
+    Newline,
+    /// nothing
+    None,
+}
+
+/// Types transcripts into whatever window has the keyboard focus
+pub struct Typist {
+    backend: TypeBackend,
+    suffix: TypeSuffix,
+}
+
+impl Typist {
+    pub fn new(backend: TypeBackend, suffix: TypeSuffix) -> Typist {
+        Typist { backend, suffix }
+    }
+
+    pub fn backend(&self) -> TypeBackend {
+        self.backend
+    }
+
+    /// Types `text` followed by the suffix, returns once all keystrokes are
+    /// sent
+    pub fn type_text(&self, text: &str) -> io::Result<()> {
+        let text = match self.suffix {
+            TypeSuffix::Space => format!("{text} "),
+            TypeSuffix::Newline => format!("{text}\n"),
+            TypeSuffix::None => text.to_string(),
+        };
+        let status = self
+            .backend
+            .command(&text)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not run {}: {err}", self.backend.program()),
+                )
+            })?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {status}",
+                self.backend.program()
+            )));
+        }
+        Ok(())
+    }
+}
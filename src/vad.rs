@@ -1,26 +1,63 @@
-use core::panic;
-use std::mem::{self, MaybeUninit};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait};
-use cpal::{BufferSize, Device, SampleRate, StreamConfig};
+use cpal::{
+    BufferSize, Device, Host, HostId, SampleFormat, SampleRate, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfig, SupportedStreamConfigRange,
+};
 use earshot::{VoiceActivityDetector, VoiceActivityModel, VoiceActivityProfile};
 use ringbuf::storage::Heap;
 use ringbuf::traits::{Consumer, Observer, Producer};
 use ringbuf::LocalRb;
 use samplerate::Samplerate;
-use wav_io::utils::stereo_to_mono;
 
-use crate::whisper::SAMPLE_RATE;
+use crate::events::{Warning, WarningSink};
+#[cfg(feature = "silero")]
+use crate::silero::SileroDetector;
+use crate::whisper::{MAX_WHISPER_FRAME, SAMPLE_RATE};
 
 pub const SPEECH_DETECTION_LINGER: Duration = Duration::from_millis(90);
 pub const SEGMENT_SEPARATOR_SILENCE: Duration = Duration::from_millis(240);
 
-pub const LINGER_FRAMES: usize =
-    (SPEECH_DETECTION_LINGER.as_millis() as usize * SAMPLE_RATE) / 1000 / VAD_FRAME;
-pub const SILENCE_FRAMES: usize =
-    (SEGMENT_SEPARATOR_SILENCE.as_millis() as usize * SAMPLE_RATE) / 1000 / VAD_FRAME;
+/// Default silence windows per language as `(code, end of speech ms, tail
+/// ms)`
+///
+/// Languages differ in how long speakers pause mid sentence, languages not
+/// listed here use [`SEGMENT_SEPARATOR_SILENCE`] and
+/// [`SPEECH_DETECTION_LINGER`]. The tail has to stay shorter than the end of
+/// speech silence.
+pub const LANGUAGE_SILENCE: &[(&str, u64, u64)] = &[
+    ("en", 240, 90),
+    ("de", 300, 120),
+    ("nl", 300, 120),
+    ("fi", 330, 120),
+    ("ja", 180, 60),
+    ("ko", 180, 60),
+    ("zh", 180, 60),
+    ("es", 210, 90),
+    ("it", 210, 90),
+];
+
+/// silence windows for `language` as `(end of speech, tail)`
+pub fn language_silence(language: &str) -> (Duration, Duration) {
+    LANGUAGE_SILENCE
+        .iter()
+        .find(|(code, _, _)| *code == language)
+        .map(|&(_, end, tail)| (Duration::from_millis(end), Duration::from_millis(tail)))
+        .unwrap_or((SEGMENT_SEPARATOR_SILENCE, SPEECH_DETECTION_LINGER))
+}
+
+/// amount of whole vad frames that fit into `duration`
+pub const fn duration_to_frames(duration: Duration) -> usize {
+    (duration.as_millis() as usize * SAMPLE_RATE) / 1000 / VAD_FRAME
+}
 
 /// selectable alsa buffer sizes follow a weird pattern 32 seems to work as a
 /// quantum over a wide range of buffer sizes
@@ -30,27 +67,305 @@ const ALSA_BUFFER_MIN: u32 = 32;
 /// ~30ms of audio
 pub const VAD_FRAME: usize = 480; // sample count
 
+/// amount of audio used to estimate the noise floor in rms mode
+const RMS_CALIBRATION_FRAMES: usize = SAMPLE_RATE / VAD_FRAME; // ~1s
+/// frames louder than the noise floor by this factor count as speech (~10dB)
+const RMS_THRESHOLD_FACTOR: f32 = 3.0;
+/// lower bound for the rms threshold so digital silence doesn't turn every
+/// sound into speech
+const RMS_MIN_THRESHOLD: f32 = 200.0;
+
+/// how far before the longest segment length a forced split may move back to
+/// a pause, capped at half the longest segment
+const SPLIT_SEARCH: Duration = Duration::from_secs(5);
+/// frames quieter than this fraction of the utterance's average level are
+/// pauses a long utterance can be split at (~-12dB)
+const SPLIT_ENERGY_RATIO: f32 = 0.25;
+
+/// how far the resampler output may fall behind the input (100ms), it holds
+/// back some audio for its filter
+const RESAMPLE_MAX_LAG: usize = SAMPLE_RATE / 10;
+
+/// samples at or beyond this magnitude are considered clipped
+const CLIP_LEVEL: f32 = 0.999;
+/// warn when more then this fraction of a buffer is clipped
+const CLIP_WARN_FRACTION: f32 = 0.001;
+const CLIP_WARN_INTERVAL: Duration = Duration::from_secs(1);
+const RESAMPLE_WARN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Default)]
+pub struct VadOptions {
+    pub mode: VadMode,
+    /// onnx file of the silero model, needed for [`VadMode::Silero`]
+    pub silero_model: Option<PathBuf>,
+    /// language code used to look up default silence windows in
+    /// [`LANGUAGE_SILENCE`]
+    pub language: String,
+    /// silence that ends a speech segment, overrides the language default
+    pub end_silence: Option<Duration>,
+    /// trailing silence that is still recorded after speech, overrides the
+    /// language default
+    ///
+    /// has to be shorter than the end of speech silence
+    pub tail: Option<Duration>,
+    /// audio repeated at the start of a segment when a long utterance has to
+    /// be split, so words at the cut aren't lost
+    pub overlap: Duration,
+    /// report [`VadActivity::Idle`] at this interval while there is no speech
+    pub idle_interval: Option<Duration>,
+    /// longer speech is split into several segments, defaults to
+    /// [`MAX_WHISPER_FRAME`]
+    pub max_samples: Option<NSamples>,
+    /// audio before the detected onset that is added to the segment, zero
+    /// disables it
+    ///
+    /// when enabled speech only starts after two speech frames in a row, so
+    /// single noisy frames don't open segments
+    pub onset_lookahead: Duration,
+    /// speech has to go on this long without a silent frame before a segment
+    /// starts, shorter noises are ignored. Zero starts on the first speech
+    /// frame
+    pub min_speech: Duration,
+    pub warnings: WarningSink,
+}
+
+/// how speech is told apart from silence
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum VadMode {
+    /// neural voice activity detection (earshot)
+    #[default]
+    Earshot,
+    /// energy threshold calibrated on the first second of audio
+    Rms,
+    /// silero vad, needs a build with the `silero` feature
+    Silero,
+}
+
 pub type NSamples = usize;
+/// position in the 16kHz mono stream counted in samples since the start
+pub type SampleOffset = usize;
+
+/// a finished speech window
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// stream position of the first captured frame
+    pub start: SampleOffset,
+    /// stream position right after the last captured frame
+    pub end: SampleOffset,
+    /// amount of samples that were pushed to the transcription ring
+    pub samples: NSamples,
+    /// amount of samples at the start repeating the end of the previous
+    /// segment (only non zero after a forced split)
+    pub overlap: NSamples,
+    /// index of the input the segment was heard on, see [`Vad::set_source`]
+    pub source: usize,
+}
+
+impl Segment {
+    pub fn start_time(&self) -> Duration {
+        samples_to_duration(self.start)
+    }
+
+    pub fn end_time(&self) -> Duration {
+        samples_to_duration(self.end)
+    }
+}
+
+pub fn samples_to_duration(samples: NSamples) -> Duration {
+    Duration::from_millis((samples * 1000 / SAMPLE_RATE) as u64)
+}
+
 pub enum VadStatus {
     Silence,
-    SpeechStart,
+    SpeechStart {
+        start: SampleOffset,
+        source: usize,
+    },
     Speech,
-    SpeechEnd(NSamples),
+    SpeechEnd(Segment),
+    /// no speech for the contained duration
+    Idle(Duration),
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum AudioError {
-    #[allow(dead_code)] // this is implicitly read during except via debug
+    #[error("input device unavailable: {0}")]
     InputDeviceUnavailable(String),
+    #[error("output device unavailable: {0}")]
+    OutputDeviceUnavailable(String),
+    #[error("no default input device found, select one with `listen --device` (see `devices`)")]
+    NoDefaultInputDevice,
+    /// audio of this rate can't be converted to [`SAMPLE_RATE`]
+    #[error("can't resample {rate}Hz audio: {reason}")]
+    UnsupportedSampleRate { rate: u32, reason: String },
+    /// the input stream couldn't be started or died
+    #[error("could not listen to the input device: {0}")]
+    StreamFailed(String),
+    /// reading audio from a file or stdin failed
+    #[error("could not read audio: {0}")]
+    UnreadableInput(String),
 }
 
 pub enum VadActivity {
-    SpeechStart,
-    SpeechEnd(NSamples),
+    /// speech began at stream position `start` on input `source`
+    SpeechStart {
+        start: SampleOffset,
+        source: usize,
+    },
+    SpeechEnd(Segment),
+    /// heartbeat while nobody speaks, carries how long it has been silent
+    Idle(Duration),
+    /// no more audio will follow, carries the length of the stream
+    End(Duration),
+}
+
+/// per frame speech decision
+///
+/// [`VadMode`] picks one of the builtin detectors, other models can be
+/// plugged in with [`Vad::set_detector`]
+pub trait VoiceDetector: Send {
+    fn is_speech(&mut self, frame: &[i16; VAD_FRAME]) -> bool;
+}
+
+impl VoiceDetector for VoiceActivityDetector {
+    fn is_speech(&mut self, frame: &[i16; VAD_FRAME]) -> bool {
+        self.predict_16khz(frame)
+            .expect("frame should have valid length")
+    }
+}
+
+fn builtin_detector(opt: &VadOptions) -> Result<Box<dyn VoiceDetector>, &'static str> {
+    Ok(match opt.mode {
+        VadMode::Earshot => Box::new(VoiceActivityDetector::new_with_model(
+            VoiceActivityModel::ES_ALPHA,
+            VoiceActivityProfile::VERY_AGGRESSIVE,
+        )),
+        VadMode::Rms => Box::<RmsDetector>::default(),
+        #[cfg(feature = "silero")]
+        VadMode::Silero => {
+            let model = opt
+                .silero_model
+                .as_ref()
+                .ok_or("the silero vad needs a model file")?;
+            Box::new(SileroDetector::new(model).map_err(|err| {
+                eprintln!("silero: {err}");
+                "could not load the silero vad"
+            })?)
+        }
+        #[cfg(not(feature = "silero"))]
+        VadMode::Silero => {
+            return Err("the silero vad needs a build with the `silero` feature");
+        }
+    })
+}
+
+/// Takes every frame for speech, so segments only end when the pause switch
+/// is set (see [`Vad::set_pause_switch`]) or they grow too long
+///
+/// for push to talk, where holding a key decides what is speech
+pub struct AlwaysSpeech;
+
+impl VoiceDetector for AlwaysSpeech {
+    fn is_speech(&mut self, _frame: &[i16; VAD_FRAME]) -> bool {
+        true
+    }
+}
+
+#[derive(Default)]
+struct RmsDetector {
+    /// `None` while calibrating
+    threshold: Option<f32>,
+    calibration_sum: f32,
+    calibration_frames: usize,
+}
+
+impl VoiceDetector for RmsDetector {
+    fn is_speech(&mut self, frame: &[i16; VAD_FRAME]) -> bool {
+        let rms = rms(frame);
+        let Some(threshold) = self.threshold else {
+            self.calibration_sum += rms;
+            self.calibration_frames += 1;
+            if self.calibration_frames == RMS_CALIBRATION_FRAMES {
+                let noise_floor = self.calibration_sum / RMS_CALIBRATION_FRAMES as f32;
+                let threshold = (noise_floor * RMS_THRESHOLD_FACTOR).max(RMS_MIN_THRESHOLD);
+                eprintln!(
+                    "rms vad calibrated: noise floor {noise_floor:.0}, threshold {threshold:.0}"
+                );
+                self.threshold = Some(threshold);
+            }
+            return false;
+        };
+        rms >= threshold
+    }
+}
+
+fn rms(samples: &[i16]) -> f32 {
+    let sum: f32 = samples.iter().map(|&s| (s as f32) * (s as f32)).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// see [`Vad::set_activity_callback`]
+pub type ActivityCallback = Box<dyn FnMut(&VadActivity) + Send>;
+/// see [`Vad::set_frame_callback`]
+pub type FrameCallback = Box<dyn FnMut(FrameTrace) + Send>;
+
+/// detector state after a single frame, for debugging
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTrace {
+    /// position of the frame in the stream counted in vad frames
+    pub index: usize,
+    /// whether the detector heard speech in this frame
+    pub is_speech: bool,
+    /// silent frames in a row up to and including this one
+    pub silence_frames: usize,
+    /// whether the frame belongs to a speech segment
+    pub in_speech: bool,
 }
 
 pub struct Vad {
-    vad: VoiceActivityDetector,
+    detector: Box<dyn VoiceDetector>,
+    /// silent frames that end a speech segment
+    end_frames: usize,
+    /// silent frames after speech that are still recorded
+    tail_frames: usize,
+    on_activity: Option<ActivityCallback>,
+    on_frame: Option<FrameCallback>,
+    warnings: WarningSink,
+    /// all audio is discarded while this is set
+    paused: Option<Arc<AtomicBool>>,
+    /// stamped into every [`Segment`]
+    source: usize,
+    /// receive a copy of everything handed to [`Vad::input`]
+    input_taps: Vec<Sender<Vec<i16>>>,
+    /// samples between idle reports, `None` disables them
+    idle_samples: Option<NSamples>,
+    /// samples since speech last ended
+    silence_samples: NSamples,
+    /// value of `silence_samples` at which the next idle report is due
+    next_idle: NSamples,
+    /// segments are split before reaching this length
+    max_samples: NSamples,
+    /// samples repeated after a forced split
+    overlap_samples: NSamples,
+    /// forced splits only move back to pauses this close to `max_samples`
+    split_search: NSamples,
+    /// the most recent pause in the current segment as the segment's samples
+    /// up to it and the stream position after it
+    pause: Option<(NSamples, SampleOffset)>,
+    /// summed rms of the frames recorded in the current utterance
+    energy: f32,
+    energy_frames: usize,
+    /// the last `overlap_samples` recorded samples
+    recent: VecDeque<i16>,
+    /// silent frames kept in front of an onset, zero disables the lookahead
+    lookahead_frames: usize,
+    /// the last `lookahead_frames` silent frames with their stream position
+    lookahead: VecDeque<(SampleOffset, [i16; VAD_FRAME])>,
+    /// speech frames in a row that start a segment
+    onset_frames: usize,
+    /// the first speech frames waiting for enough others to confirm the
+    /// onset
+    onset_candidate: Vec<(SampleOffset, [i16; VAD_FRAME])>,
     ring: LocalRb<Heap<i16>>,
     // TODO: build control structure
     /// reading this while `last_speech_frame = None` is undefined behavior
@@ -58,87 +373,297 @@ pub struct Vad {
     last_speech_frame: Option<usize>,
     /// reading this while `last_speech_frame = None` is undefined behavior
     current_speech_samples: NSamples,
+    /// amount of samples that went through the detector so far
+    processed_samples: SampleOffset,
+    /// reading this while `last_speech_frame = None` is undefined behavior
+    segment_start: SampleOffset,
+    /// reading this while `last_speech_frame = None` is undefined behavior
+    segment_end: SampleOffset,
+    /// reading this while `last_speech_frame = None` is undefined behavior
+    segment_overlap: NSamples,
 }
 
 impl Vad {
-    pub fn try_new(config: &StreamConfig) -> Result<Vad, &'static str> {
-        let BufferSize::Fixed(buffer_size) = config.buffer_size else {
-            return Err("config doesnt allow safe vad setup");
+    pub fn try_new(config: &StreamConfig, opt: VadOptions) -> Result<Vad, &'static str> {
+        // with the host default the buffer size is unknown until audio arrives,
+        // the ring grows in `input` if needed
+        let buffer_size = match config.buffer_size {
+            BufferSize::Fixed(buffer_size) => buffer_size as usize,
+            BufferSize::Default => VAD_FRAME,
         };
-        let ring = LocalRb::new((buffer_size * 2).max(VAD_FRAME as u32 * 2) as usize);
+        let (end_silence, tail) = language_silence(&opt.language);
+        let end_frames = duration_to_frames(opt.end_silence.unwrap_or(end_silence));
+        let tail_frames = duration_to_frames(opt.tail.unwrap_or(tail));
+        if tail_frames >= end_frames {
+            return Err("the recorded tail has to be shorter than the end of speech silence");
+        }
+        let idle_samples = opt
+            .idle_interval
+            .map(|interval| (interval.as_millis() as usize * SAMPLE_RATE / 1000).max(VAD_FRAME));
+        let overlap_samples = opt.overlap.as_millis() as usize * SAMPLE_RATE / 1000;
+        let max_samples = opt.max_samples.unwrap_or(MAX_WHISPER_FRAME);
+        if overlap_samples * 2 >= max_samples {
+            return Err("the overlap has to be shorter than half of the longest segment");
+        }
+        let lookahead_frames = match opt.onset_lookahead.is_zero() {
+            true => 0,
+            false => duration_to_frames(opt.onset_lookahead).max(1),
+        };
+        let mut onset_frames = duration_to_frames(opt.min_speech).max(1);
+        if !opt.onset_lookahead.is_zero() {
+            onset_frames = onset_frames.max(2);
+        }
+        let ring = LocalRb::new((buffer_size * 2).max(VAD_FRAME * 2));
         Ok(Vad {
-            vad: VoiceActivityDetector::new_with_model(
-                VoiceActivityModel::ES_ALPHA,
-                VoiceActivityProfile::VERY_AGGRESSIVE,
-            ),
+            detector: builtin_detector(&opt)?,
+            end_frames,
+            tail_frames,
+            on_activity: None,
+            on_frame: None,
+            warnings: opt.warnings,
+            paused: None,
+            source: 0,
+            input_taps: Vec::new(),
+            idle_samples,
+            silence_samples: 0,
+            next_idle: idle_samples.unwrap_or(0),
+            max_samples,
+            overlap_samples,
+            split_search: (duration_to_frames(SPLIT_SEARCH) * VAD_FRAME).min(max_samples / 2),
+            pause: None,
+            energy: 0.0,
+            energy_frames: 0,
+            recent: VecDeque::with_capacity(overlap_samples),
+            lookahead_frames,
+            lookahead: VecDeque::with_capacity(lookahead_frames),
+            onset_frames,
+            onset_candidate: Vec::with_capacity(onset_frames),
             ring,
             current_frame: 0,
             last_speech_frame: None,
             current_speech_samples: 0,
+            processed_samples: 0,
+            segment_start: 0,
+            segment_end: 0,
+            segment_overlap: 0,
         })
     }
 
+    /// silence that ends a segment, rounded to whole vad frames
+    pub fn end_silence(&self) -> Duration {
+        samples_to_duration(self.end_frames * VAD_FRAME)
+    }
+
+    /// trailing silence still recorded after speech, rounded to whole vad
+    /// frames
+    pub fn tail(&self) -> Duration {
+        samples_to_duration(self.tail_frames * VAD_FRAME)
+    }
+
+    /// Registers a callback that fires as soon as speech starts or ends
+    ///
+    /// The callback runs on the audio thread right before the event is queued
+    /// for transcription, independent of how busy whisper is. It has to be
+    /// cheap and must never block, otherwise audio gets dropped.
+    pub fn set_activity_callback(&mut self, callback: impl FnMut(&VadActivity) + Send + 'static) {
+        self.on_activity = Some(Box::new(callback));
+    }
+
+    /// Registers a callback that sees the decision for every single frame
+    ///
+    /// Runs on the audio thread like [`Vad::set_activity_callback`], so it has
+    /// to be cheap as well.
+    pub fn set_frame_callback(&mut self, callback: impl FnMut(FrameTrace) + Send + 'static) {
+        self.on_frame = Some(Box::new(callback));
+    }
+
+    fn trace_frame(&mut self, frame_start: SampleOffset, is_speech: bool) {
+        let silence_frames = match (is_speech, self.last_speech_frame) {
+            (true, _) => 0,
+            (false, Some(last_speech_frame)) => self.current_frame + 1 - last_speech_frame,
+            (false, None) => self.silence_samples / VAD_FRAME + 1,
+        };
+        let in_speech =
+            is_speech || (self.last_speech_frame.is_some() && silence_frames < self.end_frames);
+        if let Some(callback) = self.on_frame.as_mut() {
+            callback(FrameTrace {
+                index: frame_start / VAD_FRAME,
+                is_speech,
+                silence_frames,
+                in_speech,
+            });
+        }
+    }
+
+    fn notify(&mut self, activity: &VadActivity) {
+        if let Some(callback) = self.on_activity.as_mut() {
+            callback(activity);
+        }
+    }
+
+    /// Makes [`audio_loop`] discard all audio while `paused` is set
+    pub fn set_pause_switch(&mut self, paused: Arc<AtomicBool>) {
+        self.paused = Some(paused);
+    }
+
+    /// Replaces the detector picked by [`VadOptions::mode`]
+    pub fn set_detector(&mut self, detector: impl VoiceDetector + 'static) {
+        self.detector = Box::new(detector);
+    }
+
+    /// Marks the segments of this detector as coming from input `source`
+    ///
+    /// used when several devices are transcribed separately, defaults to 0
+    pub fn set_source(&mut self, source: usize) {
+        self.source = source;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+            .as_ref()
+            .is_some_and(|paused| paused.load(Ordering::Relaxed))
+    }
+
+    /// Discards `samples` and everything not yet processed, ending the open
+    /// segment if there is one
+    fn skip(&mut self, samples: NSamples) -> Option<Segment> {
+        self.processed_samples += self.ring.occupied_len() + samples;
+        self.ring.clear();
+        self.lookahead.clear();
+        self.onset_candidate.clear();
+        self.last_speech_frame.take()?;
+        self.recent.clear();
+        Some(self.segment())
+    }
+
+    /// Ends the open segment if there is one, for streams that won't deliver
+    /// any more audio
+    ///
+    /// audio not filling a whole frame is dropped, [`end_speech`] and
+    /// [`end_stream`] also report the segment
+    pub fn flush(&mut self) -> Option<Segment> {
+        self.ring.clear();
+        self.lookahead.clear();
+        self.onset_candidate.clear();
+        self.last_speech_frame.take()?;
+        self.recent.clear();
+        Some(self.segment())
+    }
+
+    /// Sends a copy of all audio entering the detector to `tap`
+    pub fn add_input_tap(&mut self, tap: Sender<Vec<i16>>) {
+        self.input_taps.push(tap);
+    }
+
     pub fn input(&mut self, samples: &[i16]) {
-        if self.ring.push_slice(samples) != samples.len() {
-            eprintln!("warning: internal buffer full, some audio was dropped");
+        self.input_taps
+            .retain(|tap| tap.send(samples.to_vec()).is_ok());
+        if self.ring.vacant_len() < samples.len() {
+            // the host delivers bigger buffers than announced
+            self.grow((self.ring.occupied_len() + samples.len()) * 2);
         }
+        self.ring.push_slice(samples);
+    }
+
+    fn grow(&mut self, capacity: usize) {
+        let mut ring = LocalRb::new(capacity);
+        ring.push_iter(self.ring.pop_iter());
+        self.ring = ring;
     }
 
     pub fn output_to(&mut self, final_ring: &mut impl Producer<Item = i16>) -> VadStatus {
         while self.ring.occupied_len() >= VAD_FRAME {
-            let mut frame: [MaybeUninit<i16>; VAD_FRAME] =
-                [const { MaybeUninit::uninit() }; VAD_FRAME];
-            if VAD_FRAME != self.ring.pop_slice_uninit(&mut frame) {
-                panic!("vad ring should have enough data for at least one frame");
-            }
-            // SAFETY: this is safe because the panic above makes sure that all i16 were
-            // initialized
-            let frame =
-                unsafe { mem::transmute::<[MaybeUninit<i16>; VAD_FRAME], [i16; VAD_FRAME]>(frame) };
+            // the loop condition makes sure the whole frame gets filled
+            let mut frame = [0i16; VAD_FRAME];
+            self.ring.pop_slice(&mut frame);
+            let frame_start = self.processed_samples;
+            self.processed_samples += VAD_FRAME;
 
-            let is_speech = self
-                .vad
-                .predict_16khz(&frame)
-                .expect("frame should have valid length");
+            let is_speech = self.detector.is_speech(&frame);
+            if self.on_frame.is_some() {
+                self.trace_frame(frame_start, is_speech);
+            }
 
             let Some(last_speech_frame) = self.last_speech_frame.as_mut() else {
                 // we are inside a silence window
                 if !is_speech {
+                    // speech too short to start a segment was noise, it only
+                    // counts as lookahead
+                    let candidate = mem::take(&mut self.onset_candidate);
+                    if self.lookahead_frames > 0 {
+                        for frame in candidate {
+                            self.remember_lookahead(frame);
+                        }
+                        self.remember_lookahead((frame_start, frame));
+                    }
+                    self.silence_samples += VAD_FRAME;
+                    match self.idle_samples {
+                        Some(interval) if self.silence_samples >= self.next_idle => {
+                            self.next_idle += interval;
+                            return VadStatus::Idle(samples_to_duration(self.silence_samples));
+                        }
+                        _ => continue,
+                    }
+                }
+                self.onset_candidate.push((frame_start, frame));
+                if self.onset_candidate.len() < self.onset_frames {
+                    // wait for more speech frames to confirm the onset
                     continue;
                 }
-                // speech just started
-                let n = final_ring.push_slice(&frame);
-                if n != frame.len() {
-                    eprintln!("transcription audio ring was full, dropped some audio");
+                // speech just started, the lookahead goes in first
+                let mut frames: Vec<_> = self.lookahead.drain(..).collect();
+                frames.append(&mut self.onset_candidate);
+                self.segment_start = frames[0].0;
+                self.current_speech_samples = 0;
+                for (_, frame) in &frames {
+                    let n = final_ring.push_slice(frame);
+                    self.check_dropped(frame.len() - n);
+                    self.remember(&frame[..n]);
+                    self.current_speech_samples += n;
                 }
-
+                self.silence_samples = 0;
                 self.last_speech_frame = Some(0);
-                self.current_speech_samples = n;
+                self.segment_overlap = 0;
+                self.pause = None;
+                self.energy = 0.0;
+                self.energy_frames = 0;
                 self.current_frame = 0;
-                return VadStatus::SpeechStart; // it's ok to return here since
-                                               // the upper level will poll
-                                               // again until `Speech`
+                self.segment_end = self.processed_samples;
+                // it's ok to return here since the upper level will poll again
+                // until `Speech`
+                return VadStatus::SpeechStart {
+                    start: self.segment_start,
+                    source: self.source,
+                };
             };
             // we are inside a speech window
             self.current_frame += 1;
             let silence_frames = self.current_frame - *last_speech_frame;
-            if !is_speech && silence_frames >= SILENCE_FRAMES {
-                // if silence for 240ms
+            if !is_speech && silence_frames >= self.end_frames {
+                // if silence for 240ms (by default)
                 self.last_speech_frame = None;
-                return VadStatus::SpeechEnd(self.current_speech_samples);
+                self.recent.clear();
+                self.silence_samples = self.end_frames * VAD_FRAME;
+                self.next_idle = self.idle_samples.unwrap_or(0);
+                return VadStatus::SpeechEnd(self.segment());
             }
 
             if is_speech {
                 *last_speech_frame = self.current_frame;
             }
-            if is_speech || silence_frames <= LINGER_FRAMES {
-                // if speech or silence <= 90ms record audio
-                let n = final_ring.push_slice(&frame);
-                if n != frame.len() {
-                    eprintln!("transcription audio ring was full, dropped some audio");
+            if is_speech || silence_frames <= self.tail_frames {
+                // if speech or silence <= tail (90ms by default) record audio
+                if self.current_speech_samples + VAD_FRAME >= self.max_samples {
+                    // whisper can't take any more audio
+                    let segment = match self.pause {
+                        Some((samples, _)) if samples + self.split_search >= self.max_samples => {
+                            self.split_at_pause(final_ring, &frame, is_speech)
+                        }
+                        _ => self.split(final_ring, &frame),
+                    };
+                    return VadStatus::SpeechEnd(segment);
                 }
-
-                self.current_speech_samples += n;
+                self.record(final_ring, &frame, is_speech);
             }
         }
         match self.last_speech_frame {
@@ -146,44 +671,303 @@ impl Vad {
             None => VadStatus::Silence,
         }
     }
+
+    fn check_dropped(&self, samples: NSamples) {
+        if samples > 0 {
+            self.warnings.emit(Warning::RingFull { samples });
+        }
+    }
+
+    fn segment(&self) -> Segment {
+        Segment {
+            start: self.segment_start,
+            end: self.segment_end,
+            samples: self.current_speech_samples,
+            overlap: self.segment_overlap,
+            source: self.source,
+        }
+    }
+
+    /// Adds `frame` to the current segment and notes it as a pause when it's
+    /// silent or quiet compared to the rest of the utterance
+    fn record(
+        &mut self,
+        final_ring: &mut impl Producer<Item = i16>,
+        frame: &[i16],
+        is_speech: bool,
+    ) {
+        let n = final_ring.push_slice(frame);
+        self.check_dropped(frame.len() - n);
+        self.remember(&frame[..n]);
+        self.current_speech_samples += n;
+        self.segment_end = self.processed_samples;
+
+        let level = rms(frame);
+        self.energy += level;
+        self.energy_frames += 1;
+        let average = self.energy / self.energy_frames as f32;
+        if !is_speech || level < average * SPLIT_ENERGY_RATIO {
+            self.pause = Some((self.current_speech_samples, self.processed_samples));
+        }
+    }
+
+    /// Keeps the tail of the recorded audio around for the next forced split
+    fn remember(&mut self, samples: &[i16]) {
+        self.recent.extend(samples);
+        let excess = self.recent.len().saturating_sub(self.overlap_samples);
+        self.recent.drain(..excess);
+    }
+
+    fn remember_lookahead(&mut self, frame: (SampleOffset, [i16; VAD_FRAME])) {
+        self.lookahead.push_back(frame);
+        if self.lookahead.len() > self.lookahead_frames {
+            self.lookahead.pop_front();
+        }
+    }
+
+    /// Ends the current segment while speech is still ongoing and starts the
+    /// next one with the overlap followed by `frame`
+    fn split(&mut self, final_ring: &mut impl Producer<Item = i16>, frame: &[i16]) -> Segment {
+        let segment = self.segment();
+        let overlap = self.recent.make_contiguous().to_vec();
+        let pushed = final_ring.push_slice(&overlap);
+        let n = final_ring.push_slice(frame);
+        self.check_dropped(overlap.len() + frame.len() - pushed - n);
+        self.remember(&frame[..n]);
+        self.current_speech_samples = pushed + n;
+        self.segment_overlap = pushed;
+        self.segment_start = self.segment_end.saturating_sub(overlap.len());
+        self.segment_end = self.processed_samples;
+        self.pause = None;
+        segment
+    }
+
+    /// Ends the current segment at the last pause, the audio recorded after
+    /// it stays in the ring and starts the next segment followed by `frame`
+    ///
+    /// nothing is cut in the middle of a word, so no overlap is repeated
+    fn split_at_pause(
+        &mut self,
+        final_ring: &mut impl Producer<Item = i16>,
+        frame: &[i16],
+        is_speech: bool,
+    ) -> Segment {
+        let (samples, end) = self.pause.take().expect("splits at pauses need a pause");
+        let segment = Segment {
+            end,
+            samples,
+            ..self.segment()
+        };
+        self.current_speech_samples -= samples;
+        self.segment_overlap = 0;
+        self.segment_start = end;
+        self.record(final_ring, frame, is_speech);
+        segment
+    }
+}
+
+/// Warns (throttled) when the input is overdriven
+pub struct ClipDetector {
+    last_warning: Option<Instant>,
+    warnings: WarningSink,
+}
+
+impl ClipDetector {
+    pub fn new(warnings: WarningSink) -> ClipDetector {
+        ClipDetector {
+            last_warning: None,
+            warnings,
+        }
+    }
+
+    pub fn check(&mut self, data: &[f32]) {
+        if data.is_empty() {
+            return;
+        }
+        let clipped = data.iter().filter(|s| s.abs() >= CLIP_LEVEL).count();
+        let fraction = clipped as f32 / data.len() as f32;
+        if fraction <= CLIP_WARN_FRACTION {
+            return;
+        }
+        if self
+            .last_warning
+            .is_some_and(|last| last.elapsed() < CLIP_WARN_INTERVAL)
+        {
+            return;
+        }
+        self.last_warning = Some(Instant::now());
+        self.warnings.emit(Warning::Clipping { fraction });
+    }
+}
+
+/// Averages interleaved audio into mono
+///
+/// Buffers don't have to hold whole frames, a trailing partial frame is kept
+/// and completed by the next buffer so channels never get mixed up.
+pub struct Downmixer {
+    channels: usize,
+    /// samples of the last incomplete frame
+    partial: Vec<f32>,
+}
+
+impl Downmixer {
+    pub fn new(channels: u16) -> Downmixer {
+        assert!(channels > 0, "audio needs at least one channel");
+        Downmixer {
+            channels: channels as usize,
+            partial: Vec::with_capacity(channels as usize),
+        }
+    }
+
+    pub fn process<'a>(&mut self, data: &'a [f32]) -> Cow<'a, [f32]> {
+        if self.channels == 1 {
+            return Cow::Borrowed(data);
+        }
+        self.partial.extend_from_slice(data);
+        let whole = self.partial.len() - self.partial.len() % self.channels;
+        let mono = self.partial[..whole]
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+            .collect();
+        self.partial.drain(..whole);
+        Cow::Owned(mono)
+    }
+}
+
+/// Converts mono audio to [`SAMPLE_RATE`]
+pub struct Resampler {
+    inner: Samplerate,
+    /// output samples per input sample
+    ratio: f64,
+    /// samples passed in so far
+    consumed: usize,
+    /// samples handed out so far
+    produced: usize,
+    warnings: WarningSink,
+    last_warning: Option<Instant>,
+    /// input samples dropped since the last warning
+    failed: usize,
+}
+
+impl Resampler {
+    /// Resamples `mono`, audio the resampler rejects is dropped with a
+    /// (throttled) warning instead of ending the stream
+    pub fn process(&mut self, mono: &[f32]) -> Vec<f32> {
+        if mono.is_empty() {
+            return Vec::new();
+        }
+        let resampled = match self.inner.process(mono) {
+            Ok(resampled) => resampled,
+            Err(_) => {
+                self.failed += mono.len();
+                self.warn_failed();
+                return Vec::new();
+            }
+        };
+        self.consumed += mono.len();
+        self.produced += resampled.len();
+        let expected = self.consumed as f64 * self.ratio;
+        debug_assert!(
+            self.produced as f64 <= expected.ceil(),
+            "resampler produced more audio than it was given"
+        );
+        debug_assert!(
+            expected - self.produced as f64 <= RESAMPLE_MAX_LAG as f64,
+            "resampler output is lagging behind by {} samples",
+            expected as usize - self.produced
+        );
+        resampled
+    }
+
+    fn warn_failed(&mut self) {
+        if self
+            .last_warning
+            .is_some_and(|last| last.elapsed() < RESAMPLE_WARN_INTERVAL)
+        {
+            return;
+        }
+        self.last_warning = Some(Instant::now());
+        let samples = mem::take(&mut self.failed);
+        self.warnings.emit(Warning::ResampleFailed { samples });
+    }
 }
 
 pub fn audio_loop(
     data: &[f32],
-    channels: u16,
-    resample_from: &Option<Samplerate>,
+    downmix: &mut Downmixer,
+    resample_from: &mut Option<Resampler>,
     ring_buffer: &mut impl Producer<Item = i16>,
     vad: &mut Vad,
     activity: &mut Sender<VadActivity>,
+    clipping: Option<&mut ClipDetector>,
 ) {
-    let data = match channels {
-        1 => data,
-        2 => &stereo_to_mono(data.to_vec()),
-        n => panic!("configs with {n} channels are not supported"),
-    };
+    if let Some(clipping) = clipping {
+        clipping.check(data);
+    }
+    let data = to_mono(data, downmix, resample_from);
+    let data = wav_io::convert_samples_f32_to_i16(&data.to_vec());
+    detect(&data, ring_buffer, vad, activity);
+}
 
-    let data = match resample_from {
+/// Converts device audio to [`SAMPLE_RATE`] mono
+pub fn to_mono<'a>(
+    data: &'a [f32],
+    downmix: &mut Downmixer,
+    resample_from: &mut Option<Resampler>,
+) -> Cow<'a, [f32]> {
+    // downmix first, the resampler only handles mono audio
+    let data = downmix.process(data);
+    match resample_from {
         None => data,
-        Some(resampler) => &resampler.process(data).expect("should be able to resample"),
-    };
-    let data = wav_io::convert_samples_f32_to_i16(&data.to_vec());
+        Some(resampler) => Cow::Owned(resampler.process(&data)),
+    }
+}
 
-    vad.input(&data);
+/// Runs 16kHz mono audio through `vad`, queueing speech into `ring_buffer`
+/// and reporting activity on `activity`
+pub fn detect(
+    data: &[i16],
+    ring_buffer: &mut impl Producer<Item = i16>,
+    vad: &mut Vad,
+    activity: &mut Sender<VadActivity>,
+) {
+    if vad.is_paused() {
+        // whatever was said before pausing still gets transcribed
+        if let Some(segment) = vad.skip(data.len()) {
+            let event = VadActivity::SpeechEnd(segment);
+            vad.notify(&event);
+            let _ = activity.send(event);
+        }
+        return;
+    }
+    vad.input(data);
     loop {
         let status = vad.output_to(ring_buffer);
         match status {
             VadStatus::Silence => (),
             VadStatus::Speech => (),
-            VadStatus::SpeechEnd(samples) => {
+            VadStatus::SpeechEnd(segment) => {
+                let event = VadActivity::SpeechEnd(segment);
+                vad.notify(&event);
+                // can safely drop the error case here as it only happens when the receiver has
+                // hung up (which means the stream is bound to stop soon too)
+                let _ = activity.send(event);
+                continue; // make sure we run this input to completion
+            }
+            VadStatus::Idle(silence) => {
+                let event = VadActivity::Idle(silence);
+                vad.notify(&event);
                 // can safely drop the error case here as it only happens when the receiver has
                 // hung up (which means the stream is bound to stop soon too)
-                let _ = activity.send(VadActivity::SpeechEnd(samples));
+                let _ = activity.send(event);
                 continue; // make sure we run this input to completion
             }
-            VadStatus::SpeechStart => {
+            VadStatus::SpeechStart { start, source } => {
+                let event = VadActivity::SpeechStart { start, source };
+                vad.notify(&event);
                 // can safely drop the error case here as it only happens when the receiver has
                 // hung up (which means the stream is bound to stop soon too)
-                let _ = activity.send(VadActivity::SpeechStart);
+                let _ = activity.send(event);
                 continue; // make sure we run this input to completion
             }
         }
@@ -191,47 +975,169 @@ pub fn audio_loop(
     }
 }
 
-pub fn get_microphone_by_name(name: &str) -> Result<(Device, StreamConfig), AudioError> {
-    let host = cpal::default_host();
-    let mut devices = host.input_devices().unwrap();
-    if let Some(device) = devices.find(|device| device.name().unwrap() == name) {
-        let config = device
+/// Ends speech still going on in a last segment, for detectors that stop
+/// getting audio while another one reports the end of the stream
+pub fn end_speech(vad: &mut Vad, activity: &mut Sender<VadActivity>) {
+    if let Some(segment) = vad.flush() {
+        let event = VadActivity::SpeechEnd(segment);
+        vad.notify(&event);
+        // the receiver may already be gone if it stopped early (`--once`)
+        let _ = activity.send(event);
+    }
+}
+
+/// Finalizes a stream that won't deliver any more audio
+///
+/// speech still going on ends in a last segment, then [`VadActivity::End`] is
+/// sent
+pub fn end_stream(vad: &mut Vad, activity: &mut Sender<VadActivity>) {
+    end_speech(vad, activity);
+    let event = VadActivity::End(samples_to_duration(vad.processed_samples));
+    vad.notify(&event);
+    // the receiver may already be gone if it stopped early (`--once`)
+    let _ = activity.send(event);
+}
+
+/// The host named `name` (case insensitive), the default host if `name` is
+/// `None` or that host can't be used
+pub fn pick_host(name: Option<&str>) -> Host {
+    let Some(name) = name else {
+        return cpal::default_host();
+    };
+    let id = cpal::ALL_HOSTS
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name));
+    match id.map(|&id| cpal::host_from_id(id)) {
+        Some(Ok(host)) => host,
+        Some(Err(err)) => {
+            eprintln!("warning: audio host '{name}' is unavailable ({err}), using the default");
+            cpal::default_host()
+        }
+        None => {
+            eprintln!(
+                "warning: audio host '{name}' isn't supported by this build, using the default"
+            );
+            cpal::default_host()
+        }
+    }
+}
+
+/// Name of the input device the host would pick by default
+pub fn default_input_device_name(host: &Host) -> Result<String, AudioError> {
+    host.default_input_device()
+        .and_then(|device| device.name().ok())
+        .ok_or(AudioError::NoDefaultInputDevice)
+}
+
+/// The devices to capture from, `default` is only asked when none were
+/// `requested`
+pub fn resolve_input_devices(
+    requested: Vec<String>,
+    default: impl FnOnce() -> Result<String, AudioError>,
+) -> Result<Vec<String>, AudioError> {
+    match requested.is_empty() {
+        false => Ok(requested),
+        true => default().map(|name| vec![name]),
+    }
+}
+
+/// Picks the config that needs the least resampling to reach [`SAMPLE_RATE`]
+///
+/// Configs delivering f32 samples come first, then the sample rate closest to
+/// [`SAMPLE_RATE`] (so 48kHz wins over 192kHz), then fewer channels.
+pub fn pick_input_config(
+    configs: impl IntoIterator<Item = SupportedStreamConfigRange>,
+) -> Option<SupportedStreamConfig> {
+    configs
+        .into_iter()
+        .map(|config| {
+            let rate =
+                (SAMPLE_RATE as u32).clamp(config.min_sample_rate().0, config.max_sample_rate().0);
+            (config, rate)
+        })
+        .min_by_key(|(config, rate)| {
+            (
+                config.sample_format() != SampleFormat::F32,
+                rate.abs_diff(SAMPLE_RATE as u32),
+                config.channels(),
+            )
+        })
+        .map(|(config, rate)| config.with_sample_rate(SampleRate(rate)))
+}
+
+/// an input device as shown by `devices`
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// whether this is the default input device of its host
+    pub default: bool,
+    /// supported sample rate ranges in Hz
+    pub sample_rates: Vec<(u32, u32)>,
+    /// supported channel counts
+    pub channels: Vec<u16>,
+}
+
+/// Describes all input devices of `host`
+///
+/// devices without a name are skipped
+pub fn list_input_devices(host: &Host) -> Vec<DeviceInfo> {
+    let default = default_input_device_name(host).ok();
+    let Ok(devices) = host.input_devices() else {
+        return Vec::new();
+    };
+    devices
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            let mut channels = device
+                .supported_input_configs()
+                .map(|configs| configs.map(|config| config.channels()).collect::<Vec<_>>())
+                .unwrap_or_default();
+            channels.sort_unstable();
+            channels.dedup();
+            Some(DeviceInfo {
+                default: default.as_ref() == Some(&name),
+                sample_rates: supported_sample_rates(&device),
+                channels,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Sample rate ranges (in Hz) the device supports for input
+pub fn supported_sample_rates(device: &Device) -> Vec<(u32, u32)> {
+    let mut rates = device
+        .supported_input_configs()
+        .map(|configs| {
+            configs
+                .map(|config| (config.min_sample_rate().0, config.max_sample_rate().0))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    rates.sort_unstable();
+    rates.dedup();
+    rates
+}
+
+pub fn get_microphone_by_name(
+    host: &Host,
+    name: &str,
+) -> Result<(Device, StreamConfig), AudioError> {
+    let mut devices = host
+        .input_devices()
+        .map_err(|err| AudioError::InputDeviceUnavailable(format!("{name}: '{err}'")))?;
+    if let Some(device) = devices.find(|device| device.name().is_ok_and(|device| device == name)) {
+        let configs = device
             .supported_input_configs()
-            .map_err(|err| AudioError::InputDeviceUnavailable(format!("{name}: '{err}'")))?
-            .next()
-            .ok_or_else(|| {
-                AudioError::InputDeviceUnavailable(format!(
-                    "{name}: 'does not have any valid input configurations'"
-                ))
-            })?;
-        let config = config
-            .try_with_sample_rate(SampleRate(SAMPLE_RATE as u32))
-            .unwrap_or_else(|| {
-                let dev_rate = if config.min_sample_rate().0 > SAMPLE_RATE as u32 {
-                    config.min_sample_rate()
-                } else {
-                    config.max_sample_rate()
-                };
-                config.with_sample_rate(dev_rate)
-            });
-        let buffer_size = BufferSize::Fixed(match config.buffer_size() {
-            cpal::SupportedBufferSize::Range { min, max } => ((config.sample_rate().0 / 30)
-                .next_multiple_of(ALSA_BUFFER_QAUANTUM)
-                .max(ALSA_BUFFER_MIN))
-            .max(*min)
-            .min(*max),
-            cpal::SupportedBufferSize::Unknown => (config.sample_rate().0 / 30)
-                .next_multiple_of(ALSA_BUFFER_QAUANTUM)
-                .max(ALSA_BUFFER_MIN),
-        });
+            .map_err(|err| AudioError::InputDeviceUnavailable(format!("{name}: '{err}'")))?;
+        let config = pick_input_config(configs).ok_or_else(|| {
+            AudioError::InputDeviceUnavailable(format!(
+                "{name}: 'does not have any valid input configurations'"
+            ))
+        })?;
+        let buffer_size = pick_buffer_size(host.id(), config.sample_rate().0, config.buffer_size());
         let sample_rate = config.sample_rate();
         let channels = config.channels();
-        if channels > 2 {
-            return Err(AudioError::InputDeviceUnavailable(format!(
-                "{} has more then two channels. Only Mono and Stereo audio is supported",
-                name
-            )));
-        }
         let config = StreamConfig {
             channels,
             sample_rate,
@@ -243,21 +1149,414 @@ pub fn get_microphone_by_name(name: &str) -> Result<(Device, StreamConfig), Audi
     }
 }
 
-pub fn get_resampler(src_rate: u32) -> Option<Samplerate> {
+/// Buffer size to ask the host for, about one vad frame (30ms) of audio
+///
+/// Only alsa gets a fixed size, the sizes it accepts follow a pattern other
+/// hosts (CoreAudio, WASAPI, ...) tend to reject, they use their default
+/// instead.
+pub fn pick_buffer_size(
+    host: HostId,
+    sample_rate: u32,
+    supported: &SupportedBufferSize,
+) -> BufferSize {
+    if host.name() != "ALSA" {
+        return BufferSize::Default;
+    }
+    let frames = (sample_rate / 30)
+        .next_multiple_of(ALSA_BUFFER_QAUANTUM)
+        .max(ALSA_BUFFER_MIN);
+    BufferSize::Fixed(match supported {
+        SupportedBufferSize::Range { min, max } => frames.max(*min).min(*max),
+        SupportedBufferSize::Unknown => frames,
+    })
+}
+
+/// Buffer sizes to try in order, starting with `preferred`, when opening a
+/// stream
+///
+/// the chosen size is a guess on hosts that don't report a range, the ladder
+/// always ends with the host default
+pub fn buffer_size_ladder(preferred: BufferSize) -> Vec<BufferSize> {
+    let mut ladder = vec![preferred];
+    if let BufferSize::Fixed(frames) = preferred {
+        if !frames.is_power_of_two() {
+            ladder.push(BufferSize::Fixed(frames.next_power_of_two()));
+        }
+        ladder.push(BufferSize::Default);
+    }
+    ladder
+}
+
+/// Builds a resampler for mono audio of `src_rate`, `None` when no resampling
+/// is needed
+pub fn get_resampler(
+    src_rate: u32,
+    warnings: WarningSink,
+) -> Result<Option<Resampler>, AudioError> {
     if src_rate != SAMPLE_RATE as u32 {
-        eprintln!(
-            "running with resampling src{:?}->dest{SAMPLE_RATE}",
-            src_rate
-        );
+        warnings.emit(Warning::Resampling { rate: src_rate });
         let resampler = Samplerate::new(
             samplerate::ConverterType::SincFastest,
             src_rate,
             SAMPLE_RATE as u32,
             1,
         )
-        .expect("should be able to build resampler");
-        Some(resampler)
+        .map_err(|err| AudioError::UnsupportedSampleRate {
+            rate: src_rate,
+            reason: err.to_string(),
+        })?;
+        Ok(Some(Resampler {
+            inner: resampler,
+            ratio: SAMPLE_RATE as f64 / src_rate as f64,
+            consumed: 0,
+            produced: 0,
+            warnings,
+            last_warning: None,
+            failed: 0,
+        }))
     } else {
-        None
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// says whether frames are speech from a script, silence once it ran out
+    struct Script(VecDeque<bool>);
+
+    impl VoiceDetector for Script {
+        fn is_speech(&mut self, _frame: &[i16; VAD_FRAME]) -> bool {
+            self.0.pop_front().unwrap_or(false)
+        }
+    }
+
+    fn options() -> VadOptions {
+        VadOptions {
+            mode: VadMode::Rms,
+            silero_model: None,
+            language: "en".to_string(),
+            end_silence: None,
+            tail: None,
+            overlap: Duration::ZERO,
+            idle_interval: None,
+            max_samples: None,
+            onset_lookahead: Duration::ZERO,
+            min_speech: Duration::ZERO,
+            warnings: WarningSink::Stderr,
+        }
+    }
+
+    fn frames(frames: usize) -> Duration {
+        samples_to_duration(frames * VAD_FRAME)
+    }
+
+    fn mono() -> StreamConfig {
+        StreamConfig {
+            channels: 1,
+            sample_rate: SampleRate(SAMPLE_RATE as u32),
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    /// runs `script` (`#` speech, `.` silence) through a detector, returns
+    /// its events
+    fn detect_script(opt: VadOptions, script: &str) -> Vec<VadActivity> {
+        let mut vad = Vad::try_new(&mono(), opt).expect("valid options");
+        vad.set_detector(Script(script.chars().map(|c| c == '#').collect()));
+        let mut ring = LocalRb::<Heap<i16>>::new(MAX_WHISPER_FRAME);
+        let (mut activity, events) = mpsc::channel();
+        detect(
+            &vec![0; script.len() * VAD_FRAME],
+            &mut ring,
+            &mut vad,
+            &mut activity,
+        );
+        events.try_iter().collect()
+    }
+
+    /// stream position speech started at, `None` if it never did
+    fn speech_start(events: &[VadActivity]) -> Option<SampleOffset> {
+        events.iter().find_map(|event| match event {
+            VadActivity::SpeechStart { start, .. } => Some(*start),
+            _ => None,
+        })
+    }
+
+    /// the first finished segment, `None` if speech never ended
+    fn speech_end(events: &[VadActivity]) -> Option<Segment> {
+        events.iter().find_map(|event| match event {
+            VadActivity::SpeechEnd(segment) => Some(*segment),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn tail_has_to_be_shorter_than_the_end_silence() {
+        let opt = |tail| VadOptions {
+            end_silence: Some(frames(8)),
+            tail: Some(tail),
+            ..options()
+        };
+        assert!(Vad::try_new(&mono(), opt(frames(8))).is_err());
+        assert!(Vad::try_new(&mono(), opt(frames(7))).is_ok());
+        // partial frames don't count
+        assert_eq!(duration_to_frames(Duration::from_millis(89)), 2);
+        assert_eq!(duration_to_frames(Duration::from_millis(90)), 3);
+    }
+
+    #[test]
+    fn tail_frames_are_recorded_after_speech() {
+        for tail in [0, 3, 7] {
+            let opt = VadOptions {
+                end_silence: Some(frames(8)),
+                tail: Some(frames(tail)),
+                ..options()
+            };
+            let segment = speech_end(&detect_script(opt, "..###..............")).unwrap();
+            assert_eq!(segment.samples, (3 + tail) * VAD_FRAME);
+            assert_eq!(segment.start, 2 * VAD_FRAME);
+        }
+    }
+
+    #[test]
+    fn forced_splits_repeat_the_overlap() {
+        let opt = VadOptions {
+            max_samples: Some(20 * VAD_FRAME),
+            overlap: frames(3),
+            ..options()
+        };
+        let segments: Vec<Segment> = detect_script(opt, &"#".repeat(50))
+            .into_iter()
+            .filter_map(|event| match event {
+                VadActivity::SpeechEnd(segment) => Some(segment),
+                _ => None,
+            })
+            .collect();
+        assert!(segments.len() >= 2);
+        assert_eq!(segments[0].overlap, 0);
+        assert_eq!(segments[1].overlap, 3 * VAD_FRAME);
+        assert_eq!(segments[1].start, segments[0].end - 3 * VAD_FRAME);
+    }
+
+    #[test]
+    fn onset_lookahead_captures_frames_before_speech() {
+        let script = "......####...............";
+        let without = speech_end(&detect_script(options(), script)).unwrap();
+        let opt = VadOptions {
+            onset_lookahead: frames(2),
+            ..options()
+        };
+        let with = speech_end(&detect_script(opt, script)).unwrap();
+        assert_eq!(with.samples, without.samples + 2 * VAD_FRAME);
+        assert_eq!(with.start, without.start - 2 * VAD_FRAME);
+    }
+
+    #[test]
+    fn onset_lookahead_debounces_single_frames() {
+        let opt = VadOptions {
+            onset_lookahead: frames(2),
+            ..options()
+        };
+        assert_eq!(
+            speech_start(&detect_script(opt.clone(), "..#.#.#......")),
+            None
+        );
+        assert_eq!(
+            speech_start(&detect_script(opt, "..#.##......")),
+            Some(2 * VAD_FRAME)
+        );
+    }
+
+    #[test]
+    fn speech_running_to_the_end_is_flushed() {
+        let mut vad = Vad::try_new(&mono(), options()).expect("valid options");
+        vad.set_detector(Script("..#####".chars().map(|c| c == '#').collect()));
+        let mut ring = LocalRb::<Heap<i16>>::new(MAX_WHISPER_FRAME);
+        let (mut activity, events) = mpsc::channel();
+        // the last frame isn't complete
+        detect(&[0; 7 * VAD_FRAME - 10], &mut ring, &mut vad, &mut activity);
+        assert!(speech_end(&events.try_iter().collect::<Vec<_>>()).is_none());
+        end_stream(&mut vad, &mut activity);
+        let events: Vec<_> = events.try_iter().collect();
+        let segment = speech_end(&events).expect("open speech is flushed");
+        assert_eq!(segment.start, 2 * VAD_FRAME);
+        assert_eq!(segment.samples, 4 * VAD_FRAME);
+        assert_eq!(ring.occupied_len(), 4 * VAD_FRAME);
+        assert!(matches!(events.last(), Some(VadActivity::End(_))));
+        assert!(vad.flush().is_none());
+    }
+
+    #[test]
+    fn min_speech_ignores_short_noises() {
+        let opt = VadOptions {
+            min_speech: frames(3),
+            ..options()
+        };
+        assert_eq!(
+            speech_start(&detect_script(opt.clone(), "..##.........")),
+            None
+        );
+        assert_eq!(
+            speech_start(&detect_script(opt, "..##...###......")),
+            Some(7 * VAD_FRAME)
+        );
+    }
+
+    #[test]
+    fn resampler_survives_odd_buffers() {
+        let mut resampler = get_resampler(44_100, WarningSink::Stderr)
+            .expect("44.1khz is supported")
+            .expect("44.1khz needs resampling");
+        let mut produced = 0;
+        for len in [0, 1, 0, 7, 441, 3, 4410, 0, 1] {
+            produced += resampler.process(&vec![0.1; len]).len();
+        }
+        let consumed = 1 + 7 + 441 + 3 + 4410 + 1;
+        assert!(produced as f64 <= (consumed as f64 * resampler.ratio).ceil());
+    }
+
+    #[test]
+    fn requested_devices_skip_the_default() {
+        let devices = resolve_input_devices(vec!["usb".to_string()], || {
+            panic!("the default device shouldn't be looked up")
+        });
+        assert_eq!(devices.unwrap(), ["usb"]);
+    }
+
+    #[test]
+    fn missing_default_device_points_at_list() {
+        let devices = resolve_input_devices(Vec::new(), || Ok("built-in".to_string()));
+        assert_eq!(devices.unwrap(), ["built-in"]);
+        let err = resolve_input_devices(Vec::new(), || Err(AudioError::NoDefaultInputDevice))
+            .unwrap_err();
+        assert!(err.to_string().contains("`devices`"));
+    }
+
+    #[test]
+    fn resampled_length_follows_the_ratio() {
+        for (rate, channels) in [(48_000, 2), (44_100, 1), (22_050, 6)] {
+            let mut downmix = Downmixer::new(channels);
+            let mut resampler =
+                get_resampler(rate, WarningSink::Stderr).expect("rate is supported");
+            assert!(resampler.is_some());
+            let second = vec![0.1; rate as usize * channels as usize];
+            let mut produced = 0;
+            for buffer in second.chunks(441 * channels as usize) {
+                produced += to_mono(buffer, &mut downmix, &mut resampler).len();
+            }
+            assert!(produced <= SAMPLE_RATE, "{rate}Hz: {produced}");
+            assert!(
+                produced >= SAMPLE_RATE - RESAMPLE_MAX_LAG,
+                "{rate}Hz: {produced}"
+            );
+        }
+    }
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    #[test]
+    fn input_config_needs_the_least_resampling() {
+        let picked = |configs: Vec<SupportedStreamConfigRange>| {
+            let config = pick_input_config(configs).expect("configs aren't empty");
+            (config.channels(), config.sample_rate().0)
+        };
+        // a range containing 16kHz is used at 16kHz
+        assert_eq!(
+            picked(vec![
+                range(2, 192_000, 192_000, SampleFormat::F32),
+                range(2, 8_000, 96_000, SampleFormat::F32),
+            ]),
+            (2, 16_000)
+        );
+        // 48kHz beats the extremes
+        assert_eq!(
+            picked(vec![
+                range(2, 192_000, 192_000, SampleFormat::F32),
+                range(2, 48_000, 48_000, SampleFormat::F32),
+                range(2, 96_000, 192_000, SampleFormat::F32),
+            ]),
+            (2, 48_000)
+        );
+        // f32 first, then fewer channels
+        assert_eq!(
+            picked(vec![
+                range(1, 16_000, 16_000, SampleFormat::I16),
+                range(4, 44_100, 44_100, SampleFormat::F32),
+                range(2, 44_100, 44_100, SampleFormat::F32),
+            ]),
+            (2, 44_100)
+        );
+        assert!(pick_input_config(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn downmix_carries_partial_frames_over() {
+        // every channel of frame `i` holds `i`, mixing up frames shows as halves
+        let stereo: Vec<f32> = (0..100).flat_map(|i| [i as f32; 2]).collect();
+        let mut downmix = Downmixer::new(2);
+        let mut mono = Vec::new();
+        let mut rest = &stereo[..];
+        for len in [3, 1, 0, 5, 7, 2].into_iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (buffer, tail) = rest.split_at(len.min(rest.len()));
+            mono.extend_from_slice(&downmix.process(buffer));
+            rest = tail;
+        }
+        let expected: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        assert_eq!(mono, expected);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd"))]
+    #[test]
+    fn alsa_buffer_size_follows_the_quantum() {
+        let size = |rate, supported| pick_buffer_size(HostId::Alsa, rate, &supported);
+        let unknown = SupportedBufferSize::Unknown;
+        assert_eq!(size(48_000, unknown), BufferSize::Fixed(1600));
+        assert_eq!(size(44_100, unknown), BufferSize::Fixed(1472));
+        assert_eq!(size(16_000, unknown), BufferSize::Fixed(544));
+        assert_eq!(size(0, unknown), BufferSize::Fixed(ALSA_BUFFER_MIN));
+        let range = |min, max| SupportedBufferSize::Range { min, max };
+        assert_eq!(size(48_000, range(2048, 4096)), BufferSize::Fixed(2048));
+        assert_eq!(size(48_000, range(64, 1024)), BufferSize::Fixed(1024));
+        assert_eq!(size(48_000, range(64, 8192)), BufferSize::Fixed(1600));
+    }
+
+    #[test]
+    fn buffer_size_ladder_ends_with_the_default() {
+        assert_eq!(
+            buffer_size_ladder(BufferSize::Fixed(1600)),
+            [
+                BufferSize::Fixed(1600),
+                BufferSize::Fixed(2048),
+                BufferSize::Default
+            ]
+        );
+        assert_eq!(
+            buffer_size_ladder(BufferSize::Fixed(1024)),
+            [BufferSize::Fixed(1024), BufferSize::Default]
+        );
+        assert_eq!(
+            buffer_size_ladder(BufferSize::Default),
+            [BufferSize::Default]
+        );
     }
 }
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use whisper_real_time::whisper::SAMPLE_RATE;
+
+/// audio at the start of a segment searched for the wake phrase while
+/// asleep, the rest of the segment is only transcribed after waking up
+pub const WAKE_WINDOW: usize = 3 * SAMPLE_RATE;
+
+/// Only lets transcripts through after the wake phrase was said
+///
+/// While asleep whisper only looks at the first [`WAKE_WINDOW`] of every
+/// segment. Once the phrase is heard everything is transcribed until nobody
+/// spoke for the timeout.
+pub struct WakeWord {
+    /// the phrase as normalized words
+    phrase: Vec<String>,
+    timeout: Duration,
+    /// stream position the last speech heard while awake ended at, `None`
+    /// while asleep
+    last_speech: Option<Duration>,
+}
+
+/// lowercase without punctuation, transcripts vary in both
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl WakeWord {
+    /// fails when `phrase` has no words left once punctuation is removed
+    pub fn new(phrase: &str, timeout: Duration) -> Result<WakeWord, String> {
+        let phrase = phrase
+            .split_whitespace()
+            .map(normalize)
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        if phrase.is_empty() {
+            return Err("has no words to listen for".to_string());
+        }
+        Ok(WakeWord {
+            phrase,
+            timeout,
+            last_speech: None,
+        })
+    }
+
+    pub fn is_awake(&self) -> bool {
+        self.last_speech.is_some()
+    }
+
+    /// Falls asleep if speech starting at `start` follows a silence longer
+    /// than the timeout, returns whether it did
+    pub fn sleep_before(&mut self, start: Duration) -> bool {
+        let silence = self.last_speech.map(|end| start.saturating_sub(end));
+        self.sleep_after(silence.unwrap_or_default())
+    }
+
+    /// Falls asleep if `silence` is longer than the timeout, returns whether
+    /// it did
+    pub fn sleep_after(&mut self, silence: Duration) -> bool {
+        if self.is_awake() && silence >= self.timeout {
+            self.last_speech = None;
+            return true;
+        }
+        false
+    }
+
+    /// Keeps awake for another timeout after speech ending at `end`
+    pub fn heard(&mut self, end: Duration) {
+        if self.is_awake() {
+            self.last_speech = Some(end);
+        }
+    }
+
+    /// Wakes up if `text` contains the phrase
+    pub fn listen(&mut self, text: &str, end: Duration) -> bool {
+        if self.after_phrase(text).is_some() {
+            self.last_speech = Some(end);
+            return true;
+        }
+        false
+    }
+
+    /// The part of `text` following the first mention of the phrase
+    pub fn after_phrase<'a>(&self, text: &'a str) -> Option<&'a str> {
+        // (end of the word in `text`, normalized word)
+        let words = text
+            .split_whitespace()
+            .map(|word| {
+                let end = word.as_ptr() as usize - text.as_ptr() as usize + word.len();
+                (end, normalize(word))
+            })
+            .filter(|(_, word)| !word.is_empty())
+            .collect::<Vec<_>>();
+        let at = words
+            .windows(self.phrase.len())
+            .find(|window| window.iter().map(|(_, word)| word).eq(self.phrase.iter()))?;
+        let end = at.last().expect("the phrase has words").0;
+        Some(
+            text[end..].trim_start_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_phrases_without_words() {
+        assert!(WakeWord::new("!!", Duration::ZERO).is_err());
+        assert!(WakeWord::new("  ", Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn finds_text_after_the_phrase() {
+        let wake = WakeWord::new("Hey, Computer!", Duration::ZERO).unwrap();
+        assert_eq!(
+            wake.after_phrase("oh hey computer, open the door"),
+            Some("open the door")
+        );
+        assert_eq!(wake.after_phrase("hey there computer"), None);
+    }
+}
@@ -0,0 +1,222 @@
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::{Message, WebSocket};
+
+/// clients get this long to send their upgrade request
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// clients that can't keep up for this long get disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// messages waiting for a client, it's disconnected once it falls this far
+/// behind
+const CLIENT_QUEUE: usize = 64;
+/// how long a client's thread waits for a message before looking for pings
+/// and close frames of the client
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Sends text messages to every connected WebSocket client
+///
+/// Works like [`crate::tcp::TcpBroadcaster`], browsers just can't open plain
+/// TCP connections. Every client gets a thread that does the handshake,
+/// writes the messages and answers pings and close frames, anything else the
+/// client sends is ignored.
+pub struct WebSocketBroadcaster {
+    messages: Sender<Arc<str>>,
+}
+
+impl WebSocketBroadcaster {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<WebSocketBroadcaster> {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!("serving transcripts on ws://{}", listener.local_addr()?);
+        Ok(WebSocketBroadcaster::serve(listener))
+    }
+
+    fn serve(listener: TcpListener) -> WebSocketBroadcaster {
+        let clients = Arc::new(Mutex::new(Vec::<SyncSender<Arc<str>>>::new()));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = match client {
+                    Ok(client) => client,
+                    Err(err) => {
+                        eprintln!("websocket: could not accept client: {err}");
+                        continue;
+                    }
+                };
+                // a client that is slow to upgrade doesn't hold up the others
+                let clients = accepted.clone();
+                thread::spawn(move || {
+                    let socket = match handshake(client) {
+                        Ok(socket) => socket,
+                        Err(err) => {
+                            eprintln!("websocket: could not accept client: {err}");
+                            return;
+                        }
+                    };
+                    let (messages, messages_rx) = mpsc::sync_channel(CLIENT_QUEUE);
+                    clients
+                        .lock()
+                        .expect("websocket clients poisoned")
+                        .push(messages);
+                    talk(socket, messages_rx);
+                });
+            }
+        });
+
+        let (messages, messages_rx) = mpsc::channel::<Arc<str>>();
+        thread::spawn(move || {
+            while let Ok(message) = messages_rx.recv() {
+                clients
+                    .lock()
+                    .expect("websocket clients poisoned")
+                    // a client whose queue is full fell too far behind
+                    .retain(|client| client.try_send(message.clone()).is_ok());
+            }
+        });
+        WebSocketBroadcaster { messages }
+    }
+
+    /// Queues `text` as a message for all connected clients
+    pub fn broadcast(&self, text: &str) {
+        // the writer thread only exits if this sender is gone
+        let _ = self.messages.send(text.into());
+    }
+}
+
+/// Answers the upgrade request `client` opens with
+fn handshake(client: TcpStream) -> io::Result<WebSocket<TcpStream>> {
+    client.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let socket = tungstenite::accept(client)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err.to_string()))?;
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    socket
+        .get_ref()
+        .set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+    Ok(socket)
+}
+
+/// Sends `messages` to the client behind `socket` until either side is done
+///
+/// reading lets tungstenite queue the pongs and close replies, which go out
+/// with the next flush
+fn talk(mut socket: WebSocket<TcpStream>, messages: Receiver<Arc<str>>) {
+    loop {
+        match messages.recv_timeout(POLL_INTERVAL) {
+            Ok(message) => {
+                // messages that queued up while waiting for the client go
+                // out together
+                for message in iter::once(message).chain(messages.try_iter()) {
+                    if socket.write(Message::text(&*message)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            // dropped for falling behind, or the broadcaster is gone
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return;
+            }
+        }
+        match socket.read() {
+            Ok(_) => (),
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            // closed by the client or broken
+            Err(_) => return,
+        }
+        if socket.flush().is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn connect(addr: std::net::SocketAddr) -> WebSocket<TcpStream> {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (socket, _) = tungstenite::client(format!("ws://{addr}/"), stream).unwrap();
+        socket
+    }
+
+    fn serve() -> (WebSocketBroadcaster, std::net::SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        (WebSocketBroadcaster::serve(listener), addr)
+    }
+
+    /// broadcasts until `socket` got a text message, the client may not be
+    /// registered yet right after the handshake
+    fn receive(broadcaster: &WebSocketBroadcaster, socket: &mut WebSocket<TcpStream>) -> String {
+        socket
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            broadcaster.broadcast("hello");
+            match socket.read() {
+                Ok(Message::Text(text)) => return text.to_string(),
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(_)) => (),
+                Err(err) => panic!("{err}"),
+            }
+        }
+        panic!("no message arrived");
+    }
+
+    #[test]
+    fn clients_receive_messages_and_pongs() {
+        let (broadcaster, addr) = serve();
+        let mut socket = connect(addr);
+        assert_eq!(receive(&broadcaster, &mut socket), "hello");
+        socket.send(Message::Ping(b"ping"[..].into())).unwrap();
+        let started = Instant::now();
+        loop {
+            assert!(started.elapsed() < Duration::from_secs(5), "no pong");
+            match socket.read() {
+                Ok(Message::Pong(payload)) => {
+                    assert_eq!(&payload[..], b"ping");
+                    break;
+                }
+                Ok(_) | Err(tungstenite::Error::Io(_)) => (),
+                Err(err) => panic!("{err}"),
+            }
+        }
+        // the server answers the close frame
+        socket.close(None).unwrap();
+        let started = Instant::now();
+        loop {
+            assert!(started.elapsed() < Duration::from_secs(5), "not closed");
+            match socket.read() {
+                Err(tungstenite::Error::ConnectionClosed) => break,
+                Ok(_) | Err(tungstenite::Error::Io(_)) => (),
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn silent_clients_dont_hold_up_the_handshake() {
+        let (broadcaster, addr) = serve();
+        let _silent = TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+        let mut socket = connect(addr);
+        assert!(started.elapsed() < HANDSHAKE_TIMEOUT);
+        assert_eq!(receive(&broadcaster, &mut socket), "hello");
+    }
+}
@@ -1,37 +1,453 @@
+use std::collections::VecDeque;
+use std::ffi::c_int;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use whisper_rs::{
-    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperError,
+    WhisperState,
 };
 
 /// Whisper expects this sample rate
 pub const SAMPLE_RATE: usize = 16000;
+/// longest window whisper processes at once
+pub const MAX_WINDOW_SECS: u64 = 30;
 /// Wait for at most 30s before dispatching to whisper
-pub const MAX_WHISPER_FRAME: usize = (SAMPLE_RATE * 30) - WHISPER_PREPEND_SILENCE;
+pub const MAX_WHISPER_FRAME: usize =
+    (SAMPLE_RATE * MAX_WINDOW_SECS as usize) - WHISPER_PREPEND_SILENCE;
 /// prepend 700ms of silence to each whisper frame so the first word gets picked
 /// up better
 const WHISPER_PREPEND_SILENCE: usize = 1600 * 7;
+/// whisper rejects less than 1s of audio, prepended silence included
+///
+/// shorter audio gets padded with silence
+pub const MIN_WHISPER_FRAME: usize = SAMPLE_RATE - WHISPER_PREPEND_SILENCE;
+/// window the silence trimmer computes the rms over (10ms)
+const TRIM_WINDOW: usize = SAMPLE_RATE / 100;
+/// windows quieter than this rms count as silence when trimming
+const TRIM_THRESHOLD: f32 = 200.0;
+/// audio kept around the detected speech when trimming (100ms)
+const TRIM_MARGIN: usize = SAMPLE_RATE / 10;
+/// threads whisper.cpp runs on by default
+pub const DEFAULT_THREADS: usize = 4;
+/// largest audio context whisper models support (30s of audio)
+pub const MAX_AUDIO_CTX: i32 = 1500;
+/// audio a single step of the audio context covers
+const SAMPLES_PER_AUDIO_CTX: usize = SAMPLE_RATE * 30 / MAX_AUDIO_CTX as usize;
+/// largest text context whisper.cpp accepts (its default)
+pub const MAX_TEXT_CTX: i32 = 16384;
+/// whisper.cpp's default temperature increase on decoding failures
+pub const DEFAULT_TEMPERATURE_INC: f32 = 0.2;
+/// whisper.cpp's default compression threshold, decodings above it are
+/// considered repetitive and retried at a higher temperature
+pub const DEFAULT_ENTROPY_THRESHOLD: f32 = 2.4;
+/// whisper.cpp's default log probability threshold, less likely decodings are
+/// retried at a higher temperature
+pub const DEFAULT_LOGPROB_THRESHOLD: f32 = -1.0;
+/// wait before the first retry of a failed model load, doubled for every
+/// following one
+const LOAD_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+/// file magics of models whisper.cpp is able to load (`ggml` is stored as a
+/// little endian u32)
+const MODEL_MAGICS: [&[u8; 4]; 2] = [b"lmgg", b"GGUF"];
 
+/// translation target of whisper
+///
+/// whisper itself only knows how to translate into english, other variants can
+/// be added once models support them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Translate {
+    /// transcribe speech in the language it was spoken in
+    #[default]
+    Off,
+    /// translate all speech to english
+    English,
+}
+
+#[derive(Debug, Clone)]
 pub struct WhisperOptions {
-    /// whether whisper should translate all speech to english
-    pub translate_en: bool,
+    /// whether whisper should translate the speech
+    pub translate: Translate,
     /// the language whisper should transcribe (can be "auto" for auto
     /// detection)
     pub language: String,
+    /// languages auto detection is restricted to, empty allows all
+    pub languages: Vec<String>,
+    /// detect the language on only this much audio at the start of a segment
+    /// and keep it for following segments, `None` detects it on every whole
+    /// segment
+    pub detect_window: Option<Duration>,
+    /// detect the language again after this many segments, `None` keeps the
+    /// first detected language until [`Whisper::redetect_language`]
+    ///
+    /// only used with `detect_window`
+    pub redetect_every: Option<u32>,
+    /// amount of candidates the greedy sampler picks the best one from
+    ///
+    /// only has an effect when sampling above a temperature of 0
+    pub best_of: i32,
+    /// decode with beam search keeping this many candidates instead of
+    /// greedy sampling, `None` samples greedily
+    pub beam_size: Option<i32>,
+    /// threads whisper.cpp runs on
+    pub threads: usize,
+    /// sampling temperature of the first decoding attempt (0 to 1), 0 always
+    /// picks the most likely token
+    pub temperature: f32,
+    /// temperature added for every retry after a failed decoding, 0 disables
+    /// retries
+    pub temperature_inc: f32,
+    /// decodings more repetitive than this are retried
+    pub entropy_threshold: f32,
+    /// decodings with a lower mean log probability are retried
+    pub logprob_threshold: f32,
+    /// text whisper sees as preceding every segment, biases it towards the
+    /// vocabulary and style of the prompt
+    pub initial_prompt: Option<String>,
+    /// tokens at the end of the previous transcription fed to whisper as the
+    /// prompt of the next one, 0 transcribes every segment on its own
+    ///
+    /// keeps names and sentences consistent across segments, whisper.cpp
+    /// ignores [`WhisperOptions::initial_prompt`] once there is context
+    pub carry_context: usize,
+    /// keep whisper from emitting non speech tokens like `[music]`
+    pub suppress_non_speech: bool,
+    /// force whisper to produce a single segment per transcription
+    pub single_segment: bool,
+    /// let whisper predict timestamp tokens
+    pub timestamps: bool,
+    /// time every word of the transcription, see [`Transcription::words`]
+    pub word_timestamps: bool,
+    /// size of the audio context (up to [`MAX_AUDIO_CTX`]), `None` uses the
+    /// model default
+    ///
+    /// smaller values speed up processing at some accuracy cost, it's raised
+    /// for audio that wouldn't fit
+    pub audio_ctx: Option<i32>,
+    /// upper limit of tokens per segment, `None` means no limit
+    pub max_tokens: Option<i32>,
+    /// tokens of earlier text used as context (up to [`MAX_TEXT_CTX`]),
+    /// `None` uses the whisper.cpp default
+    ///
+    /// only models built with an extended text context make use of more
+    /// than the usual 224 tokens
+    pub max_text_ctx: Option<i32>,
+    /// cut leading and trailing silence off the audio before transcribing
+    pub trim_silence: bool,
+    /// shorter audio isn't transcribed
+    ///
+    /// whisper may still produce nothing for very short clips, anything below
+    /// [`MIN_WHISPER_FRAME`] is best effort
+    pub min_audio: Duration,
+    /// amount of recent transcriptions kept for [`Whisper::recent`]
+    pub history: usize,
+    /// longest audio window handed to whisper, prepended silence included
+    ///
+    /// capped at [`MAX_WINDOW_SECS`]
+    pub max_window: Duration,
+    /// how often loading a model that passed the file check is retried, gpus
+    /// under contention can fail to set up a context transiently
+    pub load_retries: u32,
+    /// gpu whisper runs on, `None` runs it on the cpu
+    ///
+    /// only has an effect in builds with one of the gpu features, the cpu is
+    /// used if the gpu can't be set up
+    pub gpu_device: Option<i32>,
+}
+
+impl WhisperOptions {
+    /// Samples of speech that fit into [`WhisperOptions::max_window`], audio
+    /// has to stay below this
+    pub fn max_frame(&self) -> usize {
+        let window = (self.max_window.as_millis() as usize * SAMPLE_RATE / 1000)
+            .min(MAX_WHISPER_FRAME + WHISPER_PREPEND_SILENCE);
+        window.saturating_sub(WHISPER_PREPEND_SILENCE).max(1)
+    }
+}
+
+/// result of a successful transcription
+#[derive(Debug, Clone)]
+pub struct Transcription {
+    pub text: String,
+    /// language code the text was transcribed as (the detected one for
+    /// "auto")
+    pub language: String,
+    /// mean log probability of the text tokens, closer to 0 means more
+    /// confident
+    pub avg_logprob: f32,
+    /// tokens whisper produced, special tokens included
+    pub tokens: usize,
+    /// time whisper.cpp spent decoding the audio
+    pub decode_time: Duration,
+    /// the words of the text with their timing, empty unless
+    /// [`WhisperOptions::word_timestamps`] is set
+    pub words: Vec<Word>,
+}
+
+/// a word of a [`Transcription`]
+#[derive(Debug, Clone)]
+pub struct Word {
+    /// the word with the punctuation following it
+    pub text: String,
+    /// offset into the transcribed audio the word starts at
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// audio handed to [`Whisper::audio_buf`] was longer than
+/// [`Whisper::max_frame`] allows
+#[derive(Debug, thiserror::Error)]
+#[error("{samples} samples of audio don't fit into whisper's window of {max} samples")]
+pub struct AudioTooLong {
+    pub samples: usize,
+    pub max: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscribeError {
+    /// the segment is skipped, following ones can still be transcribed
+    #[error(transparent)]
+    TooLong(#[from] AudioTooLong),
+    #[error("whisper.cpp failed to transcribe: {0}")]
+    Whisper(#[from] WhisperError),
+}
+
+/// Ring of the last items up to a capacity, the oldest is dropped first
+pub(crate) struct History<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> History<T> {
+    pub(crate) fn new(capacity: usize) -> History<T> {
+        History {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back(item);
+        // keeps `as_slice` from having to borrow mutably
+        self.items.make_contiguous();
+    }
+
+    /// oldest first
+    pub(crate) fn as_slice(&self) -> &[T] {
+        self.items.as_slices().0
+    }
 }
 
 pub struct Whisper {
     state: WhisperState,
     params: FullParams<'static, 'static>,
-    language: String, // set language later in params because it wants a ref
+    // language is set later in params because it wants a ref
+    options: WhisperOptions,
+    /// the last `options.history` transcriptions
+    history: History<Transcription>,
+    /// language detected on the window of an earlier segment
+    detected_language: Option<String>,
+    /// segments transcribed with `detected_language`
+    detected_uses: u32,
+    /// text tokens carried over from the last transcription, at most
+    /// `options.carry_context`
+    context: Vec<c_int>,
     buf: Box<[i16; WHISPER_PREPEND_SILENCE + MAX_WHISPER_FRAME]>,
     samples_in_buf: usize,
+    /// samples cut off the front of the registered audio by trimming
+    trimmed: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum WhisperSetupError {
+    #[error("model file not found")]
     ModelFileNotFound,
+    #[error("not a whisper ggml model (see the README on where to find models)")]
     ModelInvalid,
+    /// whisper.cpp only takes model paths that are valid utf8
+    #[error("the model path is not valid utf8, rename it or move the model elsewhere")]
+    ModelPathNotUtf8,
+    /// whisper.cpp couldn't set up the model, even after retrying
+    #[error("whisper.cpp failed to load the model: {0}")]
+    ModelLoadFailed(String),
+    #[error("whisper doesn't know the language '{0}'")]
+    UnknownLanguage(String),
+}
+
+/// Cheap sanity check of the model file so obviously wrong files get a
+/// helpful error instead of an opaque whisper.cpp failure
+pub fn check_model_file(model: &Path) -> Result<(), WhisperSetupError> {
+    let mut file = File::open(model).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => WhisperSetupError::ModelFileNotFound,
+        _ => WhisperSetupError::ModelInvalid,
+    })?;
+    if !file
+        .metadata()
+        .map_err(|_| WhisperSetupError::ModelInvalid)?
+        .is_file()
+    {
+        return Err(WhisperSetupError::ModelFileNotFound);
+    }
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)
+        .map_err(|_| WhisperSetupError::ModelInvalid)?;
+    if !MODEL_MAGICS.contains(&&magic) {
+        return Err(WhisperSetupError::ModelInvalid);
+    }
+    Ok(())
+}
+
+/// gpu backend whisper.cpp was built with, if any
+fn gpu_backend() -> Option<&'static str> {
+    if cfg!(feature = "cuda") {
+        Some("cuda")
+    } else if cfg!(feature = "metal") {
+        Some("metal")
+    } else if cfg!(feature = "vulkan") {
+        Some("vulkan")
+    } else {
+        None
+    }
+}
+
+/// Loads `model` on `gpu` (the cpu if `None`), retrying up to `retries` times
+/// with backoff
+///
+/// problems with the file itself are reported right away, only failures of
+/// whisper.cpp are retried. If the gpu can't be used at all the model is
+/// loaded on the cpu instead.
+fn load_model(
+    model: &Path,
+    retries: u32,
+    gpu: Option<i32>,
+) -> Result<WhisperState, WhisperSetupError> {
+    check_model_file(model)?;
+    let path = model.to_str().ok_or(WhisperSetupError::ModelPathNotUtf8)?;
+    let backend = match (gpu, gpu_backend()) {
+        (Some(device), Some(backend)) => format!("{backend} gpu {device}"),
+        (Some(_), None) => "cpu (built without gpu support)".to_string(),
+        (None, _) => "cpu".to_string(),
+    };
+    let mut backoff = LOAD_RETRY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let mut params = WhisperContextParameters::default();
+        params
+            .use_gpu(gpu.is_some())
+            .gpu_device(gpu.unwrap_or_default());
+        let state = WhisperContext::new_with_params(path, params)
+            // now we can run the model
+            .and_then(|ctx| ctx.create_state());
+        match state {
+            Ok(state) => {
+                eprintln!("running whisper on {backend}");
+                return Ok(state);
+            }
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                eprintln!("warning: loading the model failed ({err}), retry {attempt}/{retries}");
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(err) if gpu.is_some() => {
+                eprintln!("warning: loading the model on the gpu failed ({err}), using the cpu");
+                return load_model(model, retries, None);
+            }
+            Err(err) => return Err(WhisperSetupError::ModelLoadFailed(err.to_string())),
+        }
+    }
+}
+
+/// whether `samples` of audio are below [`WhisperOptions::min_audio`], empty
+/// audio always is
+fn too_short(samples: usize, min_audio: Duration) -> bool {
+    let min_samples = min_audio.as_millis() as usize * SAMPLE_RATE / 1000;
+    samples == 0 || samples < min_samples
+}
+
+/// The part of `audio` between its first and last loud window with
+/// [`TRIM_MARGIN`] around it, `None` if it is silent throughout
+fn loud_range(audio: &[i16]) -> Option<Range<usize>> {
+    let loud = |window: &[i16]| rms(window) >= TRIM_THRESHOLD;
+    let first = audio.chunks(TRIM_WINDOW).position(loud)?;
+    let last = audio
+        .chunks(TRIM_WINDOW)
+        .rposition(loud)
+        .expect("a loud window exists");
+    let start = (first * TRIM_WINDOW).saturating_sub(TRIM_MARGIN);
+    let end = ((last + 1) * TRIM_WINDOW + TRIM_MARGIN).min(audio.len());
+    Some(start..end)
+}
+
+/// The audio whisper runs on when `samples` samples were written after the
+/// prepended silence of `buf`
+///
+/// whisper rejects less than 1s of audio, short clips are filled up with
+/// silence rather than whatever an earlier clip left in the buffer
+fn padded_input(buf: &mut [i16], samples: usize) -> &[i16] {
+    let padded = samples.max(MIN_WHISPER_FRAME);
+    buf[WHISPER_PREPEND_SILENCE + samples..WHISPER_PREPEND_SILENCE + padded].fill(0);
+    &buf[..WHISPER_PREPEND_SILENCE + padded]
+}
+
+/// The audio context whisper runs `samples` with, at least `audio_ctx` but
+/// large enough that none of the audio is ignored
+fn fitting_audio_ctx(audio_ctx: i32, samples: usize) -> i32 {
+    let needed = samples.div_ceil(SAMPLES_PER_AUDIO_CTX) as i32;
+    audio_ctx.max(needed).clamp(1, MAX_AUDIO_CTX)
+}
+
+fn sampling_strategy(opt: &WhisperOptions) -> SamplingStrategy {
+    match opt.beam_size {
+        Some(beam_size) => SamplingStrategy::BeamSearch {
+            beam_size: beam_size.max(1),
+            // whisper.cpp's default, patience isn't implemented there yet
+            patience: -1.0,
+        },
+        None => SamplingStrategy::Greedy {
+            best_of: opt.best_of,
+        },
+    }
+}
+
+fn build_params(opt: &WhisperOptions) -> FullParams<'static, 'static> {
+    let mut params = FullParams::new(sampling_strategy(opt));
+    params.set_n_threads(opt.threads.max(1) as i32);
+    params.set_temperature(opt.temperature.clamp(0.0, 1.0));
+    params.set_temperature_inc(opt.temperature_inc.max(0.0));
+    params.set_entropy_thold(opt.entropy_threshold);
+    params.set_logprob_thold(opt.logprob_threshold);
+    match opt.translate {
+        Translate::Off => params.set_translate(false),
+        Translate::English => params.set_translate(true),
+    }
+    params.set_no_timestamps(!opt.timestamps);
+    params.set_suppress_non_speech_tokens(opt.suppress_non_speech);
+    params.set_token_timestamps(opt.word_timestamps);
+    params.set_single_segment(opt.single_segment);
+    if let Some(audio_ctx) = opt.audio_ctx {
+        params.set_audio_ctx(audio_ctx.clamp(1, MAX_AUDIO_CTX));
+    }
+    if let Some(max_tokens) = opt.max_tokens {
+        params.set_max_tokens(max_tokens.max(1));
+    }
+    if let Some(prompt) = &opt.initial_prompt {
+        params.set_initial_prompt(prompt);
+    }
+    if let Some(max_text_ctx) = opt.max_text_ctx {
+        params.set_n_max_text_ctx(max_text_ctx.clamp(1, MAX_TEXT_CTX));
+    }
+    params
 }
 
 impl Whisper {
@@ -39,88 +455,571 @@ impl Whisper {
         model: impl AsRef<Path>,
         opt: WhisperOptions,
     ) -> Result<Whisper, WhisperSetupError> {
-        let params = WhisperContextParameters::default();
-        let ctx = WhisperContext::new_with_params(
-            model
-                .as_ref()
-                .to_str()
-                .ok_or(WhisperSetupError::ModelFileNotFound)?,
-            params,
-        )
-        .map_err(|_| WhisperSetupError::ModelInvalid)?;
-        // now we can run the model
-        let state = ctx
-            .create_state()
-            .map_err(|_| WhisperSetupError::ModelInvalid)?;
-
-        // create a params object
-        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
-        params.set_n_threads(4);
-        params.set_translate(opt.translate_en);
-        params.set_no_timestamps(true);
-        params.set_suppress_non_speech_tokens(true);
-        params.set_single_segment(true);
+        let fixed_language = Some(&opt.language).filter(|language| *language != "auto");
+        for language in opt.languages.iter().chain(fixed_language) {
+            if whisper_rs::get_lang_id(language).is_none() {
+                return Err(WhisperSetupError::UnknownLanguage(language.clone()));
+            }
+        }
+        let state = load_model(model.as_ref(), opt.load_retries, opt.gpu_device)?;
 
         Ok(Whisper {
             state,
-            params,
-            language: opt.language,
+            params: build_params(&opt),
+            history: History::new(opt.history),
+            detected_language: None,
+            detected_uses: 0,
+            context: Vec::new(),
+            options: opt,
             buf: Box::new([0i16; MAX_WHISPER_FRAME + WHISPER_PREPEND_SILENCE]),
             samples_in_buf: 0,
+            trimmed: 0,
         })
     }
 
+    /// Replaces the model used for following transcriptions
+    ///
+    /// the current model stays active if the new one can't be loaded
+    pub fn set_model(&mut self, model: impl AsRef<Path>) -> Result<(), WhisperSetupError> {
+        self.state = load_model(
+            model.as_ref(),
+            self.options.load_retries,
+            self.options.gpu_device,
+        )?;
+        // token ids differ between the english only and multilingual models
+        self.context.clear();
+        Ok(())
+    }
+
+    /// Samples of speech that fit into the configured window, audio has to
+    /// stay below this
+    pub fn max_frame(&self) -> usize {
+        self.options.max_frame()
+    }
+
+    /// Forgets the cached language, the next segment detects it again
+    pub fn redetect_language(&mut self) {
+        self.detected_language = None;
+    }
+
+    pub fn options(&self) -> &WhisperOptions {
+        &self.options
+    }
+
+    /// Changes the speed/accuracy tradeoff for following transcriptions
+    pub fn set_quality(&mut self, best_of: i32, beam_size: Option<i32>, audio_ctx: Option<i32>) {
+        self.options.best_of = best_of;
+        self.options.beam_size = beam_size;
+        self.options.audio_ctx = audio_ctx;
+        self.params = build_params(&self.options);
+    }
+
     /// Obtain access to `sample_count` audio samples of the internal buffer
     ///
+    /// saves a copy over [`Whisper::transcribe_samples`] when the audio can be
+    /// written straight into the buffer
+    ///
     /// # Note
     /// - make sure to write the entire len of the slice, otherwise it COULD
     ///   contain junk audio
-    /// - panics if sample count is bigger then MAX_WHISPER_FRAME
-    pub fn audio_buf(&mut self, sample_count: usize) -> &mut [i16] {
-        assert!(sample_count < MAX_WHISPER_FRAME);
+    /// - fails if sample count isn't below [`Whisper::max_frame`]
+    pub fn audio_buf(&mut self, sample_count: usize) -> Result<&mut [i16], AudioTooLong> {
+        let max = self.max_frame();
+        if sample_count >= max {
+            return Err(AudioTooLong {
+                samples: sample_count,
+                max,
+            });
+        }
         self.samples_in_buf = sample_count;
-        &mut self.buf[WHISPER_PREPEND_SILENCE..WHISPER_PREPEND_SILENCE + sample_count]
+        self.trimmed = 0;
+        Ok(&mut self.buf[WHISPER_PREPEND_SILENCE..WHISPER_PREPEND_SILENCE + sample_count])
+    }
+
+    /// Transcribes `samples` in one go
+    ///
+    /// fails if there are too many samples for [`Whisper::max_frame`]
+    pub fn transcribe_samples(
+        &mut self,
+        samples: &[i16],
+    ) -> Result<Option<Transcription>, TranscribeError> {
+        self.audio_buf(samples.len())?.copy_from_slice(samples);
+        self.transcribe()
+    }
+
+    /// Transcribes `samples` without keeping the result as history or
+    /// context, for previews of audio that gets transcribed again later
+    ///
+    /// fails if there are too many samples for [`Whisper::max_frame`]
+    pub fn preview_samples(
+        &mut self,
+        samples: &[i16],
+    ) -> Result<Option<Transcription>, TranscribeError> {
+        self.audio_buf(samples.len())?.copy_from_slice(samples);
+        self.run(false)
     }
 
     /// Transcribes the registered audio
-    pub fn transcribe(&mut self) -> Option<String> {
-        if self.samples_in_buf < SAMPLE_RATE - WHISPER_PREPEND_SILENCE {
-            // save some processing since whisper will reject <1s audio anyway
-            return None;
+    pub fn transcribe(&mut self) -> Result<Option<Transcription>, TranscribeError> {
+        self.run(true)
+    }
+
+    /// Forgets the context carried over from earlier transcriptions
+    pub fn clear_context(&mut self) {
+        self.context.clear();
+    }
+
+    fn run(&mut self, remember: bool) -> Result<Option<Transcription>, TranscribeError> {
+        if self.options.trim_silence {
+            self.trim();
         }
-        let samples = &self.buf[0..WHISPER_PREPEND_SILENCE + self.samples_in_buf];
+        if too_short(self.samples_in_buf, self.options.min_audio) {
+            return Ok(None);
+        }
+        let samples = padded_input(&mut self.buf[..], self.samples_in_buf);
         let mut float_samples = Box::new([0f32; WHISPER_PREPEND_SILENCE + MAX_WHISPER_FRAME]);
-        whisper_rs::convert_integer_to_float_audio(samples, &mut float_samples[..samples.len()])
-            .expect("should be able to de-quantize data");
+        whisper_rs::convert_integer_to_float_audio(samples, &mut float_samples[..samples.len()])?;
+
+        let audio = &float_samples[..samples.len()];
 
+        let language = self.language(audio)?;
         let mut params = self.params.clone();
-        params.set_language(Some(&self.language));
-        self.state
-            .full(params, &float_samples[..samples.len()])
-            .expect("failed to run model");
+        params.set_language(Some(&language));
+        if let Some(audio_ctx) = self.options.audio_ctx {
+            params.set_audio_ctx(fitting_audio_ctx(audio_ctx, audio.len()));
+        }
+        if !self.context.is_empty() {
+            params.set_tokens(&self.context);
+        }
+        let decode_start = Instant::now();
+        self.state.full(params, audio)?;
+        let decode_time = decode_start.elapsed();
+        let language = match language.as_str() {
+            "auto" => self
+                .state
+                .full_lang_id_from_state()
+                .ok()
+                .and_then(whisper_rs::get_lang_str)
+                .map_or(language, str::to_string),
+            _ => language,
+        };
 
         // fetch the results
-        let num_segments = self
-            .state
-            .full_n_segments()
-            .expect("failed to get number of segments");
+        let num_segments = self.state.full_n_segments()?;
         if num_segments == 0 {
-            return None;
-        }
-        if num_segments > 1 {
-            // this should not happen as we have enabled the single segment option
-            eprintln!("more then one text segment received from whisper, dropping others");
-        }
-        self.state
-            .full_get_segment_text(0)
-            .ok()
-            // filter hallucination
-            .and_then(|text| {
-                if text.eq_ignore_ascii_case(" you") {
-                    None
-                } else {
-                    Some(text)
+            return Ok(None);
+        }
+        let Ok(segments) = (0..num_segments)
+            .map(|segment| self.state.full_get_segment_text(segment))
+            .collect::<Result<Vec<String>, _>>()
+        else {
+            return Ok(None);
+        };
+        let text = segments
+            .iter()
+            .map(|text| text.trim())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        // filter hallucination
+        if text.is_empty() || text.eq_ignore_ascii_case("you") {
+            return Ok(None);
+        }
+        let transcription = Transcription {
+            text,
+            language,
+            avg_logprob: self.avg_logprob(num_segments),
+            tokens: (0..num_segments)
+                .map(|segment| self.state.full_n_tokens(segment).unwrap_or(0) as usize)
+                .sum(),
+            decode_time,
+            words: match self.options.word_timestamps {
+                true => self.words(num_segments),
+                false => Vec::new(),
+            },
+        };
+        if remember {
+            self.remember(&transcription);
+            self.carry(num_segments);
+        }
+        Ok(Some(transcription))
+    }
+
+    /// The most recent transcriptions, oldest first
+    ///
+    /// holds at most [`WhisperOptions::history`] entries
+    pub fn recent(&self) -> &[Transcription] {
+        self.history.as_slice()
+    }
+
+    fn remember(&mut self, transcription: &Transcription) {
+        if self.options.history > 0 {
+            self.history.push(transcription.clone());
+        }
+    }
+
+    /// Keeps the last text tokens of the last transcription as context for
+    /// the next one
+    fn carry(&mut self, num_segments: i32) {
+        if self.options.carry_context == 0 {
+            return;
+        }
+        let mut tokens = Vec::new();
+        for segment in 0..num_segments {
+            for token in 0..self.state.full_n_tokens(segment).unwrap_or(0) {
+                let Ok(text) = self.state.full_get_token_text(segment, token) else {
+                    continue;
+                };
+                if is_special(&text) {
+                    continue;
+                }
+                if let Ok(id) = self.state.full_get_token_id(segment, token) {
+                    tokens.push(id);
+                }
+            }
+        }
+        let skip = tokens.len().saturating_sub(self.options.carry_context);
+        self.context = tokens.split_off(skip);
+    }
+
+    /// Language to transcribe `audio` in, "auto" leaves the detection to the
+    /// transcription itself
+    fn language(&mut self, audio: &[f32]) -> Result<String, WhisperError> {
+        if self.options.language != "auto" {
+            return Ok(self.options.language.clone());
+        }
+        let Some(window) = self.options.detect_window else {
+            return match self.options.languages.is_empty() {
+                true => Ok(self.options.language.clone()),
+                false => self.pick_language(audio),
+            };
+        };
+        let due = self
+            .options
+            .redetect_every
+            .is_some_and(|every| self.detected_uses >= every);
+        if let Some(language) = self.detected_language.as_ref().filter(|_| !due) {
+            self.detected_uses += 1;
+            return Ok(language.clone());
+        }
+        let window = WHISPER_PREPEND_SILENCE + window.as_millis() as usize * SAMPLE_RATE / 1000;
+        let language = self.pick_language(&audio[..window.min(audio.len())])?;
+        self.detected_language = Some(language.clone());
+        self.detected_uses = 1;
+        Ok(language)
+    }
+
+    /// Detects the most likely of the allowed languages in `audio`, any
+    /// language if none are configured
+    fn pick_language(&mut self, audio: &[f32]) -> Result<String, WhisperError> {
+        self.state.pcm_to_mel(audio, self.options.threads.max(1))?;
+        let (_, probabilities) = self.state.lang_detect(0, self.options.threads.max(1))?;
+        let probability = |language: &String| {
+            whisper_rs::get_lang_id(language)
+                .and_then(|id| probabilities.get(id as usize).copied())
+                .unwrap_or(f32::MIN)
+        };
+        if self.options.languages.is_empty() {
+            return Ok((0..probabilities.len())
+                .max_by(|&a, &b| probabilities[a].total_cmp(&probabilities[b]))
+                .and_then(|id| whisper_rs::get_lang_str(id as i32))
+                .map_or(self.options.language.clone(), str::to_string));
+        }
+        Ok(self
+            .options
+            .languages
+            .iter()
+            .max_by(|a, b| probability(a).total_cmp(&probability(b)))
+            .cloned()
+            .expect("languages aren't empty"))
+    }
+
+    /// Drops low energy audio from both ends of the registered audio, see
+    /// [`loud_range`]
+    ///
+    /// audio without any loud window is dropped entirely
+    fn trim(&mut self) {
+        let audio =
+            &self.buf[WHISPER_PREPEND_SILENCE..WHISPER_PREPEND_SILENCE + self.samples_in_buf];
+        let Some(Range { start, end }) = loud_range(audio) else {
+            self.samples_in_buf = 0;
+            return;
+        };
+        self.buf.copy_within(
+            WHISPER_PREPEND_SILENCE + start..WHISPER_PREPEND_SILENCE + end,
+            WHISPER_PREPEND_SILENCE,
+        );
+        self.samples_in_buf = end - start;
+        self.trimmed = start;
+    }
+
+    /// Groups the text tokens of the last transcription into words, a token
+    /// starting with a space starts a new word
+    fn words(&self, num_segments: i32) -> Vec<Word> {
+        // token times are in 10ms steps and include the prepended silence
+        let offset = WHISPER_PREPEND_SILENCE as i64 * 100 / SAMPLE_RATE as i64;
+        let trimmed = self.trimmed as f64 / SAMPLE_RATE as f64;
+        let time = |t: i64| {
+            Duration::from_millis((t - offset).max(0) as u64 * 10)
+                + Duration::from_secs_f64(trimmed)
+        };
+        let mut words: Vec<Word> = Vec::new();
+        for segment in 0..num_segments {
+            for token in 0..self.state.full_n_tokens(segment).unwrap_or(0) {
+                let Ok(text) = self.state.full_get_token_text_lossy(segment, token) else {
+                    continue;
+                };
+                if is_special(&text) {
+                    continue;
+                }
+                let Ok(data) = self.state.full_get_token_data(segment, token) else {
+                    continue;
+                };
+                match words.last_mut() {
+                    Some(word) if !text.starts_with(' ') => {
+                        word.text.push_str(&text);
+                        word.end = time(data.t1);
+                    }
+                    _ if text.trim().is_empty() => (),
+                    _ => words.push(Word {
+                        text: text.trim_start().to_string(),
+                        start: time(data.t0),
+                        end: time(data.t1),
+                    }),
+                }
+            }
+        }
+        words
+    }
+
+    /// mean log probability over all text tokens of the last transcription
+    fn avg_logprob(&self, num_segments: i32) -> f32 {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for segment in 0..num_segments {
+            let tokens = self.state.full_n_tokens(segment).unwrap_or(0);
+            for token in 0..tokens {
+                let Ok(text) = self.state.full_get_token_text(segment, token) else {
+                    continue;
+                };
+                if is_special(&text) {
+                    continue;
+                }
+                if let Ok(data) = self.state.full_get_token_data(segment, token) {
+                    sum += data.plog;
+                    count += 1;
                 }
+            }
+        }
+        if count == 0 {
+            0.0
+        } else {
+            sum / count as f32
+        }
+    }
+}
+
+/// special tokens like `[_BEG_]` or `<|endoftext|>` that aren't part of the
+/// text
+fn is_special(text: &str) -> bool {
+    text.starts_with("[_") || text.starts_with("<|")
+}
+
+fn rms(samples: &[i16]) -> f32 {
+    let sum: f32 = samples.iter().map(|&s| (s as f32) * (s as f32)).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> WhisperOptions {
+        WhisperOptions {
+            translate: Translate::Off,
+            language: "en".to_string(),
+            languages: Vec::new(),
+            detect_window: None,
+            redetect_every: None,
+            best_of: 1,
+            beam_size: None,
+            threads: 1,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            entropy_threshold: 2.4,
+            logprob_threshold: -1.0,
+            initial_prompt: None,
+            carry_context: 0,
+            suppress_non_speech: false,
+            single_segment: false,
+            timestamps: false,
+            word_timestamps: false,
+            audio_ctx: None,
+            max_tokens: None,
+            max_text_ctx: None,
+            trim_silence: false,
+            min_audio: Duration::ZERO,
+            history: 0,
+            max_window: Duration::from_secs(MAX_WINDOW_SECS),
+            load_retries: 0,
+            gpu_device: None,
+        }
+    }
+
+    #[test]
+    fn min_audio_boundary() {
+        let second = Duration::from_secs(1);
+        assert!(too_short(SAMPLE_RATE - 1, second));
+        assert!(!too_short(SAMPLE_RATE, second));
+        assert!(too_short(0, Duration::ZERO));
+        assert!(!too_short(1, Duration::ZERO));
+    }
+
+    #[test]
+    fn silent_audio_has_nothing_to_keep() {
+        assert_eq!(loud_range(&[]), None);
+        assert_eq!(loud_range(&[0; SAMPLE_RATE]), None);
+        // noise below the threshold is silence too
+        let hum: Vec<i16> = (0..SAMPLE_RATE).map(|i| [-100, 100][i % 2]).collect();
+        assert_eq!(loud_range(&hum), None);
+    }
+
+    #[test]
+    fn trimming_keeps_a_margin_around_speech() {
+        let mut audio = vec![0; SAMPLE_RATE];
+        let speech = SAMPLE_RATE / 2..SAMPLE_RATE / 2 + TRIM_WINDOW;
+        audio[speech.clone()].fill(5000);
+        assert_eq!(
+            loud_range(&audio),
+            Some(speech.start - TRIM_MARGIN..speech.end + TRIM_MARGIN)
+        );
+        // the margin stops at the ends of the audio
+        assert_eq!(loud_range(&audio[speech.clone()]), Some(0..TRIM_WINDOW));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_model_paths_are_reported_as_such() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"model-\xff-test.bin");
+        let path = std::env::temp_dir()
+            .join(format!("whisper-test-{}", std::process::id()))
+            .join(name);
+        assert!(matches!(
+            load_model(&path, 0, None),
+            Err(WhisperSetupError::ModelFileNotFound)
+        ));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"GGUF").unwrap();
+        let result = load_model(&path, 0, None);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(matches!(result, Err(WhisperSetupError::ModelPathNotUtf8)));
+    }
+
+    /// model the transcription tests run on, they are skipped without one
+    fn test_model() -> Option<std::path::PathBuf> {
+        let model = std::env::var_os("WHISPER_TEST_MODEL").map(Into::into);
+        if model.is_none() {
+            eprintln!("skipped, set WHISPER_TEST_MODEL to a ggml model to run it");
+        }
+        model
+    }
+
+    #[test]
+    fn transcribe_samples_leaves_no_audio_behind() {
+        let Some(model) = test_model() else {
+            return;
+        };
+        // silence has to reach whisper as it is, trimming would hide leftovers
+        let opt = WhisperOptions {
+            trim_silence: false,
+            ..options()
+        };
+        let mut whisper = Whisper::with_options(model, opt).expect("test model loads");
+        let too_long = vec![0; whisper.max_frame()];
+        assert!(whisper.transcribe_samples(&too_long).is_err());
+        let tone: Vec<i16> = (0..5 * SAMPLE_RATE)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                ((t * 440.0 * std::f32::consts::TAU).sin() * 8000.0) as i16
             })
+            .collect();
+        let first = whisper.transcribe_samples(&tone).expect("tone fits");
+        whisper.clear_context();
+        // a shorter buffer after the tone must not pick any of it up again
+        let silence = vec![0; SAMPLE_RATE / 2];
+        let second = whisper.transcribe_samples(&silence).expect("fits");
+        if let (Some(first), Some(second)) = (first, second) {
+            assert_ne!(first.text, second.text);
+        }
+    }
+
+    #[test]
+    fn short_clips_are_padded_with_silence() {
+        let mut buf = vec![0; WHISPER_PREPEND_SILENCE + MAX_WHISPER_FRAME];
+        // a longer clip transcribed before
+        buf[WHISPER_PREPEND_SILENCE..].fill(7000);
+        buf[WHISPER_PREPEND_SILENCE..WHISPER_PREPEND_SILENCE + 100].fill(5);
+        let input = padded_input(&mut buf, 100);
+        assert_eq!(input.len(), WHISPER_PREPEND_SILENCE + MIN_WHISPER_FRAME);
+        assert!(input[..WHISPER_PREPEND_SILENCE].iter().all(|&s| s == 0));
+        assert!(input[WHISPER_PREPEND_SILENCE..][..100]
+            .iter()
+            .all(|&s| s == 5));
+        assert!(input[WHISPER_PREPEND_SILENCE + 100..]
+            .iter()
+            .all(|&s| s == 0));
+    }
+
+    #[test]
+    fn audio_context_covers_the_whole_segment() {
+        // 10s of audio need a third of the full context
+        assert_eq!(fitting_audio_ctx(256, 10 * SAMPLE_RATE), 500);
+        assert_eq!(fitting_audio_ctx(750, 10 * SAMPLE_RATE), 750);
+        assert_eq!(fitting_audio_ctx(256, 30 * SAMPLE_RATE), MAX_AUDIO_CTX);
+        assert_eq!(fitting_audio_ctx(256, SAMPLE_RATE / 2), 256);
+    }
+
+    #[test]
+    fn greedy_sampler_uses_best_of() {
+        let opt = WhisperOptions {
+            best_of: 5,
+            ..options()
+        };
+        assert!(matches!(
+            sampling_strategy(&opt),
+            SamplingStrategy::Greedy { best_of: 5 }
+        ));
+    }
+
+    #[test]
+    fn beam_search_ignores_best_of() {
+        let opt = WhisperOptions {
+            best_of: 5,
+            beam_size: Some(3),
+            ..options()
+        };
+        assert!(matches!(
+            sampling_strategy(&opt),
+            SamplingStrategy::BeamSearch { beam_size: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn history_evicts_the_oldest_past_capacity() {
+        let mut history = History::new(3);
+        for item in 0..5 {
+            history.push(item);
+        }
+        assert_eq!(history.as_slice(), &[2, 3, 4]);
+    }
+
+    #[test]
+    fn history_without_capacity_stays_empty() {
+        let mut history = History::new(0);
+        history.push(1);
+        assert!(history.as_slice().is_empty());
     }
 }